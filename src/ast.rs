@@ -1,37 +1,344 @@
-use crate::token::{Token, Kind};
+use crate::token::{Kind, Position};
 use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
 
-/// Node is an object that can exist in an AST.
-//
-// TODO: Note that in cases where I expect a specific enum branch I am required
-// generalise to `Node` because enum variants are not first class types.
-// In order to be more correct I create individual struct types and wrap 
-// them in the enum. 
-//
-#[derive(Eq, PartialEq, Debug, Clone)]
-pub enum Node {
-    // Placeholder just allows for a partialially constructed Node (for easier
-    // development). Means I don't have to have all the parsing complete at once.
-    Placeholder,
-    Int(i64),
-    String(String),
-    Boolean(bool),
-    Expression { precedence: Precedence, value: Box<Node> },
-    Identifier { value: String },
-    Let { name: String, value: Box<Node> },
-    Return { value: Box<Node> },
-    If { predicate: Box<Node>, success: Box<Node>, fail: Option<Box<Node>> },
-    Block(Vec<Node>),
-    Prefix { operator: Prefix, value: Box<Node> },
-    Infix { left: Box<Node>, operator: Infix, right: Box<Node> },
-    Function { parameters: Vec<Node>, body: Box<Node> },
-    // function is an identifier or a function literal.
-    // arguments are expressions.
-    Call { function: Box<Node>, arguments: Vec<Node> },
+/// Program is the root of the syntax tree: the statements of a source file,
+/// in order.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+    pub statements: Vec<Statement>,
+}
+
+impl Program {
+    pub fn new(statements: Vec<Statement>) -> Program {
+        Program {
+            statements,
+        }
+    }
+}
+
+// Every node records where it was found in the source. Trees built by hand
+// have unknown positions, so they are compared with parsed ones after
+// `visit::strip`.
+
+/// Statement is a statement at the top level of a program or in a block.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "type"))]
+pub enum Statement {
+    Let(LetStatement),
+    Return(ReturnStatement),
+    // An expression statement is encoded as the bare expression.
+    #[cfg_attr(feature = "json", serde(untagged))]
+    Expression(Expression),
+}
+
+/// LetStatement binds the value of an expression to a name. Its position is
+/// that of the `let` keyword.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct LetStatement {
+    pub name: Identifier,
+    pub value: Expression,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+/// ReturnStatement returns from the enclosing function, or ends the program.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReturnStatement {
+    pub value: Expression,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+/// BlockStatement is a braced list of statements, as found in functions and
+/// conditionals. Its position is that of the opening brace.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockStatement {
+    pub statements: Vec<Statement>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+/// Expression is anything that produces a value.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "type"))]
+pub enum Expression {
+    Identifier(Identifier),
+    Int(IntegerLiteral),
+    String(StringLiteral),
+    Boolean(BooleanLiteral),
+    Prefix(PrefixExpression),
+    Infix(InfixExpression),
+    If(IfExpression),
+    Function(FunctionLiteral),
+    Call(CallExpression),
+}
+
+/// Identifier is a name, either being bound by a let statement or parameter
+/// or being used. The resolver fills in its binding.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Identifier {
+    pub name: String,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+    #[cfg_attr(feature = "json", serde(skip))]
+    pub binding: Binding,
+}
+
+/// Binding is where the value of an identifier lives at runtime, as worked
+/// out by the resolver. Slots number the names bound in a function call, or
+/// in the globals.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Binding {
+    #[default]
+    Unresolved,
+    // Local is bound by the function the identifier is in.
+    Local(usize),
+    // Free is bound by a function enclosing this one, `depth` functions out,
+    // and captured when the inner function was created.
+    Free { depth: usize, slot: usize },
+    Global(usize),
+    // Builtin indexes `Builtin::ALL`.
+    Builtin(usize),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct IntegerLiteral {
+    pub value: i64,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+/// StringLiteral holds the text of a string, with its escapes replaced.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct StringLiteral {
+    pub value: String,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct BooleanLiteral {
+    pub value: bool,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrefixExpression {
+    pub operator: Prefix,
+    pub value: Box<Expression>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+/// InfixExpression applies a binary operator. Its position is that of the
+/// operator.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct InfixExpression {
+    pub left: Box<Expression>,
+    pub operator: Infix,
+    pub right: Box<Expression>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct IfExpression {
+    pub predicate: Box<Expression>,
+    pub success: BlockStatement,
+    pub fail: Option<BlockStatement>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct FunctionLiteral {
+    pub parameters: Vec<Identifier>,
+    // body is shared with the functions made by evaluating the literal.
+    pub body: Arc<BlockStatement>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+/// CallExpression calls a function. Its position is that of the opening
+/// parenthesis of the arguments.
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallExpression {
+    pub function: Box<Expression>,
+    pub arguments: Vec<Expression>,
+    #[cfg_attr(feature = "json", serde(default))]
+    pub position: Position,
+}
+
+// The constructors below leave positions unknown. They are for building trees
+// by hand, in passes that rewrite the tree and in tests.
+
+impl LetStatement {
+    pub fn new<S: Into<String>, E: Into<Expression>>(name: S, value: E) -> LetStatement {
+        LetStatement { name: Identifier::new(name), value: value.into(), position: Position::default() }
+    }
+}
+
+impl ReturnStatement {
+    pub fn new<E: Into<Expression>>(value: E) -> ReturnStatement {
+        ReturnStatement { value: value.into(), position: Position::default() }
+    }
+}
+
+impl BlockStatement {
+    pub fn new(statements: Vec<Statement>) -> BlockStatement {
+        BlockStatement { statements, position: Position::default() }
+    }
+}
+
+impl Identifier {
+    pub fn new<S: Into<String>>(name: S) -> Identifier {
+        Identifier { name: name.into(), position: Position::default(), binding: Binding::Unresolved }
+    }
+}
+
+impl IntegerLiteral {
+    pub fn new(value: i64) -> IntegerLiteral {
+        IntegerLiteral { value, position: Position::default() }
+    }
+}
+
+impl StringLiteral {
+    pub fn new<S: Into<String>>(value: S) -> StringLiteral {
+        StringLiteral { value: value.into(), position: Position::default() }
+    }
+}
+
+impl BooleanLiteral {
+    pub fn new(value: bool) -> BooleanLiteral {
+        BooleanLiteral { value, position: Position::default() }
+    }
+}
+
+impl PrefixExpression {
+    pub fn new<E: Into<Expression>>(operator: Prefix, value: E) -> PrefixExpression {
+        PrefixExpression { operator, value: Box::new(value.into()), position: Position::default() }
+    }
+}
+
+impl InfixExpression {
+    pub fn new<L: Into<Expression>, R: Into<Expression>>(left: L, operator: Infix, right: R) -> InfixExpression {
+        InfixExpression {
+            left: Box::new(left.into()),
+            operator,
+            right: Box::new(right.into()),
+            position: Position::default(),
+        }
+    }
+}
+
+impl IfExpression {
+    pub fn new<E: Into<Expression>>(predicate: E, success: BlockStatement, fail: Option<BlockStatement>) -> IfExpression {
+        IfExpression { predicate: Box::new(predicate.into()), success, fail, position: Position::default() }
+    }
+}
+
+impl FunctionLiteral {
+    pub fn new(parameters: Vec<Identifier>, body: BlockStatement) -> FunctionLiteral {
+        FunctionLiteral { parameters, body: Arc::new(body), position: Position::default() }
+    }
+}
+
+impl CallExpression {
+    pub fn new<E: Into<Expression>>(function: E, arguments: Vec<Expression>) -> CallExpression {
+        CallExpression { function: Box::new(function.into()), arguments, position: Position::default() }
+    }
+}
+
+impl From<LetStatement> for Statement {
+    fn from(stmt: LetStatement) -> Statement {
+        Statement::Let(stmt)
+    }
+}
+
+impl From<ReturnStatement> for Statement {
+    fn from(stmt: ReturnStatement) -> Statement {
+        Statement::Return(stmt)
+    }
+}
+
+impl<E: Into<Expression>> From<E> for Statement {
+    fn from(exp: E) -> Statement {
+        Statement::Expression(exp.into())
+    }
+}
+
+macro_rules! expression_from {
+    ($($variant:ident($node:ty)),*) => {
+        $(
+            impl From<$node> for Expression {
+                fn from(node: $node) -> Expression {
+                    Expression::$variant(node)
+                }
+            }
+        )*
+    };
+}
+
+expression_from!(
+    Identifier(Identifier),
+    Int(IntegerLiteral),
+    String(StringLiteral),
+    Boolean(BooleanLiteral),
+    Prefix(PrefixExpression),
+    Infix(InfixExpression),
+    If(IfExpression),
+    Function(FunctionLiteral),
+    Call(CallExpression)
+);
+
+impl Statement {
+    /// position is where the statement was found in the source.
+    pub fn position(&self) -> Position {
+        match self {
+            Statement::Let(node) => node.position,
+            Statement::Return(node) => node.position,
+            Statement::Expression(exp) => exp.position(),
+        }
+    }
+}
+
+impl Expression {
+    /// position is where the expression was found in the source.
+    pub fn position(&self) -> Position {
+        match self {
+            Expression::Identifier(node) => node.position,
+            Expression::Int(node) => node.position,
+            Expression::String(node) => node.position,
+            Expression::Boolean(node) => node.position,
+            Expression::Prefix(node) => node.position,
+            Expression::Infix(node) => node.position,
+            Expression::If(node) => node.position,
+            Expression::Function(node) => node.position,
+            Expression::Call(node) => node.position,
+        }
+    }
 }
 
 // Prefix operator. 
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub enum Prefix {
     Not, // !
     Negative, // -
@@ -39,6 +346,7 @@ pub enum Prefix {
 
 // Infix operator. 
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub enum Infix {
     Eq,
     NotEq,
@@ -74,78 +382,83 @@ impl Precedence {
     }
 }
 
-impl Node {
-    fn token(&self) -> Token {
-        Token::new(Kind::Illegal, "")
+impl Infix {
+    /// precedence is how tightly the operator binds its operands.
+    pub fn precedence(&self) -> Precedence {
+        match self {
+            Infix::Eq | Infix::NotEq => Precedence::Equals,
+            Infix::LessThan | Infix::GreaterThan => Precedence::LessGreater,
+            Infix::Add | Infix::Subtract => Precedence::Sum,
+            Infix::Multiply | Infix::Divide => Precedence::Product,
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct Program {
-    pub statements: Vec<Node>,
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for stmt in &self.statements {
+            write!(f, "{}", stmt)?;
+        }
+        Ok(())
+    }
 }
 
-impl Program {
-    pub fn new(statements: Vec<Node>) -> Program {
-        Program {
-            statements,
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Statement::Let(stmt) => write!(f, "let {} = {};", stmt.name, stmt.value),
+            Statement::Return(stmt) => write!(f, "return {};", stmt.value),
+            Statement::Expression(exp) => write!(f, "{}", exp),
         }
     }
 }
 
-impl Display for Program {
+impl Display for BlockStatement {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{{")?;
         for stmt in &self.statements {
             write!(f, "{}", stmt)?;
         }
-        Ok(())
+        write!(f, "}}")
     }
 }
 
-impl Display for Node {
+impl Display for Identifier {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", match self {
-            Node::Prefix { operator, value } => format!("({}{})", operator, value),
-            Node::Infix { left, operator, right } => format!("({} {} {})", left, operator, right),
-            Node::Int(n) => n.to_string(),
-            Node::String(s) => s.to_owned(),
-            Node::Boolean(b) => b.to_string(),
-            Node::Identifier { value } => value.to_owned(),
-            Node::If { predicate, success, fail } => {
-                match fail {
-                    None => format!("if {} {{ {} }}", predicate, success),
-                    Some(fail) => format!("if {} {{ {} }} else {{ {} }}", predicate, success, fail),
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expression::Prefix(exp) => write!(f, "({}{})", exp.operator, exp.value),
+            Expression::Infix(exp) => write!(f, "({} {} {})", exp.left, exp.operator, exp.right),
+            Expression::Int(exp) => write!(f, "{}", exp.value),
+            Expression::String(exp) => write!(f, "{}", exp.value),
+            Expression::Boolean(exp) => write!(f, "{}", exp.value),
+            Expression::Identifier(exp) => write!(f, "{}", exp),
+            Expression::If(exp) => {
+                match &exp.fail {
+                    None => write!(f, "if {} {{ {} }}", exp.predicate, exp.success),
+                    Some(fail) => write!(f, "if {} {{ {} }} else {{ {} }}", exp.predicate, exp.success, fail),
                 }
             },
-            Node::Block(list) => {
-                format!("{{{}}}",
-                    list
-                        .iter()
-                        .map(|b| b.to_string()).collect::<Vec<String>>()
-                        .join(""))
-            },
-            Node::Function { parameters, body } => {
-                let parameters = parameters
+            Expression::Function(exp) => {
+                let parameters = exp.parameters
                     .iter()
                     .map(|p| p.to_string()).collect::<Vec<String>>()
                     .join(", ");
-                format!("({}){}", parameters, body)
+                write!(f, "({}){}", parameters, exp.body)
             },
-            Node::Call { function, arguments } => {
-                format!("{}({})", function, arguments
+            Expression::Call(exp) => {
+                write!(f, "{}({})", exp.function, exp.arguments
                     .iter()
                     .map(|a| a.to_string())
                     .collect::<Vec<String>>()
                     .join(", "))
             },
-            Node::Let { name, value } => {
-                format!("let {} = {};", name, value)
-            }
-            Node::Return { value } => {
-                format!("return {};", value)
-            }
-            _ => format!("{:?}", self),
-        })
+        }
     }
 }
 
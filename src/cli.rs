@@ -0,0 +1,1128 @@
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::evaluator::Evaluator;
+use crate::optimizer;
+use crate::lint;
+use crate::codes;
+use crate::resolver;
+use crate::object::Env;
+use crate::token::Token;
+use crate::util::{self, Diagnostic};
+use crate::tree;
+use crate::dot;
+use crate::formatter::{self, Config};
+use crate::printer;
+#[cfg(feature = "json")]
+use crate::json;
+use crate::repl;
+use crate::editor::Editor;
+use crate::server::Server;
+
+use std::env;
+use std::fs;
+use std::fmt::Display;
+use std::io::{self, BufReader, IsTerminal, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use colored::*;
+
+const USAGE: &str = "\
+usage: monkey [command]
+
+commands:
+    repl                     start an interactive session (default)
+    run [--error-format=human|json] <file> [args...]
+                             evaluate a script, passing it the remaining
+                             arguments
+    tokens [--json] [file]   print the tokens of a file, or of stdin
+    ast [--debug | --json | --dot] [file]
+                             print the syntax tree of a file, or of stdin;
+                             --dot draws it as a Graphviz graph
+    print [file]             print the source of a syntax tree written by
+                             `ast --json`
+    check [--error-format=human|json] <path>...
+                             parse and resolve files without running them;
+                             directories are searched for *.monkey files and
+                             patterns are globbed
+    lint [--disable=<rule>,...] [--error-format=human|json] <path>...
+                             warn about unused names, shadowing, unreachable
+                             code, self-comparisons and constant conditions
+    fmt [--check] [--indent=<n>] [--width=<n>] [path...]
+                             format files in place, or stdin to stdout; --check
+                             lists the files that are not formatted instead
+    explain [code]           describe a diagnostic code such as E0003, with an
+                             example; with no code, list them all
+    serve [--shared] [--tcp <address> | --unix <path>]
+                             run a REPL session for each client that connects,
+                             on 127.0.0.1:7070 by default; --shared gives every
+                             session the same globals. :load and :save are
+                             not available to clients, nor is :reset with
+                             --shared
+    help                     print this message
+
+check and lint write their diagnostics to stdout, in either format, and a
+summary to stderr. Every other command writes errors to stderr, leaving
+stdout to its output; for run, that is the script's.
+";
+
+const SERVE_ADDRESS: &str = "127.0.0.1:7070";
+
+// json stands in for the JSON encoding when the json feature is off, so that
+// the commands needing it explain why they fail.
+#[cfg(not(feature = "json"))]
+mod json {
+    use crate::ast::Program;
+    use crate::token::{Token, Span};
+
+    type Error = Box<dyn std::error::Error>;
+
+    const UNSUPPORTED: &str = "JSON support requires building with the json feature";
+
+    pub fn encode_tokens(_tokens: &[Token]) -> Result<String, Error> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn encode_program(_program: &Program, _spans: &[Span]) -> Result<String, Error> {
+        Err(UNSUPPORTED.into())
+    }
+
+    pub fn decode_program(_json: &str) -> Result<Program, Error> {
+        Err(UNSUPPORTED.into())
+    }
+}
+
+/// Command is an action requested on the command line.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Command {
+    Repl,
+    Run { path: String, args: Vec<String>, format: ErrorFormat },
+    // A path of None reads from stdin.
+    Tokens { path: Option<String>, json: bool },
+    Ast { path: Option<String>, format: AstFormat },
+    // Print reads a JSON syntax tree.
+    Print { path: Option<String> },
+    Check { paths: Vec<String>, format: ErrorFormat },
+    Lint { paths: Vec<String>, config: lint::Config, format: ErrorFormat },
+    // No paths formats stdin.
+    Fmt { paths: Vec<String>, check: bool, config: Config },
+    Serve { listen: Listen, shared: bool },
+    // No code lists every code.
+    Explain { code: Option<String> },
+    Help,
+}
+
+/// Listen is where the REPL server accepts connections.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Listen {
+    Tcp(String),
+    Unix(String),
+}
+
+/// AstFormat selects how the `ast` command prints a syntax tree.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum AstFormat {
+    // An indented outline of the nodes.
+    Tree,
+    // The Rust structures, as `{:#?}` prints them.
+    Debug,
+    // The versioned JSON document that `print` reads back.
+    Json,
+    // A Graphviz graph, for `dot -Tsvg`.
+    Dot,
+}
+
+/// ErrorFormat selects how diagnostics are printed.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ErrorFormat {
+    // `file:line:column: error: message`, for people.
+    Human,
+    // One JSON object per line, for tools.
+    Json,
+}
+
+impl ErrorFormat {
+    fn parse(name: &str) -> Result<ErrorFormat, String> {
+        match name {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown error format: {}", name)),
+        }
+    }
+}
+
+impl Command {
+    /// parse interprets the command line arguments, excluding the program
+    /// name.
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        let (name, rest) = match args.split_first() {
+            Some((name, rest)) => (name.as_str(), rest),
+            None => return Ok(Command::Repl),
+        };
+        match name {
+            "repl" => Ok(Command::Repl),
+            "run" => {
+                // Flags come before the file; everything after it is the
+                // script's.
+                let mut format = ErrorFormat::Human;
+                let mut rest = rest;
+                while let Some((flag, after)) = rest.split_first().filter(|(arg, _)| arg.starts_with("--")) {
+                    match flag.strip_prefix("--error-format=") {
+                        Some(name) => format = ErrorFormat::parse(name)?,
+                        None => return Err(format!("run: unknown flag: {}", flag)),
+                    }
+                    rest = after;
+                }
+                match rest.split_first() {
+                    Some((path, args)) => Ok(Command::Run {
+                        path: path.clone(),
+                        args: args.to_vec(),
+                        format,
+                    }),
+                    None => Err("run: missing file".into()),
+                }
+            },
+            "tokens" => {
+                let mut path = None;
+                let mut json = false;
+                for arg in rest {
+                    match arg.as_str() {
+                        "--json" => json = true,
+                        flag if flag.starts_with("--") => {
+                            return Err(format!("tokens: unknown flag: {}", flag));
+                        },
+                        _ if path.is_some() => return Err("tokens: too many arguments".into()),
+                        _ => path = Some(arg.clone()),
+                    }
+                }
+                Ok(Command::Tokens { path, json })
+            },
+            "ast" => {
+                let mut path = None;
+                let mut format = AstFormat::Tree;
+                for arg in rest {
+                    match arg.as_str() {
+                        "--debug" => format = AstFormat::Debug,
+                        "--json" => format = AstFormat::Json,
+                        "--dot" => format = AstFormat::Dot,
+                        flag if flag.starts_with("--") => {
+                            return Err(format!("ast: unknown flag: {}", flag));
+                        },
+                        _ if path.is_some() => return Err("ast: too many arguments".into()),
+                        _ => path = Some(arg.clone()),
+                    }
+                }
+                Ok(Command::Ast { path, format })
+            },
+            "print" => match rest {
+                [] => Ok(Command::Print { path: None }),
+                [path] => Ok(Command::Print { path: Some(path.clone()) }),
+                _ => Err("print: too many arguments".into()),
+            },
+            "check" => {
+                let mut paths = vec![];
+                let mut format = ErrorFormat::Human;
+                for arg in rest {
+                    if let Some(name) = arg.strip_prefix("--error-format=") {
+                        format = ErrorFormat::parse(name)?;
+                    } else if arg.starts_with("--") {
+                        return Err(format!("check: unknown flag: {}", arg));
+                    } else {
+                        paths.push(arg.clone());
+                    }
+                }
+                if paths.is_empty() {
+                    return Err("check: missing path".into());
+                }
+                Ok(Command::Check { paths, format })
+            },
+            "lint" => {
+                let mut paths = vec![];
+                let mut config = lint::Config::default();
+                let mut format = ErrorFormat::Human;
+                for arg in rest {
+                    if let Some(name) = arg.strip_prefix("--error-format=") {
+                        format = ErrorFormat::parse(name)?;
+                    } else if let Some(names) = arg.strip_prefix("--disable=") {
+                        for name in names.split(',') {
+                            match lint::Rule::lookup(name) {
+                                Some(rule) => config.disabled.insert(rule),
+                                None => return Err(format!("lint: unknown rule: {}", name)),
+                            };
+                        }
+                    } else if arg.starts_with("--") {
+                        return Err(format!("lint: unknown flag: {}", arg));
+                    } else {
+                        paths.push(arg.clone());
+                    }
+                }
+                if paths.is_empty() {
+                    return Err("lint: missing path".into());
+                }
+                Ok(Command::Lint { paths, config, format })
+            },
+            "fmt" => {
+                let mut paths = vec![];
+                let mut check = false;
+                let mut config = Config::default();
+                for arg in rest {
+                    if arg == "--check" {
+                        check = true;
+                    } else if let Some(n) = arg.strip_prefix("--indent=") {
+                        config.indent = number("--indent", n)?;
+                    } else if let Some(n) = arg.strip_prefix("--width=") {
+                        config.width = number("--width", n)?;
+                    } else if arg.starts_with("--") {
+                        return Err(format!("fmt: unknown flag: {}", arg));
+                    } else {
+                        paths.push(arg.clone());
+                    }
+                }
+                Ok(Command::Fmt { paths, check, config })
+            },
+            "serve" => {
+                let mut listen = Listen::Tcp(SERVE_ADDRESS.into());
+                let mut shared = false;
+                let mut args = rest.iter();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--shared" => shared = true,
+                        "--tcp" => match args.next() {
+                            Some(address) => listen = Listen::Tcp(address.clone()),
+                            None => return Err("serve: --tcp needs an address".into()),
+                        },
+                        "--unix" => match args.next() {
+                            Some(path) => listen = Listen::Unix(path.clone()),
+                            None => return Err("serve: --unix needs a path".into()),
+                        },
+                        _ => return Err(format!("serve: unknown argument: {}", arg)),
+                    }
+                }
+                Ok(Command::Serve { listen, shared })
+            },
+            "explain" => match rest {
+                [] => Ok(Command::Explain { code: None }),
+                [code] => Ok(Command::Explain { code: Some(code.clone()) }),
+                _ => Err("explain: too many arguments".into()),
+            },
+            "help" | "-h" | "--help" => Ok(Command::Help),
+            _ => Err(format!("unknown command: {}", name)),
+        }
+    }
+}
+
+// number parses the value of a numeric flag.
+fn number(flag: &str, value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("{} needs a number, got {}", flag, value))
+}
+
+/// main runs the command described by `args` and returns the process exit
+/// status. Output is only coloured on a terminal, and never when `NO_COLOR`
+/// is set.
+pub fn main(args: &[String]) -> i32 {
+    if env::var_os("NO_COLOR").is_some() || !io::stdout().is_terminal() {
+        colored::control::set_override(false);
+    }
+    let command = match Command::parse(args) {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("{}: {}\n\n{}", "error".red(), err, USAGE);
+            return 2;
+        }
+    };
+    let (mut stdin, mut stdout, mut stderr) = (io::stdin(), io::stdout(), io::stderr());
+    execute(command, &mut Io { stdin: &mut stdin, stdout: &mut stdout, stderr: &mut stderr })
+}
+
+/// Io is where a command reads its input and writes its output and errors.
+pub struct Io<'a> {
+    pub stdin: &'a mut dyn Read,
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+}
+
+impl Io<'_> {
+    // out writes to stdout. Like `print!`, it panics if stdout has gone.
+    fn out<T: Display>(&mut self, text: T) {
+        write!(self.stdout, "{}", text).expect("failed printing to stdout");
+    }
+
+    // err writes to stderr. Like `eprint!`, it panics if stderr has gone.
+    fn err<T: Display>(&mut self, text: T) {
+        write!(self.stderr, "{}", text).expect("failed printing to stderr");
+    }
+}
+
+/// execute runs a command against `io` and returns its exit status.
+pub fn execute(command: Command, io: &mut Io) -> i32 {
+    match command {
+        Command::Repl => {
+            io.out(format!("Hello {}! This is the Monkey programming language.\n", whoami::username()));
+            io.out("Feel free to type in commands.\n");
+            if io::stdin().is_terminal() {
+                match Editor::new() {
+                    Ok(mut editor) => {
+                        let result = repl::run(&mut editor, &mut io.stdout);
+                        return session_status(result, io);
+                    },
+                    Err(err) => io.err(format!("{}: line editing unavailable: {}\n", "warning".yellow(), err)),
+                }
+            }
+            let result = repl::start(&mut BufReader::new(&mut *io.stdin), &mut io.stdout);
+            session_status(result, io)
+        },
+        Command::Run { path, args, format } => run(&path, args, format, io),
+        Command::Tokens { path, json } => tokens(path, json, io),
+        Command::Ast { path, format } => ast(path, format, io),
+        Command::Print { path } => print(path, io),
+        Command::Check { paths, format } => check(&paths, format, io),
+        Command::Lint { paths, config, format } => lint(&paths, &config, format, io),
+        Command::Fmt { paths, check, config } => fmt(&paths, check, &config, io),
+        Command::Serve { listen, shared } => serve(listen, shared, io),
+        Command::Explain { code } => explain(code.as_deref(), io),
+        Command::Help => {
+            io.out(USAGE);
+            0
+        },
+    }
+}
+
+// session_status reports an I/O failure that ended a REPL session.
+fn session_status(result: io::Result<()>, io: &mut Io) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            io.err(format!("{}: {}\n", "error".red(), err));
+            1
+        }
+    }
+}
+
+// run evaluates the script at `path`. Every parse error is reported before
+// giving up, so that a script can be fixed in one pass. Diagnostics go to
+// stderr in either format, leaving stdout to the script.
+fn run(path: &str, args: Vec<String>, format: ErrorFormat, io: &mut Io) -> i32 {
+    let (name, source) = match read_source(Some(path), io) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    let mut evaluator = Evaluator::new().args(args);
+    let mut parser = Parser::new(Lexer::new(source.chars()));
+    let mut program = match parser.parse() {
+        Ok(program) => program,
+        Err(diagnostics) => {
+            report(io.stderr, format, &name, &diagnostics);
+            return 1;
+        }
+    };
+    report(io.stderr, format, &name, parser.warnings());
+    if let Err(errors) = evaluator.resolve(&mut program) {
+        report(io.stderr, format, &name, &errors);
+        return 1;
+    }
+    let program = optimizer::optimize(program);
+    match evaluator.eval(&program, io.stdout) {
+        Ok(_) => 0,
+        Err(err) => {
+            match err.downcast_ref::<Diagnostic>() {
+                Some(diagnostic) => report(io.stderr, format, &name, [diagnostic]),
+                None => failure(io.stderr, format, &name, &err.to_string()),
+            }
+            1
+        }
+    }
+}
+
+fn tokens(path: Option<String>, json: bool, io: &mut Io) -> i32 {
+    let (_, source) = match read_source(path.as_deref(), io) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    if json {
+        let tokens: Vec<Token> = Lexer::new(source.chars()).collect();
+        return print_json(json::encode_tokens(&tokens), io);
+    }
+    for token in Lexer::new(source.chars()) {
+        io.out(format!("{:<8}{:<12}{}\n", token.position.to_string(), format!("{:?}", token.kind), token.literal));
+    }
+    0
+}
+
+fn ast(path: Option<String>, format: AstFormat, io: &mut Io) -> i32 {
+    let (name, source) = match read_source(path.as_deref(), io) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    let mut parser = Parser::new(Lexer::new(source.chars()));
+    let program = match parser.parse() {
+        Ok(program) => program,
+        Err(errors) => {
+            report(io.stderr, ErrorFormat::Human, &name, &errors);
+            return 1;
+        }
+    };
+    match format {
+        AstFormat::Tree => io.out(tree::render(&program)),
+        AstFormat::Debug => io.out(format!("{:#?}\n", program)),
+        AstFormat::Json => return print_json(json::encode_program(&program, parser.spans()), io),
+        AstFormat::Dot => io.out(dot::render(&program)),
+    }
+    0
+}
+
+// print writes out the source of a JSON syntax tree.
+fn print(path: Option<String>, io: &mut Io) -> i32 {
+    let (name, input) = match read_source(path.as_deref(), io) {
+        Ok(input) => input,
+        Err(code) => return code,
+    };
+    match json::decode_program(&input) {
+        Ok(program) => {
+            io.out(printer::print(&program));
+            0
+        },
+        Err(err) => {
+            failure(io.stderr, ErrorFormat::Human, &name, &err.to_string());
+            1
+        }
+    }
+}
+
+fn print_json(encoded: Result<String, Box<dyn std::error::Error>>, io: &mut Io) -> i32 {
+    match encoded {
+        Ok(encoded) => {
+            io.out(format!("{}\n", encoded));
+            0
+        },
+        Err(err) => {
+            io.err(format!("{}: {}\n", "error".red(), err));
+            1
+        }
+    }
+}
+
+// check parses every file matched by `paths` and reports all of their
+// errors to stdout, with a summary on stderr. The status is non-zero if any
+// file could not be read or parsed.
+fn check(paths: &[String], format: ErrorFormat, io: &mut Io) -> i32 {
+    let (files, mut status) = files(paths, format, io.stdout);
+    let mut failed = 0;
+    for file in &files {
+        let name = file.display().to_string();
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                failure(io.stdout, format, &name, &err.to_string());
+                failed += 1;
+                continue;
+            }
+        };
+        let mut parser = Parser::new(Lexer::new(source.chars()));
+        let diagnostics = match parser.parse() {
+            Ok(mut program) => {
+                let mut diagnostics = parser.warnings().clone();
+                if let Err(errors) = resolver::resolve(&mut program, &Env::new()) {
+                    diagnostics.extend(errors);
+                }
+                diagnostics
+            },
+            Err(diagnostics) => diagnostics,
+        };
+        report(io.stdout, format, &name, &diagnostics);
+        if diagnostics.has_errors() {
+            failed += 1;
+        }
+    }
+    if format == ErrorFormat::Human {
+        io.err(format!("checked {} file(s), {} failed\n", files.len(), failed));
+    }
+    if failed > 0 {
+        status = 1;
+    }
+    status
+}
+
+// lint prints the warnings for the files matched by `paths` to stdout, with
+// a summary on stderr. The status is non-zero if there are any, so that lint
+// can fail a build.
+fn lint(paths: &[String], config: &lint::Config, format: ErrorFormat, io: &mut Io) -> i32 {
+    let (files, mut status) = files(paths, format, io.stdout);
+    let mut warnings = 0;
+    for file in &files {
+        let name = file.display().to_string();
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                failure(io.stdout, format, &name, &err.to_string());
+                status = 1;
+                continue;
+            }
+        };
+        let diagnostics = lint::lint(&source, config);
+        report(io.stdout, format, &name, &diagnostics);
+        if diagnostics.has_errors() {
+            status = 1;
+        } else {
+            warnings += diagnostics.len();
+        }
+    }
+    if format == ErrorFormat::Human {
+        io.err(format!("linted {} file(s), {} warning(s)\n", files.len(), warnings));
+    }
+    if warnings > 0 {
+        status = 1;
+    }
+    status
+}
+
+// fmt formats the files matched by `paths` in place, or stdin to stdout when
+// there are none. With `check` nothing is written; the files that would
+// change are listed and the status is non-zero.
+fn fmt(paths: &[String], check: bool, config: &Config, io: &mut Io) -> i32 {
+    if paths.is_empty() {
+        let (name, source) = match read_source(None, io) {
+            Ok(input) => input,
+            Err(code) => return code,
+        };
+        return match formatter::format(&source, config) {
+            Ok(formatted) if check => (formatted != source) as i32,
+            Ok(formatted) => {
+                io.out(formatted);
+                0
+            },
+            Err(errors) => {
+                report(io.stderr, ErrorFormat::Human, &name, &errors);
+                1
+            },
+        };
+    }
+    let (files, mut status) = files(paths, ErrorFormat::Human, io.stderr);
+    for file in &files {
+        let name = file.display().to_string();
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                failure(io.stderr, ErrorFormat::Human, &name, &err.to_string());
+                status = 1;
+                continue;
+            }
+        };
+        let formatted = match formatter::format(&source, config) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                report(io.stderr, ErrorFormat::Human, &name, &errors);
+                status = 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            io.out(format!("{}\n", name));
+            status = 1;
+        } else if let Err(err) = fs::write(file, formatted) {
+            failure(io.stderr, ErrorFormat::Human, &name, &err.to_string());
+            status = 1;
+        }
+    }
+    status
+}
+
+// files expands every path argument, reporting those that can't be expanded
+// or match nothing to `w`. The status is non-zero if any were reported.
+fn files(paths: &[String], format: ErrorFormat, w: &mut dyn Write) -> (Vec<PathBuf>, i32) {
+    let mut files = vec![];
+    let mut status = 0;
+    for path in paths {
+        match expand(path) {
+            Ok(found) if found.is_empty() => {
+                failure(w, format, path, &format!("no files match {}", path));
+                status = 1;
+            },
+            Ok(found) => files.extend(found),
+            Err(err) => {
+                failure(w, format, path, &err);
+                status = 1;
+            },
+        }
+    }
+    (files, status)
+}
+
+// expand turns a path argument into the files it names: a directory yields
+// the *.monkey files beneath it, a pattern yields its matches and anything
+// else is taken as a file.
+fn expand(path: &str) -> Result<Vec<PathBuf>, String> {
+    let mut files = vec![];
+    if Path::new(path).is_dir() {
+        walk(Path::new(path), &mut files).map_err(|err| err.to_string())?;
+    } else if path.contains(['*', '?', '[']) {
+        let matches = glob::glob(path).map_err(|err| err.to_string())?;
+        for entry in matches {
+            let entry = entry.map_err(|err| err.to_string())?;
+            if entry.is_dir() {
+                walk(&entry, &mut files).map_err(|err| err.to_string())?;
+            } else {
+                files.push(entry);
+            }
+        }
+    } else {
+        files.push(PathBuf::from(path));
+    }
+    Ok(files)
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            walk(&entry, files)?;
+        } else if entry.extension().is_some_and(|ext| ext == "monkey") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+// report writes diagnostics found in `name` to `w`: for people, or as lines
+// of JSON. Every command reports through here; the caller picks the stream.
+fn report<'a, D>(w: &mut dyn Write, format: ErrorFormat, name: &str, diagnostics: D)
+    where D: IntoIterator<Item = &'a Diagnostic>,
+{
+    for diagnostic in diagnostics {
+        let rendered = match format {
+            ErrorFormat::Human => diagnostic.human(Some(name)),
+            ErrorFormat::Json => format!("{}\n", diagnostic.json(Some(name))),
+        };
+        write!(w, "{}", rendered).expect("failed printing diagnostics");
+    }
+}
+
+// failure reports an error that isn't about the source, such as a file that
+// can't be read, to `w`. In JSON it has the fields of a diagnostic, with no
+// code or span.
+fn failure(w: &mut dyn Write, format: ErrorFormat, name: &str, message: &str) {
+    let rendered = match format {
+        ErrorFormat::Human => format!("{}: {}: {}\n", name, "error".red(), message),
+        ErrorFormat::Json => format!(
+            r#"{{"file":{},"severity":"error","code":null,"message":{},"span":null,"labels":[],"help":null,"suggestions":[],"trace":[]}}"#,
+            util::json_string(name), util::json_string(message),
+        ) + "\n",
+    };
+    write!(w, "{}", rendered).expect("failed printing diagnostics");
+}
+
+fn explain(code: Option<&str>, io: &mut Io) -> i32 {
+    let code = match code {
+        Some(code) => code,
+        None => {
+            for entry in codes::ENTRIES {
+                io.out(format!("{}  {}\n", entry.code, entry.title));
+            }
+            return 0;
+        },
+    };
+    match codes::lookup(code) {
+        Some(entry) => {
+            io.out(codes::explain(entry));
+            0
+        },
+        None => {
+            io.err(format!("{}: unknown code: {}\n", "error".red(), code));
+            1
+        },
+    }
+}
+
+fn serve(listen: Listen, shared: bool, io: &mut Io) -> i32 {
+    let server = Server::new(shared);
+    let result = match listen {
+        Listen::Tcp(address) => TcpListener::bind(&address).and_then(|listener| {
+            io.err(format!("listening on {}\n", listener.local_addr()?));
+            server.serve_tcp(listener)
+        }),
+        #[cfg(unix)]
+        Listen::Unix(path) => UnixListener::bind(&path).and_then(|listener| {
+            io.err(format!("listening on {}\n", path));
+            server.serve_unix(listener)
+        }),
+        #[cfg(not(unix))]
+        Listen::Unix(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "unix sockets are not supported here")),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            io.err(format!("{}: serve: {}\n", "error".red(), err));
+            1
+        }
+    }
+}
+
+// read_source reads the file at `path`, or stdin if there is no path, and
+// returns a name for the input along with its contents. Failure is reported
+// here and turned into an exit status.
+fn read_source(path: Option<&str>, io: &mut Io) -> Result<(String, String), i32> {
+    let (name, result) = match path {
+        None | Some("-") => {
+            let mut source = String::new();
+            let result = io.stdin.read_to_string(&mut source).map(|_| source);
+            ("<stdin>".to_string(), result)
+        },
+        Some(path) => (path.to_string(), fs::read_to_string(path)),
+    };
+    match result {
+        Ok(source) => Ok((name, source)),
+        Err(err) => {
+            io.err(format!("{}: {}: {}\n", "error".red(), name, err));
+            Err(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    // fixture writes `files` into a fresh directory named for the test.
+    fn fixture(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("monkey-cli-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    // exec runs a command line uncoloured with `stdin` as its input,
+    // returning the exit status, stdout and stderr. `{dir}` stands for `dir`
+    // in the line, and `dir` is written as `{dir}` in the output.
+    fn exec(dir: &Path, line: &str, stdin: &str) -> (i32, String, String) {
+        colored::control::set_override(false);
+        let dir = dir.display().to_string();
+        let command = Command::parse(&args(&line.replace("{dir}", &dir))).unwrap();
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let status = execute(command, &mut Io { stdin: &mut stdin.as_bytes(), stdout: &mut stdout, stderr: &mut stderr });
+        let output = |bytes| String::from_utf8(bytes).unwrap().replace(&dir, "{dir}");
+        (status, output(stdout), output(stderr))
+    }
+
+    // commands runs each command line and checks its exit status and output.
+    fn commands(dir: &Path, tests: Vec<(&str, &str, i32, &str, &str)>) {
+        for (line, stdin, status, stdout, stderr) in tests {
+            assert_eq!(exec(dir, line, stdin), (status, stdout.to_string(), stderr.to_string()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let tests = vec![
+            ("", Ok(Command::Repl)),
+            ("repl", Ok(Command::Repl)),
+            ("help", Ok(Command::Help)),
+            ("run script.monkey", Ok(Command::Run { path: "script.monkey".into(), args: vec![], format: ErrorFormat::Human })),
+            (
+                "run script.monkey a --b",
+                Ok(Command::Run { path: "script.monkey".into(), args: vec!["a".into(), "--b".into()], format: ErrorFormat::Human }),
+            ),
+            (
+                "run --error-format=json script.monkey --error-format=human",
+                Ok(Command::Run { path: "script.monkey".into(), args: vec!["--error-format=human".into()], format: ErrorFormat::Json }),
+            ),
+            ("run --verbose script.monkey", Err("run: unknown flag: --verbose".to_string())),
+            ("run", Err("run: missing file".to_string())),
+            ("tokens", Ok(Command::Tokens { path: None, json: false })),
+            ("tokens a.monkey", Ok(Command::Tokens { path: Some("a.monkey".into()), json: false })),
+            ("tokens a b", Err("tokens: too many arguments".to_string())),
+            ("ast", Ok(Command::Ast { path: None, format: AstFormat::Tree })),
+            ("ast --debug a.monkey", Ok(Command::Ast { path: Some("a.monkey".into()), format: AstFormat::Debug })),
+            ("ast a.monkey --debug", Ok(Command::Ast { path: Some("a.monkey".into()), format: AstFormat::Debug })),
+            ("ast --dot a.monkey", Ok(Command::Ast { path: Some("a.monkey".into()), format: AstFormat::Dot })),
+            ("ast --svg", Err("ast: unknown flag: --svg".to_string())),
+            ("tokens --json a.monkey", Ok(Command::Tokens { path: Some("a.monkey".into()), json: true })),
+            ("ast --json", Ok(Command::Ast { path: None, format: AstFormat::Json })),
+            ("print tree.json", Ok(Command::Print { path: Some("tree.json".into()) })),
+            ("print a.json b.json", Err("print: too many arguments".to_string())),
+            ("check a.monkey scripts", Ok(Command::Check {
+                paths: vec!["a.monkey".into(), "scripts".into()],
+                format: ErrorFormat::Human,
+            })),
+            ("check --error-format=json a.monkey", Ok(Command::Check {
+                paths: vec!["a.monkey".into()],
+                format: ErrorFormat::Json,
+            })),
+            ("check --error-format=xml a.monkey", Err("unknown error format: xml".to_string())),
+            ("check", Err("check: missing path".to_string())),
+            ("lint --disable=shadowed-name,self-comparison src", Ok(Command::Lint {
+                paths: vec!["src".into()],
+                config: lint::Config { disabled: [lint::Rule::ShadowedName, lint::Rule::SelfComparison].into() },
+                format: ErrorFormat::Human,
+            })),
+            ("lint --error-format=json src", Ok(Command::Lint {
+                paths: vec!["src".into()],
+                config: lint::Config::default(),
+                format: ErrorFormat::Json,
+            })),
+            ("lint --disable=tabs src", Err("lint: unknown rule: tabs".to_string())),
+            ("lint", Err("lint: missing path".to_string())),
+            ("fmt", Ok(Command::Fmt { paths: vec![], check: false, config: Config::default() })),
+            ("fmt --check --indent=2 --width=80 src", Ok(Command::Fmt {
+                paths: vec!["src".into()],
+                check: true,
+                config: Config { indent: 2, width: 80 },
+            })),
+            ("fmt --width=wide", Err("--width needs a number, got wide".to_string())),
+            ("fmt --diff", Err("fmt: unknown flag: --diff".to_string())),
+            ("serve", Ok(Command::Serve { listen: Listen::Tcp("127.0.0.1:7070".into()), shared: false })),
+            ("serve --shared --tcp 127.0.0.1:9000", Ok(Command::Serve {
+                listen: Listen::Tcp("127.0.0.1:9000".into()),
+                shared: true,
+            })),
+            ("serve --unix /tmp/monkey.sock", Ok(Command::Serve {
+                listen: Listen::Unix("/tmp/monkey.sock".into()),
+                shared: false,
+            })),
+            ("serve --tcp", Err("serve: --tcp needs an address".to_string())),
+            ("explain", Ok(Command::Explain { code: None })),
+            ("explain E0003", Ok(Command::Explain { code: Some("E0003".into()) })),
+            ("explain E0003 E0004", Err("explain: too many arguments".to_string())),
+            ("frobnicate", Err("unknown command: frobnicate".to_string())),
+        ];
+        for (line, want) in tests {
+            assert_eq!(Command::parse(&args(line)), want, "{}", line);
+        }
+    }
+
+    #[test]
+    fn streams() {
+        let dir = fixture("streams", &[("bad.monkey", "let = 1;\n")]);
+        let tests = vec![
+            ("run --error-format=json {dir}/bad.monkey", false),
+            ("check --error-format=json {dir}/bad.monkey", true),
+            ("lint --error-format=json {dir}/bad.monkey", true),
+            ("check --error-format=json {dir}/missing.monkey", true),
+        ];
+        for (line, stdout) in tests {
+            let (status, out, err) = exec(&dir, line, "");
+            assert_eq!(status, 1, "{}", line);
+            let (diagnostics, other) = if stdout { (out, err) } else { (err, out) };
+            assert!(diagnostics.starts_with(r#"{"file":"#), "{}: {:?}", line, diagnostics);
+            assert_eq!(other, "", "{}", line);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn inspect() {
+        let dir = fixture("inspect", &[("a.monkey", "let x = 1 + 2;\n")]);
+        commands(&dir, vec![
+            (
+                "tokens {dir}/a.monkey", "", 0,
+                "1:1     Let         let\n\
+                 1:5     Ident       x\n\
+                 1:7     Assign      =\n\
+                 1:9     Int         1\n\
+                 1:11    Plus        +\n\
+                 1:13    Int         2\n\
+                 1:14    Semicolon   ;\n",
+                "",
+            ),
+            ("tokens", "\"hi\"", 0, "1:1     String      \"hi\"\n", ""),
+            ("tokens {dir}/missing.monkey", "", 1, "", "error: {dir}/missing.monkey: No such file or directory (os error 2)\n"),
+            (
+                "ast {dir}/a.monkey", "", 0,
+                "Program\n  Let x\n    value: Infix +\n      left: Int 1\n      right: Int 2\n",
+                "",
+            ),
+            ("ast", "x", 0, "Program\n  Identifier x\n", ""),
+            (
+                "ast", "let = 1;", 1, "",
+                "<stdin>:1:5: error[E0003]: expected Ident, got Assign\n<stdin>:1:5: error[E0001]: unexpected: =\n",
+            ),
+        ]);
+        let (status, stdout, _) = exec(&dir, "ast --debug {dir}/a.monkey", "");
+        assert_eq!(status, 0);
+        assert!(stdout.starts_with("Program {\n    statements: [\n        Let("), "{}", stdout);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dot() {
+        commands(&env::temp_dir(), vec![
+            (
+                "ast --dot", "-x", 0,
+                "digraph ast {\n    \
+                 ordering=out;\n    \
+                 node [shape=box, fontname=\"monospace\"];\n    \
+                 n0 [label=\"Program\"];\n    \
+                 n1 [label=\"Prefix -\"];\n    \
+                 n0 -> n1;\n    \
+                 n2 [label=\"Identifier x\"];\n    \
+                 n1 -> n2 [label=\"value\"];\n\
+                 }\n",
+                "",
+            ),
+            ("ast --dot", "-", 1, "", "<stdin>:1:1: error[E0002]: unexpected: end of input\n"),
+        ]);
+    }
+
+    // Directories are searched for *.monkey files, but a pattern lints
+    // whatever it matches.
+    #[test]
+    fn lint() {
+        let dir = fixture("lint", &[
+            ("clean.monkey", "let x = 1;\nx;\n"),
+            ("scripts/compare.monkey", "let f = fn(a, b) { a };\nlet x = 1;\nx == x;\n"),
+            ("scripts/nested/shadow.monkey", "let len = 1;\nlen;\n"),
+            ("scripts/notes.txt", "ignored"),
+        ]);
+        let compare = "\
+            {dir}/scripts/compare.monkey:1:15: warning[W0002]: unused parameter: b\n  \
+            help: `// lint: disable-next-line unused-variable` on the line before allows this\n\
+            {dir}/scripts/compare.monkey:3:3: warning[W0005]: comparing x to itself is always true\n  \
+            help: `// lint: disable-next-line self-comparison` on the line before allows this\n";
+        let shadow = "\
+            {dir}/scripts/nested/shadow.monkey:1:5: warning[W0003]: len shadows the builtin of the same name\n  \
+            help: `// lint: disable-next-line shadowed-name` on the line before allows this\n";
+        commands(&dir, vec![
+            ("lint {dir}/clean.monkey", "", 0, "", "linted 1 file(s), 0 warning(s)\n"),
+            ("lint {dir}/scripts", "", 1, &format!("{}{}", compare, shadow), "linted 2 file(s), 3 warning(s)\n"),
+            ("lint --disable=unused-variable,self-comparison {dir}/scripts", "", 1, shadow, "linted 2 file(s), 1 warning(s)\n"),
+            ("lint --disable=shadowed-name {dir}/scripts/nested", "", 0, "", "linted 1 file(s), 0 warning(s)\n"),
+            ("lint {dir}/scripts/* {dir}/clean.monkey", "", 1, &format!("{}{}", compare, shadow), "linted 4 file(s), 3 warning(s)\n"),
+            ("lint {dir}/scripts/ne*", "", 1, shadow, "linted 1 file(s), 1 warning(s)\n"),
+            (
+                "lint {dir}/none/*.monkey {dir}/clean.monkey", "", 1,
+                "{dir}/none/*.monkey: error: no files match {dir}/none/*.monkey\n",
+                "linted 1 file(s), 0 warning(s)\n",
+            ),
+            (
+                "lint {dir}/missing.monkey", "", 1,
+                "{dir}/missing.monkey: error: No such file or directory (os error 2)\n",
+                "linted 1 file(s), 0 warning(s)\n",
+            ),
+        ]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // A script's output goes to stdout even when it goes on to fail.
+    #[test]
+    fn run() {
+        let dir = fixture("run", &[
+            ("args.monkey", "puts(argc(), argv(0));\n"),
+            ("fail.monkey", "puts(1 + 2);\nlet f = fn(n) { n / 0 };\nf(1);\n"),
+            ("bad.monkey", "let = 1;\n"),
+        ]);
+        commands(&dir, vec![
+            ("run {dir}/args.monkey a --b", "", 0, "2\na\n", ""),
+            (
+                "run {dir}/fail.monkey", "", 1, "3\n",
+                "{dir}/fail.monkey:2:19: error[E0104]: division by zero\n  \
+                 traceback, innermost call first:\n    \
+                 {dir}/fail.monkey:2:19: in `f`\n    \
+                 {dir}/fail.monkey:3:1: at the top level\n",
+            ),
+            (
+                "run {dir}/bad.monkey", "", 1, "",
+                "{dir}/bad.monkey:1:5: error[E0003]: expected Ident, got Assign\n\
+                 {dir}/bad.monkey:1:5: error[E0001]: unexpected: =\n",
+            ),
+            ("run {dir}/missing.monkey", "", 1, "", "error: {dir}/missing.monkey: No such file or directory (os error 2)\n"),
+        ]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check() {
+        let dir = fixture("check", &[
+            ("good.monkey", "let x = 1;\nx;\n"),
+            ("scripts/bad.monkey", "let = 1;\n"),
+            ("scripts/undefined.monkey", "y;\n"),
+        ]);
+        commands(&dir, vec![
+            ("check {dir}/good.monkey", "", 0, "", "checked 1 file(s), 0 failed\n"),
+            (
+                "check {dir}/good.monkey {dir}/scripts", "", 1,
+                "{dir}/scripts/bad.monkey:1:5: error[E0003]: expected Ident, got Assign\n\
+                 {dir}/scripts/bad.monkey:1:5: error[E0001]: unexpected: =\n\
+                 {dir}/scripts/undefined.monkey:1:1: error[E0005]: identifier not found: y\n",
+                "checked 3 file(s), 2 failed\n",
+            ),
+            (
+                "check {dir}/*.monkey {dir}/missing.monkey", "", 1,
+                "{dir}/missing.monkey: error: No such file or directory (os error 2)\n",
+                "checked 2 file(s), 1 failed\n",
+            ),
+        ]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // --check leaves the files alone; without it they are rewritten, and
+    // formatting them again changes nothing.
+    #[test]
+    fn fmt() {
+        let dir = fixture("fmt", &[
+            ("tidy.monkey", "let x = 1;\n"),
+            ("messy.monkey", "let f=fn(a){a*2}\n"),
+            ("bad.monkey", "let = 1;\n"),
+        ]);
+        commands(&dir, vec![
+            ("fmt", "let x=1", 0, "let x = 1;\n", ""),
+            ("fmt --check", "let x=1", 1, "", ""),
+            ("fmt", "let x=", 1, "", "<stdin>:1:6: error[E0002]: unexpected: end of input\n"),
+            ("fmt --check {dir}/tidy.monkey {dir}/messy.monkey", "", 1, "{dir}/messy.monkey\n", ""),
+            (
+                "fmt {dir}/bad.monkey {dir}/messy.monkey", "", 1, "",
+                "{dir}/bad.monkey:1:5: error[E0003]: expected Ident, got Assign\n\
+                 {dir}/bad.monkey:1:5: error[E0001]: unexpected: =\n",
+            ),
+            ("fmt --check {dir}/tidy.monkey {dir}/messy.monkey", "", 0, "", ""),
+        ]);
+        assert_eq!(fs::read_to_string(dir.join("messy.monkey")).unwrap(), "let f = fn(a) {\n    a * 2;\n};\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn explain() {
+        let (status, stdout, stderr) = exec(&env::temp_dir(), "explain", "");
+        assert_eq!((status, stdout.lines().next(), stderr.as_str()), (0, Some("E0001  unexpected token"), ""));
+        assert_eq!(stdout.lines().count(), codes::ENTRIES.len());
+        let (status, stdout, stderr) = exec(&env::temp_dir(), "explain E0003", "");
+        assert_eq!((status, stdout.lines().next(), stderr.as_str()), (0, Some("E0003: expected a different token"), ""));
+        commands(&env::temp_dir(), vec![("explain E9999", "", 1, "", "error: unknown code: E9999\n")]);
+    }
+
+    // print reads back what ast --json writes.
+    #[test]
+    #[cfg(feature = "json")]
+    fn print() {
+        let dir = env::temp_dir();
+        let source = "let add = fn(a, b) {\n    a + b;\n};\nadd(1, 2);\n";
+        let (status, tree, _) = exec(&dir, "ast --json", source);
+        assert_eq!(status, 0);
+        commands(&dir, vec![
+            ("print", &tree, 0, source, ""),
+            ("print", r#"{"version":2,"program":{"statements":[{"type":"Loop"}]}}"#, 1, "", "<stdin>: error: program.statements[0]: unknown node type \"Loop\"\n"),
+        ]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "json"))]
+    fn print() {
+        let error = "JSON support requires building with the json feature";
+        commands(&env::temp_dir(), vec![
+            ("ast --json", "x", 1, "", &format!("error: {}\n", error)),
+            ("print", "{}", 1, "", &format!("<stdin>: error: {}\n", error)),
+        ]);
+    }
+}
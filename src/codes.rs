@@ -0,0 +1,393 @@
+use crate::parser::MAX_NESTING;
+
+/// Entry documents a diagnostic code: what it means, with a program that
+/// reports it and the same program put right.
+pub struct Entry {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub failing: &'static str,
+    pub passing: &'static str,
+}
+
+/// ENTRIES lists every code the interpreter reports. Codes are never reused:
+/// `E00xx` are found before a program runs, `E01xx` while it runs, and `Wxxxx`
+/// are warnings.
+pub const ENTRIES: &[Entry] = &[
+    Entry {
+        code: "E0001",
+        title: "unexpected token",
+        description: "\
+The parser met a token that can't start an expression, or can't follow the
+expression before it. This is often a stray operator or closing bracket, or a
+keyword used where a value was expected.",
+        failing: "let x = * 2;",
+        passing: "let x = 3 * 2;",
+    },
+    Entry {
+        code: "E0002",
+        title: "unexpected end of input",
+        description: "\
+The program ended part way through a statement: an operator has no right hand
+side, or a call is missing its closing parenthesis. In the REPL this means
+more input is needed, so the line is continued rather than reported.",
+        failing: "let sum = 1 +",
+        passing: "let sum = 1 + 2;",
+    },
+    Entry {
+        code: "E0003",
+        title: "expected a different token",
+        description: "\
+The parser knew what had to come next and found something else, such as a
+`let` without a name or a parameter list without its closing parenthesis.
+The message names the token that was expected and the one that was found.",
+        failing: "let = 1;",
+        passing: "let one = 1;",
+    },
+    Entry {
+        code: "E0004",
+        title: "invalid literal",
+        description: "\
+A literal couldn't be read: an integer too large for 64 bits, or a string with
+an escape other than `\\n`, `\\r`, `\\t`, `\\\\` and `\\\"`.",
+        failing: "let big = 99999999999999999999;",
+        passing: "let big = 9223372036854775807;",
+    },
+    Entry {
+        code: "E0005",
+        title: "identifier not found",
+        description: "\
+The name isn't bound anywhere it could be seen from: not as a parameter or
+`let` in an enclosing function, not as a global and not as a builtin. A `let`
+binds its name throughout its function, or throughout the program at the top
+level, so the binding may come later in the source.",
+        failing: "puts(greeting);",
+        passing: "let greeting = \"hi\";\nputs(greeting);",
+    },
+    Entry {
+        code: "E0006",
+        title: "duplicate parameter",
+        description: "\
+Two parameters of the same function have the same name, so the second would
+hide the first. The note points at the first.",
+        failing: "let add = fn(a, a) { a + a };",
+        passing: "let add = fn(a, b) { a + b };",
+    },
+    Entry {
+        code: "E0007",
+        title: "invalid lint directive",
+        description: "\
+A comment starting `lint:` is read by `monkey lint`, which understands
+`// lint: disable <rule>, ...` and `// lint: disable-next-line <rule>, ...`.
+The directive or one of its rule names wasn't recognised.",
+        failing: "// lint: disable unused\nlet f = fn(x) { 1 };",
+        passing: "// lint: disable unused-variable\nlet f = fn(x) { 1 };",
+    },
+    Entry {
+        code: "E0008",
+        title: "nested too deeply",
+        description: "\
+An expression is nested inside more than 256 others, counting parentheses,
+operands of prefix operators, and the bodies of conditionals and functions.
+The parser stops there rather than run out of stack. Split the expression up
+with `let` bindings.",
+        failing: DEEP,
+        passing: "let x = -1;",
+    },
+    Entry {
+        code: "E0101",
+        title: "type mismatch",
+        description: "\
+An infix operator was given operands of two different types. Only strings can
+be joined with `+`, and integers aren't converted to strings or booleans.",
+        failing: "\"total: \" + 5",
+        passing: "\"total: \" + \"5\"",
+    },
+    Entry {
+        code: "E0102",
+        title: "unknown operator",
+        description: "\
+The operator isn't defined for the type of its operands: booleans can only be
+compared with `==` and `!=`, strings can be joined and compared for equality,
+and only integers can be negated.",
+        failing: "true + false",
+        passing: "true == false",
+    },
+    Entry {
+        code: "E0103",
+        title: "identifier not bound yet",
+        description: "\
+The name is bound by a `let`, but the `let` hadn't run when the name was
+used, either because it comes later or because it is in a branch that wasn't
+taken.",
+        failing: "if (false) { let x = 1; };\nx",
+        passing: "let x = 1;\nx",
+    },
+    Entry {
+        code: "E0104",
+        title: "division by zero",
+        description: "\
+An integer was divided by zero, which has no result.",
+        failing: "let n = 0;\n10 / n",
+        passing: "let n = 2;\n10 / n",
+    },
+    Entry {
+        code: "E0105",
+        title: "integer overflow",
+        description: "\
+The result of arithmetic on integers doesn't fit in 64 bits. Integers run
+from -9223372036854775808 to 9223372036854775807.",
+        failing: "9223372036854775807 + 1",
+        passing: "9223372036854775806 + 1",
+    },
+    Entry {
+        code: "E0106",
+        title: "not a function",
+        description: "\
+A value that isn't a function or builtin was called. The message gives its
+type.",
+        failing: "let x = 1;\nx(2)",
+        passing: "let x = fn(n) { n };\nx(2)",
+    },
+    Entry {
+        code: "E0107",
+        title: "wrong number of arguments",
+        description: "\
+A function was called with more or fewer arguments than it has parameters.
+Monkey has no default or variadic parameters, except for the builtin `puts`.",
+        failing: "let add = fn(a, b) { a + b };\nadd(1)",
+        passing: "let add = fn(a, b) { a + b };\nadd(1, 2)",
+    },
+    Entry {
+        code: "E0108",
+        title: "unsupported argument",
+        description: "\
+A builtin was given an argument of a type it doesn't handle: `len` takes a
+string and `argv` an integer.",
+        failing: "len(12)",
+        passing: "len(\"12\")",
+    },
+    Entry {
+        code: "E0109",
+        title: "argument index out of range",
+        description: "\
+`argv(i)` was asked for an argument that the script wasn't given. Check
+`argc()` first.",
+        failing: "argv(argc())",
+        passing: "if (argc() > 0) { argv(0) }",
+    },
+    Entry {
+        code: "E0110",
+        title: "unresolved identifier",
+        description: "\
+A program was evaluated without being resolved first, so the evaluator doesn't
+know where the identifier's value lives. This is a bug in the tool running the
+program rather than in the program.",
+        failing: "",
+        passing: "",
+    },
+    Entry {
+        code: "E0111",
+        title: "call depth exceeded",
+        description: "\
+More than 10000 function calls were in progress at once, which usually means
+a recursive function never reaches the case that stops it. The traceback shows
+the call that repeats.",
+        failing: "let count = fn(n) { count(n + 1) };\ncount(0);",
+        passing: "let count = fn(n) { if (n < 10) { count(n + 1) } else { n } };\ncount(0);",
+    },
+    Entry {
+        code: "W0001",
+        title: "chained comparison",
+        description: "\
+Comparisons don't chain: `a < b < c` compares the boolean result of `a < b`
+with `c`, which fails when run. Compare each pair separately.",
+        failing: "let x = 5;\nif (0 < x < 10) { x }",
+        passing: "let x = 5;\nif (0 < x) { if (x < 10) { x } }",
+    },
+    Entry {
+        code: "W0002",
+        title: "unused variable",
+        description: "\
+A parameter or `let` inside a function is never used. It may be left over
+from an edit, or another name may have been used by mistake. Reported by
+`monkey lint` as `unused-variable`.",
+        failing: "let f = fn(a, b) { a };",
+        passing: "let f = fn(a) { a };",
+    },
+    Entry {
+        code: "W0003",
+        title: "shadowed name",
+        description: "\
+A parameter or `let` inside a function has the same name as a binding outside
+it, or as a builtin, which can no longer be reached from inside. Reported by
+`monkey lint` as `shadowed-name`.",
+        failing: "let total = 0;\nlet add = fn(total, n) { total + n };",
+        passing: "let total = 0;\nlet add = fn(sum, n) { sum + n };",
+    },
+    Entry {
+        code: "W0004",
+        title: "unreachable code",
+        description: "\
+A statement follows a `return` in the same block, so it never runs. Reported
+by `monkey lint` as `unreachable-code`.",
+        failing: "let f = fn() { return 1; puts(\"done\") };",
+        passing: "let f = fn() { puts(\"done\"); return 1; };",
+    },
+    Entry {
+        code: "W0005",
+        title: "self-comparison",
+        description: "\
+An expression is compared with itself, which always gives the same answer.
+Usually one side was meant to be something else. Reported by `monkey lint` as
+`self-comparison`.",
+        failing: "let same = fn(a, b) { a == a };",
+        passing: "let same = fn(a, b) { a == b };",
+    },
+    Entry {
+        code: "W0006",
+        title: "constant condition",
+        description: "\
+The condition of an `if` doesn't depend on anything, so the same branch is
+always taken. Reported by `monkey lint` as `constant-condition`.",
+        failing: "let f = fn(x) { if (1 < 2) { x } };",
+        passing: "let f = fn(x) { if (x < 2) { x } };",
+    },
+];
+
+// DEEP, the example of E0008, negates 1 once more than the parser allows.
+const DEEP: &str = match std::str::from_utf8(&NEGATIONS) {
+    Ok(source) => source,
+    Err(_) => panic!("the example is ASCII"),
+};
+
+const NEGATIONS: [u8; LET.len() + MAX_NESTING + 1 + ONE.len()] = {
+    let mut source = [b'-'; LET.len() + MAX_NESTING + 1 + ONE.len()];
+    let mut i = 0;
+    while i < LET.len() {
+        source[i] = LET[i];
+        i += 1;
+    }
+    let mut i = 0;
+    while i < ONE.len() {
+        source[source.len() - ONE.len() + i] = ONE[i];
+        i += 1;
+    }
+    source
+};
+
+const LET: &[u8] = b"let x = ";
+const ONE: &[u8] = b"1;";
+
+/// lookup finds the entry for a code, ignoring case.
+pub fn lookup(code: &str) -> Option<&'static Entry> {
+    ENTRIES.iter().find(|entry| entry.code.eq_ignore_ascii_case(code))
+}
+
+/// explain writes out the entry in full, with its examples indented.
+pub fn explain(entry: &Entry) -> String {
+    let mut out = format!("{}: {}\n\n{}\n", entry.code, entry.title, entry.description);
+    if !entry.failing.is_empty() {
+        out.push_str(&format!("\nFor example:\n\n{}\nis fixed by:\n\n{}", indent(entry.failing), indent(entry.passing)));
+    }
+    out
+}
+
+fn indent(source: &str) -> String {
+    source.lines().map(|line| format!("    {}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::lexer::Lexer;
+    use crate::lint::{self, Config};
+    use crate::parser::Parser;
+
+    use std::collections::HashSet;
+
+    // codes lists the codes reported for a program, from parsing, linting,
+    // resolving and running it, stopping at the first stage to fail.
+    fn codes(source: &str) -> Vec<&'static str> {
+        let mut program = match Parser::new(Lexer::new(source.chars())).parse() {
+            Ok(program) => program,
+            Err(errors) => return errors.iter().map(|err| err.code).collect(),
+        };
+        let mut codes: Vec<&'static str> = lint::lint(source, &Config::default()).iter().map(|d| d.code).collect();
+        let mut evaluator = Evaluator::new();
+        if let Err(errors) = evaluator.resolve(&mut program) {
+            codes.extend(errors.iter().map(|err| err.code));
+            return codes;
+        }
+        if let Err(err) = evaluator.eval(&program, &mut vec![]) {
+            match err.downcast_ref::<crate::util::Diagnostic>() {
+                Some(diagnostic) => codes.push(diagnostic.code),
+                None => panic!("{}: error without a code: {}", source, err),
+            }
+        }
+        codes
+    }
+
+    // Every code in the source of a module that reports diagnostics has an
+    // entry.
+    #[test]
+    fn registered() {
+        let sources = [
+            ("parser.rs", include_str!("parser.rs")),
+            ("resolver.rs", include_str!("resolver.rs")),
+            ("lint.rs", include_str!("lint.rs")),
+            ("evaluator.rs", include_str!("evaluator.rs")),
+        ];
+        for (name, source) in sources {
+            for code in quoted_codes(source) {
+                assert!(lookup(&code).is_some(), "{}: {} has no entry", name, code);
+            }
+        }
+        let unique: HashSet<&str> = ENTRIES.iter().map(|entry| entry.code).collect();
+        assert_eq!(unique.len(), ENTRIES.len(), "codes are repeated");
+    }
+
+    // quoted_codes finds the string literals in `source` that look like codes.
+    fn quoted_codes(source: &str) -> Vec<String> {
+        source
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .filter(|s| s.len() == 5 && (s.starts_with('E') || s.starts_with('W')) && s[1..].chars().all(|c| c.is_ascii_digit()))
+            .map(String::from)
+            .collect()
+    }
+
+    // Each failing example reports its code, and the passing one doesn't.
+    #[test]
+    fn examples() {
+        // E0110 is an internal error that no program should be able to cause.
+        for entry in ENTRIES.iter().filter(|entry| entry.code != "E0110") {
+            assert!(!entry.failing.is_empty() && !entry.passing.is_empty(), "{}: no example", entry.code);
+            assert!(codes(entry.failing).contains(&entry.code), "{}: {:?}", entry.code, codes(entry.failing));
+            assert_eq!(codes(entry.passing), Vec::<&str>::new(), "{}", entry.code);
+        }
+    }
+
+    #[test]
+    fn explained() {
+        let want = "\
+E0104: division by zero
+
+An integer was divided by zero, which has no result.
+
+For example:
+
+    let n = 0;
+    10 / n
+
+is fixed by:
+
+    let n = 2;
+    10 / n
+";
+        assert_eq!(lookup("e0104").map(explain).as_deref(), Some(want));
+        assert!(lookup("E9999").is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::ast::Program;
+use crate::tree::{self, Tree};
+
+use std::fmt::Write;
+
+/// render draws the program as a Graphviz graph, ready for `dot -Tsvg`. Each
+/// node is labelled as in `tree::render`, and each edge with the role the
+/// child plays in its parent, so operands are drawn left to right beneath
+/// the operator that binds them.
+pub fn render(program: &Program) -> String {
+    let mut graph = Graph {
+        out: String::from("digraph ast {\n    ordering=out;\n    node [shape=box, fontname=\"monospace\"];\n"),
+        nodes: 0,
+    };
+    let root = graph.node("Program");
+    for stmt in &program.statements {
+        graph.tree(root, None, Tree::Statement(stmt));
+    }
+    graph.out.push_str("}\n");
+    graph.out
+}
+
+struct Graph {
+    out: String,
+    // nodes counts the nodes drawn so far, which also numbers the next one.
+    nodes: usize,
+}
+
+impl Graph {
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.nodes;
+        self.nodes += 1;
+        let _ = writeln!(self.out, "    n{} [label={}];", id, quote(label));
+        id
+    }
+
+    // tree draws `node` and everything beneath it, joined to `parent` by an
+    // edge labelled with `edge`.
+    fn tree(&mut self, parent: usize, edge: Option<&str>, node: Tree) {
+        let id = self.node(&tree::label(node));
+        match edge {
+            Some(edge) => {
+                let _ = writeln!(self.out, "    n{} -> n{} [label={}];", parent, id, quote(edge));
+            },
+            None => {
+                let _ = writeln!(self.out, "    n{} -> n{};", parent, id);
+            },
+        }
+        for (edge, child) in tree::children(node) {
+            self.tree(id, edge, child);
+        }
+    }
+}
+
+// quote writes `s` as a DOT string. Backslashes start escapes in labels, so
+// they are escaped along with quotes and newlines.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    type Error = Box<dyn std::error::Error>;
+
+    #[test]
+    fn graph() -> Result<(), Error> {
+        let want = r#"digraph ast {
+    ordering=out;
+    node [shape=box, fontname="monospace"];
+    n0 [label="Program"];
+    n1 [label="Let x"];
+    n0 -> n1;
+    n2 [label="Infix +"];
+    n1 -> n2 [label="value"];
+    n3 [label="Int 1"];
+    n2 -> n3 [label="left"];
+    n4 [label="Infix *"];
+    n2 -> n4 [label="right"];
+    n5 [label="Int 2"];
+    n4 -> n5 [label="left"];
+    n6 [label="Int 3"];
+    n4 -> n6 [label="right"];
+    n7 [label="Call"];
+    n0 -> n7;
+    n8 [label="Identifier f"];
+    n7 -> n8 [label="function"];
+    n9 [label="Identifier x"];
+    n7 -> n9 [label="argument"];
+}
+"#;
+        assert_eq!(render(&parse("let x = 1 + 2 * 3; f(x)")?), want);
+        Ok(())
+    }
+
+    #[test]
+    fn escaped() -> Result<(), Error> {
+        let out = render(&parse(r#""say \"hi\"\n""#)?);
+        assert!(out.contains(r#"n1 [label="String \"say \\\"hi\\\"\\n\""];"#), "{}", out);
+        Ok(())
+    }
+}
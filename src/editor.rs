@@ -0,0 +1,159 @@
+use crate::lexer::KEYWORDS;
+use crate::object::Builtin;
+use crate::repl::{self, Input, Session};
+use crate::highlight::highlight;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+use std::borrow::Cow;
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const HISTORY_FILE: &str = ".monkey_history";
+
+/// Editor reads REPL input from a terminal with line editing, history that
+/// persists between sessions, reverse search (Ctrl-R), tab completion and
+/// syntax highlighting.
+pub struct Editor {
+    editor: rustyline::Editor<EditorHelper, DefaultHistory>,
+    history: Option<PathBuf>,
+}
+
+impl Editor {
+    pub fn new() -> rustyline::Result<Editor> {
+        let mut editor = rustyline::Editor::new()?;
+        editor.set_helper(Some(EditorHelper { names: vec![] }));
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means this is the first session.
+            let _ = editor.load_history(path);
+        }
+        Ok(Editor { editor, history })
+    }
+}
+
+impl Input for Editor {
+    fn read(&mut self, prompt: &str, _: &mut dyn Write) -> io::Result<Option<String>> {
+        match self.editor.readline(&format!("{} ", prompt)) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Eof) => Ok(None),
+            Err(ReadlineError::Interrupted) => Err(io::ErrorKind::Interrupted.into()),
+            Err(ReadlineError::Io(err)) => Err(err),
+            Err(err) => Err(io::Error::other(err.to_string())),
+        }
+    }
+
+    fn accepted(&mut self, entry: &str, session: &Session) {
+        let _ = self.editor.add_history_entry(entry);
+        if let Some(path) = &self.history {
+            let _ = self.editor.save_history(path);
+        }
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.names = session.names();
+        }
+    }
+}
+
+// history_path is the history file in the user's home directory.
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// EditorHelper highlights input as it is typed, and completes keywords,
+/// builtins, colon commands and the names bound in the session.
+pub struct EditorHelper {
+    names: Vec<String>,
+}
+
+impl Completer for EditorHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos, &self.names))
+    }
+}
+
+impl Hinter for EditorHelper {
+    type Hint = String;
+}
+
+impl Highlighter for EditorHelper {
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        if line.trim_start().starts_with(':') {
+            Cow::Borrowed(line)
+        } else {
+            Cow::Owned(highlight(line))
+        }
+    }
+
+    fn highlight_char(&self, _: &str, _: usize, _: bool) -> bool {
+        true
+    }
+}
+
+impl Validator for EditorHelper {}
+
+impl Helper for EditorHelper {}
+
+/// complete finds the words that could finish the one that ends at `pos`,
+/// returning where that word starts and the candidates in sorted order.
+pub fn complete(line: &str, pos: usize, names: &[String]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .map_or(0, |ii| ii + 1);
+    let word = &before[start..];
+    if word.is_empty() {
+        return (pos, vec![]);
+    }
+    let candidates: Vec<&str> = if word.starts_with(':') {
+        repl::COMMANDS.to_vec()
+    } else {
+        KEYWORDS.iter()
+            .cloned()
+            .chain(Builtin::ALL.iter().map(|b| b.name()))
+            .chain(names.iter().map(|n| n.as_str()))
+            .collect()
+    };
+    let mut matches: Vec<String> = candidates
+        .into_iter()
+        .filter(|c| c.starts_with(word))
+        .map(String::from)
+        .collect();
+    matches.sort();
+    matches.dedup();
+    (start, matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion() {
+        let names = vec!["length".to_string(), "result".to_string()];
+        let tests = vec![
+            ("le", 2, (0, vec!["len", "length", "let"])),
+            ("1 + re", 6, (4, vec!["result", "return"])),
+            ("puts(ar", 7, (5, vec!["argc", "argv"])),
+            ("f", 1, (0, vec!["false", "fn"])),
+            (":l", 2, (0, vec![":load"])),
+            ("let x = ", 8, (8, vec![])),
+            ("zz", 2, (0, vec![])),
+            ("re + 1", 2, (0, vec!["result", "return"])),
+        ];
+        for (line, pos, (start, want)) in tests {
+            let want: Vec<String> = want.into_iter().map(String::from).collect();
+            assert_eq!(complete(line, pos, &names), (start, want), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use crate::ast::{Program, Statement, BlockStatement, Expression, Identifier, Binding, Prefix, Infix};
+use crate::object::{Object, Builtin, Env, Frame};
+use crate::resolver;
+use crate::token::{Position, Span};
+use crate::util::{self, Diagnostic, Diagnostics};
+
+use std::io::Write;
+
+type Error = Box<dyn std::error::Error>;
+
+/// MAX_DEPTH is how many function calls may be in progress at once before
+/// evaluation fails, so that runaway recursion is reported rather than
+/// overflowing the stack.
+pub const MAX_DEPTH: usize = 10_000;
+
+// When less than RED_ZONE bytes of stack are left, a call carries on in a new
+// segment of STACK_SIZE bytes, so that recursion is bounded by MAX_DEPTH
+// rather than by the size of the thread's stack.
+const RED_ZONE: usize = 128 * 1024;
+const STACK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Evaluator walks an AST and computes its value. Bindings made by one
+/// program are visible to the next, so a single evaluator can serve a whole
+/// session.
+pub struct Evaluator {
+    env: Env,
+    args: Vec<String>,
+    // depth is the number of function calls in progress.
+    depth: usize,
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator {
+            env: Env::new(),
+            args: vec![],
+            depth: 0,
+        }
+    }
+
+    /// with_env creates an evaluator whose globals live in `env`, which may be
+    /// shared with other evaluators.
+    pub fn with_env(env: Env) -> Evaluator {
+        Evaluator {
+            env,
+            args: vec![],
+            depth: 0,
+        }
+    }
+
+    /// args sets the script arguments exposed through `argc` and `argv`.
+    pub fn args(mut self, args: Vec<String>) -> Evaluator {
+        self.args = args;
+        self
+    }
+
+    /// env is the global environment that programs are evaluated in.
+    pub fn env(&self) -> Env {
+        self.env.clone()
+    }
+
+    /// reset forgets every global binding, including for any evaluator that
+    /// shares them.
+    pub fn reset(&mut self) {
+        self.env.clear();
+    }
+
+    /// resolve binds the identifiers in the program against this evaluator's
+    /// globals, which must be done before it can be evaluated.
+    pub fn resolve(&self, program: &mut Program) -> Result<(), Diagnostics> {
+        resolver::resolve(program, &self.env)
+    }
+
+    /// eval evaluates the program and returns the value of its last
+    /// statement. Output from `puts` is written to `out`.
+    pub fn eval(&mut self, program: &Program, out: &mut dyn Write) -> Result<Object, Error> {
+        match self.eval_statements(&program.statements, None, out)? {
+            Object::Return(value) => Ok(*value),
+            value => Ok(value),
+        }
+    }
+
+    // eval_statements stops at the first return statement and hands back the
+    // wrapped value so that it can unwind through nested blocks. `frame` is
+    // the frame of the function being called, or None at the top level.
+    fn eval_statements(&mut self, statements: &[Statement], frame: Option<&Frame>, out: &mut dyn Write) -> Result<Object, Error> {
+        let mut result = Object::Null;
+        for stmt in statements {
+            result = self.eval_statement(stmt, frame, out)?;
+            if let Object::Return(_) = result {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    fn eval_statement(&mut self, stmt: &Statement, frame: Option<&Frame>, out: &mut dyn Write) -> Result<Object, Error> {
+        let value = match stmt {
+            Statement::Let(stmt) => {
+                let value = match self.eval_expression(&stmt.value, frame, out)? {
+                    Object::Function { name: None, parameters, body, frame } => {
+                        Object::Function { name: Some(stmt.name.name.clone()), parameters, body, frame }
+                    },
+                    value => value,
+                };
+                self.bind(&stmt.name, value, frame).map_err(|err| located(err, stmt.name.position))?;
+                Object::Null
+            }
+            Statement::Return(stmt) => {
+                Object::Return(Box::new(self.eval_expression(&stmt.value, frame, out)?))
+            }
+            Statement::Expression(exp) => self.eval_expression(exp, frame, out)?,
+        };
+        Ok(value)
+    }
+
+    fn eval_block(&mut self, block: &BlockStatement, frame: Option<&Frame>, out: &mut dyn Write) -> Result<Object, Error> {
+        self.eval_statements(&block.statements, frame, out)
+    }
+
+    fn eval_expression(&mut self, exp: &Expression, frame: Option<&Frame>, out: &mut dyn Write) -> Result<Object, Error> {
+        let value = match exp {
+            Expression::Int(n) => Object::Int(n.value),
+            Expression::Boolean(b) => Object::Boolean(b.value),
+            Expression::String(s) => Object::String(s.value.clone()),
+            Expression::Identifier(identifier) => {
+                self.lookup(identifier, frame).map_err(|err| located(err, identifier.position))?
+            }
+            Expression::Prefix(exp) => {
+                let value = self.eval_expression(&exp.value, frame, out)?;
+                self.eval_prefix(&exp.operator, value).map_err(|err| located(err, exp.position))?
+            }
+            Expression::Infix(exp) => {
+                let left = self.eval_expression(&exp.left, frame, out)?;
+                let right = self.eval_expression(&exp.right, frame, out)?;
+                self.eval_infix(&exp.operator, left, right).map_err(|err| located(err, exp.position))?
+            }
+            Expression::If(exp) => {
+                if self.eval_expression(&exp.predicate, frame, out)?.truthy() {
+                    self.eval_block(&exp.success, frame, out)?
+                } else {
+                    match &exp.fail {
+                        Some(fail) => self.eval_block(fail, frame, out)?,
+                        None => Object::Null,
+                    }
+                }
+            }
+            Expression::Function(exp) => {
+                Object::Function {
+                    name: None,
+                    parameters: exp.parameters.clone(),
+                    body: exp.body.clone(),
+                    frame: frame.cloned(),
+                }
+            }
+            Expression::Call(exp) => {
+                let function = self.eval_expression(&exp.function, frame, out)?;
+                let mut values = vec![];
+                for arg in &exp.arguments {
+                    values.push(self.eval_expression(arg, frame, out)?);
+                }
+                let site = Span { start: exp.function.position(), end: exp.position };
+                self.apply(function, values, site, out).map_err(|err| located(err, exp.position))?
+            }
+        };
+        Ok(value)
+    }
+
+    // lookup reads an identifier from where the resolver bound it. A global
+    // may not have been set yet, because the let binding it came later or
+    // failed, in which case a builtin of the same name is used if there is one.
+    fn lookup(&self, identifier: &Identifier, frame: Option<&Frame>) -> Result<Object, Error> {
+        let value = match identifier.binding {
+            Binding::Local(slot) => frame.and_then(|frame| frame.load(slot)),
+            Binding::Free { depth, slot } => frame.and_then(|frame| frame.outer(depth)).and_then(|frame| frame.load(slot)),
+            Binding::Global(slot) => self.env.load(slot),
+            Binding::Builtin(index) => Some(Object::Builtin(Builtin::ALL[index])),
+            Binding::Unresolved => return Err(error("E0110", format!("unresolved identifier: {}", identifier.name))),
+        };
+        match value.or_else(|| Builtin::lookup(&identifier.name).map(Object::Builtin)) {
+            Some(value) => Ok(value),
+            None => {
+                let mut diagnostic = Diagnostic::error("E0103", format!("identifier not found: {}", identifier.name));
+                let bound = self.env.bindings();
+                let names = bound.iter().map(|(name, _)| name.as_str()).chain(Builtin::ALL.iter().map(|b| b.name()));
+                if let Some(name) = util::suggest(&identifier.name, names) {
+                    diagnostic = diagnostic.suggest(identifier.position, identifier.name.as_str(), name);
+                }
+                Err(Box::new(diagnostic))
+            },
+        }
+    }
+
+    fn bind(&self, name: &Identifier, value: Object, frame: Option<&Frame>) -> Result<(), Error> {
+        match (name.binding, frame) {
+            (Binding::Local(slot), Some(frame)) => frame.store(slot, value),
+            (Binding::Global(slot), _) => self.env.store(slot, value),
+            _ => return Err(error("E0110", format!("unresolved identifier: {}", name.name))),
+        }
+        Ok(())
+    }
+
+    fn eval_prefix(&self, operator: &Prefix, value: Object) -> Result<Object, Error> {
+        match (operator, value) {
+            (Prefix::Not, value) => Ok(Object::Boolean(!value.truthy())),
+            (Prefix::Negative, Object::Int(n)) => {
+                n.checked_neg()
+                    .map(Object::Int)
+                    .ok_or_else(|| error("E0105", "integer overflow"))
+            },
+            (operator, value) => {
+                Err(error("E0102", format!("unknown operator: {}{}", operator, value.type_name())))
+            },
+        }
+    }
+
+    fn eval_infix(&self, operator: &Infix, left: Object, right: Object) -> Result<Object, Error> {
+        match (left, right) {
+            (Object::Int(l), Object::Int(r)) => {
+                let value = match operator {
+                    Infix::Add => l.checked_add(r),
+                    Infix::Subtract => l.checked_sub(r),
+                    Infix::Multiply => l.checked_mul(r),
+                    Infix::Divide => {
+                        if r == 0 {
+                            return Err(error("E0104", "division by zero"));
+                        }
+                        l.checked_div(r)
+                    },
+                    Infix::LessThan => return Ok(Object::Boolean(l < r)),
+                    Infix::GreaterThan => return Ok(Object::Boolean(l > r)),
+                    Infix::Eq => return Ok(Object::Boolean(l == r)),
+                    Infix::NotEq => return Ok(Object::Boolean(l != r)),
+                };
+                value.map(Object::Int).ok_or_else(|| error("E0105", "integer overflow"))
+            },
+            (Object::Boolean(l), Object::Boolean(r)) => match operator {
+                Infix::Eq => Ok(Object::Boolean(l == r)),
+                Infix::NotEq => Ok(Object::Boolean(l != r)),
+                _ => Err(error("E0102", format!("unknown operator: BOOLEAN {} BOOLEAN", operator))),
+            },
+            (Object::String(l), Object::String(r)) => match operator {
+                Infix::Add => Ok(Object::String(l + &r)),
+                Infix::Eq => Ok(Object::Boolean(l == r)),
+                Infix::NotEq => Ok(Object::Boolean(l != r)),
+                _ => Err(error("E0102", format!("unknown operator: STRING {} STRING", operator))),
+            },
+            (left, right) => {
+                if left.type_name() == right.type_name() {
+                    Err(error("E0102", format!("unknown operator: {} {} {}", left.type_name(), operator, right.type_name())))
+                } else {
+                    Err(error("E0101", format!("type mismatch: {} {} {}", left.type_name(), operator, right.type_name())))
+                }
+            },
+        }
+    }
+
+    // apply calls a function. Errors raised in its body record the call,
+    // made at `site`, so that they can be traced back to the top level.
+    fn apply(&mut self, function: Object, args: Vec<Object>, site: Span, out: &mut dyn Write) -> Result<Object, Error> {
+        match function {
+            Object::Function { name, parameters, body, frame } => {
+                if parameters.len() != args.len() {
+                    return Err(error("E0107", format!("wrong number of arguments: want={}, got={}", parameters.len(), args.len())));
+                }
+                if self.depth == MAX_DEPTH {
+                    return Err(error("E0111", format!("call depth exceeded: more than {} calls in progress", MAX_DEPTH)));
+                }
+                let frame = Frame::new(args, frame);
+                self.depth += 1;
+                let result = stacker::maybe_grow(RED_ZONE, STACK_SIZE, || self.eval_block(&body, Some(&frame), out));
+                self.depth -= 1;
+                match result {
+                    Ok(Object::Return(value)) => Ok(*value),
+                    Ok(value) => Ok(value),
+                    Err(err) => Err(unwound(err, name, site)),
+                }
+            },
+            Object::Builtin(builtin) => self.call_builtin(builtin, args, out),
+            other => Err(error("E0106", format!("not a function: {}", other.type_name()))),
+        }
+    }
+
+    fn call_builtin(&mut self, builtin: Builtin, args: Vec<Object>, out: &mut dyn Write) -> Result<Object, Error> {
+        match builtin {
+            Builtin::Len => match args.as_slice() {
+                [Object::String(s)] => Ok(Object::Int(s.chars().count() as i64)),
+                [other] => Err(error("E0108", format!("argument to `len` not supported, got {}", other.type_name()))),
+                _ => Err(error("E0107", format!("wrong number of arguments: want=1, got={}", args.len()))),
+            },
+            Builtin::Puts => {
+                for arg in &args {
+                    writeln!(out, "{}", arg)?;
+                }
+                Ok(Object::Null)
+            },
+            Builtin::Argc => Ok(Object::Int(self.args.len() as i64)),
+            Builtin::Argv => match args.as_slice() {
+                [Object::Int(n)] => {
+                    let arg = if *n >= 0 { self.args.get(*n as usize) } else { None };
+                    match arg {
+                        Some(arg) => Ok(Object::String(arg.clone())),
+                        None => Err(error("E0109", format!("argv: index {} out of range", n))),
+                    }
+                },
+                [other] => Err(error("E0108", format!("argument to `argv` not supported, got {}", other.type_name()))),
+                _ => Err(error("E0107", format!("wrong number of arguments: want=1, got={}", args.len()))),
+            },
+        }
+    }
+}
+
+// error is a runtime error, identified by its diagnostic code.
+fn error<S: Into<String>>(code: &'static str, message: S) -> Error {
+    Box::new(Diagnostic::error(code, message))
+}
+
+// located places an error that has no position yet at `position`, where the
+// expression that raised it is.
+fn located(err: Error, position: Position) -> Error {
+    match err.downcast::<Diagnostic>() {
+        Ok(diagnostic) if diagnostic.position().is_none() => Box::new(diagnostic.at(position)),
+        Ok(diagnostic) => diagnostic,
+        Err(err) => err,
+    }
+}
+
+// unwound records a call of `function` that an error unwound through.
+fn unwound(err: Error, function: Option<String>, site: Span) -> Error {
+    match err.downcast::<Diagnostic>() {
+        Ok(diagnostic) => Box::new(diagnostic.inside(function, site)),
+        Err(err) => err,
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::strip_ansi;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn eval(input: &str) -> Result<Object, Error> {
+        let mut program = Parser::new(Lexer::new(input.chars())).parse()?;
+        let mut evaluator = Evaluator::new();
+        evaluator.resolve(&mut program)?;
+        evaluator.eval(&program, &mut vec![])
+    }
+
+    #[test]
+    fn values() -> Result<(), Error> {
+        let tests = vec![
+            ("5", "5"),
+            ("-5 + 10 * 2", "15"),
+            ("15 / 3 - 1", "4"),
+            ("(5 + 10) / 3", "5"),
+            (r#"len("héllo" + "!")"#, "6"),
+            ("1 < 2 == true", "true"),
+            ("!5", "false"),
+            ("!!true", "true"),
+            ("if (1 > 2) { 10 }", "null"),
+            ("if (1 < 2) { 10 } else { 20 }", "10"),
+            ("if (1 > 2) { 10 } else { 20 }", "20"),
+            ("let a = 5; let b = a * 2; b + a;", "15"),
+            ("if (true) { if (true) { return 10; } return 1; }", "10"),
+            ("let add = fn(a, b) { a + b }; add(2, add(3, 4));", "9"),
+            ("let adder = fn(x) { fn(y) { x + y } }; let two = adder(2); two(3);", "5"),
+            ("let f = fn(x) { if (x == 0) { 0 } else { x + f(x - 1) } }; f(10);", "55"),
+            ("fn(x) { return x * 2; 0 }(4)", "8"),
+            ("let count = fn() { let down = fn(n) { if (n == 0) { 0 } else { 1 + down(n - 1) } }; down }; count()(3);", "3"),
+        ];
+        for (input, want) in tests {
+            assert_eq!(eval(input)?.to_string(), want, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let tests = vec![
+            ("5 + true;", "1:3: type mismatch: INTEGER + BOOLEAN"),
+            ("-true", "1:1: unknown operator: -BOOLEAN"),
+            ("true + false;", "1:6: unknown operator: BOOLEAN + BOOLEAN"),
+            ("if (10 > 1) { true + false; 5 }", "1:20: unknown operator: BOOLEAN + BOOLEAN"),
+            ("if (false) { let x = 1; }; x", "1:28: identifier not found: x"),
+            ("5 / 0", "1:3: division by zero"),
+            ("let x = 1; x(2)", "1:13: not a function: INTEGER"),
+            ("fn(a) { a }()", "1:12: wrong number of arguments: want=1, got=0"),
+            ("len(1)", "1:4: argument to `len` not supported, got INTEGER"),
+        ];
+        for (input, want) in tests {
+            match eval(input) {
+                Ok(value) => panic!("{}: expected error, got {}", input, value),
+                Err(err) => assert_eq!(err.to_string(), want, "{}", input),
+            }
+        }
+    }
+
+    // Recursion runs as deep as MAX_DEPTH whatever the size of the thread's
+    // stack, and fails with a diagnostic past it.
+    #[test]
+    fn depth() -> Result<(), Error> {
+        let sum = "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } };";
+        let n = MAX_DEPTH - 1;
+        assert_eq!(eval(&format!("{} sum({})", sum, n))?.to_string(), (n * (n + 1) / 2).to_string());
+        let err = eval(&format!("{} sum({})", sum, n + 1)).err().ok_or("expected an error")?;
+        let diagnostic = err.downcast_ref::<Diagnostic>().ok_or("error without a code")?;
+        assert_eq!((diagnostic.code, diagnostic.trace.len()), ("E0111", MAX_DEPTH));
+        Ok(())
+    }
+
+    // Errors in functions are traced back through the calls that led to
+    // them. Functions are named by the let that first binds them.
+    #[test]
+    fn tracebacks() -> Result<(), Error> {
+        let tests = vec![
+            (
+                "let countdown = fn(n) { if (n == 0) { 1 / n } else { countdown(n - 1) } };\ncountdown(3)",
+                "1:41: error[E0104]: division by zero\n\
+                 \x20 traceback, innermost call first:\n\
+                 \x20   1:41: in `countdown`\n\
+                 \x20   1:54: in `countdown`\n\
+                 \x20   ... repeated 2 more time(s)\n\
+                 \x20   2:1: at the top level\n",
+            ),
+            (
+                "let f = fn() { len(1) }; let g = f; g()",
+                "1:19: error[E0108]: argument to `len` not supported, got INTEGER\n\
+                 \x20 traceback, innermost call first:\n\
+                 \x20   1:19: in `f`\n\
+                 \x20   1:37: at the top level\n",
+            ),
+            (
+                "fn(x) { x(1) }(2)",
+                "1:10: error[E0106]: not a function: INTEGER\n\
+                 \x20 traceback, innermost call first:\n\
+                 \x20   1:10: in an anonymous function\n\
+                 \x20   1:1: at the top level\n",
+            ),
+            ("1 + fn() { 2 }", "1:3: error[E0101]: type mismatch: INTEGER + FUNCTION\n"),
+        ];
+        for (input, want) in tests {
+            let err = match eval(input) {
+                Ok(value) => panic!("{}: expected error, got {}", input, value),
+                Err(err) => err,
+            };
+            let diagnostic = err.downcast_ref::<Diagnostic>().ok_or("error without a code")?;
+            assert_eq!(strip_ansi(&diagnostic.human(None)), want, "{}", input);
+        }
+        Ok(())
+    }
+
+    // A name that isn't bound yet suggests one that is.
+    #[test]
+    fn suggestions() -> Result<(), Error> {
+        let tests = vec![
+            ("let count = 1; if (false) { let counts = 2; }; counts", Some("did you mean `count`?")),
+            ("if (false) { let x = 1; }; x", None),
+        ];
+        for (input, want) in tests {
+            let err = match eval(input) {
+                Ok(value) => panic!("{}: expected error, got {}", input, value),
+                Err(err) => err,
+            };
+            let diagnostic = err.downcast_ref::<Diagnostic>().ok_or("error without a code")?;
+            assert_eq!(diagnostic.help.as_deref(), want, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn builtins() -> Result<(), Error> {
+        let mut program = Parser::new(Lexer::new("puts(argc(), argv(1)); len(argv(0))".chars())).parse()?;
+        let mut out = vec![];
+        let mut evaluator = Evaluator::new().args(vec!["first".into(), "second".into()]);
+        evaluator.resolve(&mut program)?;
+        let value = evaluator.eval(&program, &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "2\nsecond\n");
+        assert_eq!(value.to_string(), "5");
+        Ok(())
+    }
+}
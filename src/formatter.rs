@@ -0,0 +1,527 @@
+use crate::ast::{Program, Statement, BlockStatement, Expression, Identifier, Precedence, Infix};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::printer::{self, parenthesize, parenthesize_callee};
+use crate::token::{Kind, Position, Span};
+use crate::util::Diagnostics;
+
+use std::collections::{HashSet, VecDeque};
+use std::slice;
+
+/// Config controls the layout chosen by `format`.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Config {
+    // indent is the number of spaces per level of nesting.
+    pub indent: usize,
+    // width is the line length that the formatter tries to stay within.
+    pub width: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { indent: 4, width: 100 }
+    }
+}
+
+/// format lays out Monkey source in the canonical style, keeping comments and
+/// single blank lines between statements. Formatting never changes the
+/// syntax tree, so source that doesn't parse is an error.
+//
+// Comments are kept at the level of statements: a comment is printed before
+// the statement that follows it, or after the statement whose line it ends.
+// The few that sit inside an expression are moved to after its statement.
+//
+pub fn format(source: &str, config: &Config) -> Result<String, Diagnostics> {
+    let mut parser = Parser::new(Lexer::new(source.chars()));
+    let program = parser.parse()?;
+    let mut formatter = Formatter::new(source, parser.spans(), config);
+    formatter.program(&program);
+    Ok(formatter.out)
+}
+
+// Comment is a comment from the source, along with the position of the token
+// before it so that comments ending a statement's line can stay there.
+struct Comment {
+    text: String,
+    position: Position,
+    after: Option<Position>,
+}
+
+struct Formatter<'a> {
+    config: &'a Config,
+    // spans are consumed as statements and blocks are met, which happens in
+    // the same order that the parser recorded them.
+    spans: slice::Iter<'a, Span>,
+    comments: VecDeque<Comment>,
+    // occupied holds the source lines that have a token or comment on them.
+    occupied: HashSet<usize>,
+    out: String,
+    depth: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &str, spans: &'a [Span], config: &'a Config) -> Formatter<'a> {
+        let mut comments = VecDeque::new();
+        let mut occupied = HashSet::new();
+        let mut after = None;
+        for token in Lexer::new(source.chars()).lossless() {
+            let lines = token.literal.matches('\n').count();
+            occupied.extend(token.position.line..=token.position.line + lines);
+            if token.kind == Kind::Comment {
+                comments.push_back(Comment {
+                    text: token.literal.trim_end().to_string(),
+                    position: token.position,
+                    after,
+                });
+            } else {
+                after = Some(token.position);
+            }
+        }
+        Formatter {
+            config,
+            spans: spans.iter(),
+            comments,
+            occupied,
+            out: String::new(),
+            depth: 0,
+        }
+    }
+
+    fn program(&mut self, program: &Program) {
+        for (ii, stmt) in program.statements.iter().enumerate() {
+            self.statement(stmt, ii == 0);
+        }
+        let mut first = program.statements.is_empty();
+        while let Some(comment) = self.comments.pop_front() {
+            self.comment(&comment, first);
+            first = false;
+        }
+    }
+
+    // statement writes a statement on its own lines, preceded by the comments
+    // before it. `first` is set for the first line of a block, which never
+    // starts with a blank line.
+    fn statement(&mut self, stmt: &Statement, mut first: bool) {
+        let span = self.span();
+        for comment in self.take_before(span.start) {
+            self.comment(&comment, first);
+            first = false;
+        }
+        if !first && self.blank_before(span.start.line) {
+            self.out.push('\n');
+        }
+        self.indent();
+        match stmt {
+            Statement::Let(stmt) => {
+                self.out.push_str("let ");
+                self.out.push_str(&stmt.name.name);
+                self.out.push_str(" = ");
+                self.expression(&stmt.value, 1);
+            },
+            Statement::Return(stmt) => {
+                self.out.push_str("return ");
+                self.expression(&stmt.value, 1);
+            },
+            Statement::Expression(exp) => self.expression(exp, 1),
+        }
+        self.out.push(';');
+        let inside = self.take_before(span.end);
+        let trailing = self.comments.front().is_some_and(|comment| {
+            comment.position.line == span.end.line && comment.after == Some(span.end)
+        });
+        if trailing {
+            if let Some(comment) = self.comments.pop_front() {
+                self.out.push(' ');
+                self.out.push_str(&comment.text);
+            }
+        }
+        self.out.push('\n');
+        for comment in inside {
+            self.comment(&comment, true);
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        let span = self.span();
+        let statements = &block.statements;
+        let dangling = self.comments.front().is_some_and(|c| c.position < span.end);
+        if statements.is_empty() && !dangling {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        for (ii, stmt) in statements.iter().enumerate() {
+            self.statement(stmt, ii == 0);
+        }
+        let mut first = statements.is_empty();
+        for comment in self.take_before(span.end) {
+            self.comment(&comment, first);
+            first = false;
+        }
+        self.depth -= 1;
+        self.indent();
+        self.out.push('}');
+    }
+
+    // expression writes an expression starting at the current column. `trail`
+    // is the width of what must follow it on the same line, such as the
+    // semicolon ending a statement.
+    fn expression(&mut self, exp: &Expression, trail: usize) {
+        match exp {
+            Expression::Prefix(prefix) => {
+                self.out.push_str(&prefix.operator.to_string());
+                self.operand(&prefix.value, &Precedence::Prefix, false, trail);
+            },
+            Expression::Infix(infix) => {
+                if self.fits(exp, trail) {
+                    let precedence = infix.operator.precedence();
+                    self.operand(&infix.left, &precedence, false, 0);
+                    self.out.push_str(&format!(" {} ", infix.operator));
+                    self.operand(&infix.right, &precedence, true, trail);
+                } else {
+                    self.chain(exp, trail);
+                }
+            },
+            Expression::If(exp) => {
+                self.out.push_str("if (");
+                self.expression(&exp.predicate, ") {".len());
+                self.out.push_str(") ");
+                self.block(&exp.success);
+                if let Some(fail) = &exp.fail {
+                    self.out.push_str(" else ");
+                    self.block(fail);
+                }
+            },
+            Expression::Function(exp) => {
+                self.out.push_str("fn");
+                self.list(&exp.parameters, ") {".len());
+                self.out.push(' ');
+                self.block(&exp.body);
+            },
+            Expression::Call(exp) => {
+                if parenthesize_callee(&exp.function) {
+                    self.grouped(&exp.function, 1);
+                } else {
+                    self.expression(&exp.function, 1);
+                }
+                self.arguments(&exp.arguments, trail);
+            },
+            _ => self.out.push_str(&printer::print_expression(exp)),
+        }
+    }
+
+    fn operand(&mut self, exp: &Expression, outer: &Precedence, right: bool, trail: usize) {
+        if parenthesize(exp, outer, right) {
+            self.grouped(exp, trail);
+        } else {
+            self.expression(exp, trail);
+        }
+    }
+
+    fn grouped(&mut self, exp: &Expression, trail: usize) {
+        self.out.push('(');
+        self.expression(exp, trail + 1);
+        self.out.push(')');
+    }
+
+    // chain writes a run of operators of the same precedence that doesn't fit
+    // on one line, breaking the line after each operator.
+    fn chain(&mut self, exp: &Expression, trail: usize) {
+        let (first, rest) = operands(exp);
+        let precedence = match rest.first() {
+            Some((operator, _)) => operator.precedence(),
+            None => return self.expression(exp, trail),
+        };
+        // Each operand but the last is followed by a space and an operator.
+        let widths: Vec<usize> = rest.iter().map(|(operator, _)| operator.to_string().len() + 1).collect();
+        self.operand(first, &precedence, false, widths[0]);
+        self.depth += 1;
+        for (ii, (operator, operand)) in rest.iter().enumerate() {
+            self.out.push(' ');
+            self.out.push_str(&operator.to_string());
+            self.out.push('\n');
+            self.indent();
+            let trail = widths.get(ii + 1).copied().unwrap_or(trail);
+            self.operand(operand, &precedence, true, trail);
+        }
+        self.depth -= 1;
+    }
+
+    // arguments writes the arguments of a call on the same line if they fit.
+    // A function passed last can start on the same line with its body below.
+    // Otherwise each argument gets a line of its own.
+    fn arguments(&mut self, arguments: &[Expression], trail: usize) {
+        self.out.push('(');
+        let line = arguments.iter().map(flat).collect::<Option<Vec<String>>>();
+        if let Some(line) = line {
+            if self.column() + line.join(", ").chars().count() + 1 + trail <= self.config.width {
+                for (ii, argument) in arguments.iter().enumerate() {
+                    if ii > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(argument, 0);
+                }
+                self.out.push(')');
+                return;
+            }
+        }
+        if let Some((last @ Expression::Function(function), init)) = arguments.split_last() {
+            let head = init.iter().map(flat).collect::<Option<Vec<String>>>();
+            if let Some(mut head) = head {
+                head.push(format!("fn({}) {{", printer::parameters(&function.parameters)));
+                if self.column() + head.join(", ").chars().count() <= self.config.width {
+                    for argument in init {
+                        self.expression(argument, 0);
+                        self.out.push_str(", ");
+                    }
+                    self.expression(last, 1);
+                    self.out.push(')');
+                    return;
+                }
+            }
+        }
+        self.lines(arguments, |formatter, argument, trail| formatter.expression(argument, trail));
+    }
+
+    // list writes a parenthesised list of parameters.
+    fn list(&mut self, parameters: &[Identifier], trail: usize) {
+        let flat = printer::parameters(parameters);
+        if self.column() + flat.chars().count() + 2 + trail <= self.config.width {
+            self.out.push('(');
+            self.out.push_str(&flat);
+            self.out.push(')');
+        } else {
+            self.out.push('(');
+            self.lines(parameters, |formatter, parameter, _| formatter.out.push_str(&parameter.name));
+        }
+    }
+
+    // lines finishes a parenthesised list with one item per line, writing
+    // each item with `write`.
+    fn lines<T>(&mut self, items: &[T], write: impl Fn(&mut Self, &T, usize)) {
+        if items.is_empty() {
+            self.out.push(')');
+            return;
+        }
+        self.depth += 1;
+        for (ii, item) in items.iter().enumerate() {
+            let last = ii + 1 == items.len();
+            self.out.push('\n');
+            self.indent();
+            write(self, item, if last { 0 } else { 1 });
+            if !last {
+                self.out.push(',');
+            }
+        }
+        self.depth -= 1;
+        self.out.push('\n');
+        self.indent();
+        self.out.push(')');
+    }
+
+    fn comment(&mut self, comment: &Comment, first: bool) {
+        if !first && self.blank_before(comment.position.line) {
+            self.out.push('\n');
+        }
+        self.indent();
+        self.out.push_str(&comment.text);
+        self.out.push('\n');
+    }
+
+    // fits reports whether the expression fits on the rest of the current line.
+    fn fits(&self, exp: &Expression, trail: usize) -> bool {
+        match flat(exp) {
+            Some(flat) => self.column() + flat.chars().count() + trail <= self.config.width,
+            None => false,
+        }
+    }
+
+    fn column(&self) -> usize {
+        let start = self.out.rfind('\n').map_or(0, |ii| ii + 1);
+        self.out[start..].chars().count()
+    }
+
+    fn indent(&mut self) {
+        self.out.push_str(&" ".repeat(self.depth * self.config.indent));
+    }
+
+    fn span(&mut self) -> Span {
+        self.spans.next().copied().unwrap_or_default()
+    }
+
+    // take_before removes the comments that come before `position`.
+    fn take_before(&mut self, position: Position) -> Vec<Comment> {
+        let mut taken = vec![];
+        while self.comments.front().is_some_and(|c| c.position < position) {
+            taken.extend(self.comments.pop_front());
+        }
+        taken
+    }
+
+    // blank_before reports whether the source has a blank line just before
+    // the given line.
+    fn blank_before(&self, line: usize) -> bool {
+        line > 1 && !self.occupied.contains(&(line - 1))
+    }
+}
+
+// flat is the expression written on a single line, or None if it contains a
+// block with statements in it, which always spans several lines.
+fn flat(exp: &Expression) -> Option<String> {
+    if multiline(exp) {
+        None
+    } else {
+        Some(printer::print_expression(exp))
+    }
+}
+
+fn multiline(exp: &Expression) -> bool {
+    let block = |block: &BlockStatement| !block.statements.is_empty();
+    match exp {
+        Expression::Function(exp) => block(&exp.body),
+        Expression::If(exp) => {
+            multiline(&exp.predicate) || block(&exp.success) || exp.fail.as_ref().is_some_and(block)
+        },
+        Expression::Prefix(exp) => multiline(&exp.value),
+        Expression::Infix(exp) => multiline(&exp.left) || multiline(&exp.right),
+        Expression::Call(exp) => multiline(&exp.function) || exp.arguments.iter().any(multiline),
+        _ => false,
+    }
+}
+
+// operands splits a chain of operators with the same precedence, such as
+// `a + b - c`, into its first operand and the operators that follow.
+fn operands(exp: &Expression) -> (&Expression, Vec<(&Infix, &Expression)>) {
+    let mut rest = vec![];
+    let mut first = exp;
+    while let Expression::Infix(infix) = first {
+        if rest.first().is_some_and(|(outer, _): &(&Infix, &Expression)| outer.precedence() != infix.operator.precedence()) {
+            break;
+        }
+        rest.push((&infix.operator, infix.right.as_ref()));
+        first = &infix.left;
+    }
+    rest.reverse();
+    (first, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::printer::arbitrary::program;
+    use crate::visit::strip;
+    use proptest::prelude::*;
+
+    type Error = Box<dyn std::error::Error>;
+
+    #[test]
+    fn layout() -> Result<(), Error> {
+        let tests = vec![
+            ("", ""),
+            ("let x=1+2*3", "let x = 1 + 2 * 3;\n"),
+            (
+                "let max = fn(a, b) { if (a > b) { return a; } else { b } };",
+                "let max = fn(a, b) {\n    if (a > b) {\n        return a;\n    } else {\n        b;\n    };\n};\n",
+            ),
+            ("let noop=fn(){};noop()", "let noop = fn() {};\nnoop();\n"),
+            ("map(xs, fn(x) { x * 2 })", "map(xs, fn(x) {\n    x * 2;\n});\n"),
+            (
+                "let a = 1;\n\n\n\nlet b = 2;\nlet c = 3;",
+                "let a = 1;\n\nlet b = 2;\nlet c = 3;\n",
+            ),
+        ];
+        for (input, want) in tests {
+            assert_eq!(format(input, &Config::default())?, want, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn comments() -> Result<(), Error> {
+        let tests = vec![
+            ("// only a comment   ", "// only a comment\n"),
+            (
+                "// leading\nlet x = 1; // trailing\n\n// before y\nlet y = x;\n// the end",
+                "// leading\nlet x = 1; // trailing\n\n// before y\nlet y = x;\n// the end\n",
+            ),
+            (
+                "let f = fn(x) { // inside\n  x\n  // dangling\n};",
+                "let f = fn(x) {\n    // inside\n    x;\n    // dangling\n};\n",
+            ),
+            ("let f = fn() {\n// empty\n}", "let f = fn() {\n    // empty\n};\n"),
+            ("f(1, // one\n2); g()", "f(1, 2);\n// one\ng();\n"),
+            ("if (x) { a } // after\nb", "if (x) {\n    a;\n}; // after\nb;\n"),
+        ];
+        for (input, want) in tests {
+            assert_eq!(format(input, &Config::default())?, want, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn width() -> Result<(), Error> {
+        let config = Config { indent: 2, width: 24 };
+        let tests = vec![
+            ("let total = first + second + third;", "let total = first +\n  second +\n  third;\n"),
+            (
+                "let r = combine(alpha, beta, gamma);",
+                "let r = combine(\n  alpha,\n  beta,\n  gamma\n);\n",
+            ),
+            (
+                "let f = fn(alpha, beta, gamma) { alpha };",
+                "let f = fn(\n  alpha,\n  beta,\n  gamma\n) {\n  alpha;\n};\n",
+            ),
+            ("each(xs, fn(x) { puts(x) });", "each(xs, fn(x) {\n  puts(x);\n});\n"),
+            (
+                "if (count * (first - second) > 0) { 1 }",
+                "if (count *\n  (first - second) >\n  0) {\n  1;\n};\n",
+            ),
+        ];
+        for (input, want) in tests {
+            assert_eq!(format(input, &config)?, want, "{}", input);
+        }
+        Ok(())
+    }
+
+    // Formatting formatted source changes nothing, and never changes what
+    // the source parses to.
+    #[test]
+    fn idempotent() -> Result<(), Error> {
+        let inputs = vec![
+            "let fibonacci = fn(x) { if (x == 0) { 0 } else { if (x == 1) { 1 } else { fibonacci(x - 1) + fibonacci(x - 2); } } };",
+            "// Higher order functions.\n\nlet twice = fn(f, x) {\n    return f(f(x)); // twice\n};\n\n\ntwice(fn(x) { x + 2 }, 2);",
+            "f(a, // moved\n  b);\n\n// last\nlet x = -(1 + 2) * !true;",
+            "let s = \"multi\nline\"; // after a string\nlet t = s;",
+            "let long = fn(first, second, third) { first + second * third - first / second + third * first };",
+            "puts(reduce(map(numbers, fn(n) { n * n }), 0, fn(total, n) { total + n }), \"squares\", \"and\", \"more\");",
+        ];
+        let configs = vec![Config::default(), Config { indent: 2, width: 30 }, Config { indent: 8, width: 0 }];
+        for config in &configs {
+            for input in &inputs {
+                let once = format(input, config)?;
+                assert_eq!(format(&once, config)?, once, "{:?} {}", config, input);
+                assert_eq!(strip(parse(&once)?), strip(parse(input)?), "{}", once);
+            }
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn idempotent_generated(program in program(), indent in 1..9usize, width in 0..120usize) {
+            let config = Config { indent, width };
+            let once = format(&printer::print(&program), &config).map_err(|err| TestCaseError::fail(err.to_string()))?;
+            let twice = format(&once, &config).map_err(|err| TestCaseError::fail(err.to_string()))?;
+            prop_assert_eq!(&twice, &once);
+            let parsed = parse(&once).map_err(|err| TestCaseError::fail(err.to_string()))?;
+            prop_assert_eq!(strip(parsed).statements, program.statements);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(format("let = 1;", &Config::default()).is_err());
+    }
+}
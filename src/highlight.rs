@@ -0,0 +1,130 @@
+use crate::lexer::Lexer;
+use crate::object::{Builtin, Object};
+use crate::token::{Kind, Token};
+
+use colored::*;
+
+/// highlight colours Monkey source for display in a terminal. The text is
+/// otherwise unchanged, including whitespace and characters that don't lex.
+///
+/// Colours follow the `colored` crate's global switch, so when colour is
+/// turned off the source comes back as is.
+pub fn highlight(source: &str) -> String {
+    let offsets = line_offsets(source);
+    let mut out = String::with_capacity(source.len());
+    let mut written = 0;
+    for token in Lexer::new(source.chars()).lossless() {
+        let start = match offset(source, &offsets, &token) {
+            Some(start) if start >= written => start,
+            _ => continue,
+        };
+        let end = source[start..]
+            .char_indices()
+            .nth(token.literal.chars().count())
+            .map_or(source.len(), |(ii, _)| start + ii);
+        out.push_str(&source[written..start]);
+        out.push_str(&paint(&token, &source[start..end]));
+        written = end;
+    }
+    out.push_str(&source[written..]);
+    out
+}
+
+/// object colours a value by its type, as the literal that would write it is
+/// coloured. Its text isn't read as source, so a string that looks like code
+/// is shown in one colour.
+pub fn object(object: &Object) -> String {
+    let text = object.to_string();
+    match object {
+        Object::Int(_) | Object::Boolean(_) | Object::String(_) => text.yellow().to_string(),
+        Object::Builtin(_) => text.green().to_string(),
+        _ => text,
+    }
+}
+
+fn paint(token: &Token, text: &str) -> String {
+    match token.kind {
+        Kind::Function | Kind::Let | Kind::Return | Kind::If | Kind::Else => text.magenta().bold().to_string(),
+        Kind::Int | Kind::Bool | Kind::String => text.yellow().to_string(),
+        Kind::Ident if Builtin::lookup(&token.literal).is_some() => text.green().to_string(),
+        Kind::Ident => text.blue().to_string(),
+        Kind::Assign | Kind::Plus | Kind::Minus | Kind::Bang | Kind::Slash | Kind::Asterisk
+        | Kind::ArrowLeft | Kind::ArrowRight | Kind::Equal | Kind::NotEqual => text.cyan().to_string(),
+        Kind::Comment => text.bright_black().to_string(),
+        Kind::Illegal => text.red().to_string(),
+        _ => text.to_string(),
+    }
+}
+
+// line_offsets is the byte offset at which each line starts.
+fn line_offsets(source: &str) -> Vec<usize> {
+    let mut offsets = vec![0];
+    offsets.extend(source.match_indices('\n').map(|(ii, _)| ii + 1));
+    offsets
+}
+
+// offset converts the token's line and column into a byte offset.
+fn offset(source: &str, offsets: &[usize], token: &Token) -> Option<usize> {
+    let line = *offsets.get(token.position.line.checked_sub(1)?)?;
+    let column = token.position.column.checked_sub(1)?;
+    source[line..]
+        .char_indices()
+        .nth(column)
+        .map(|(ii, _)| line + ii)
+}
+
+/// strip_ansi removes terminal colour codes, leaving the plain text.
+#[cfg(test)]
+pub fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut escape = false;
+    for ch in s.chars() {
+        match ch {
+            '\u{1b}' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => {},
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn painted() {
+        let got = highlight("let x = len(ü) +\n  10;");
+        let want = format!(
+            "{} {} {} {}({}) {}\n  {};",
+            "let".magenta().bold(),
+            "x".blue(),
+            "=".cyan(),
+            "len".green(),
+            "ü".blue(),
+            "+".cyan(),
+            "10".yellow(),
+        );
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn objects() {
+        let tests = vec![
+            (Object::String("let x = \"1\"; // not code".into()), "let x = \"1\"; // not code".yellow().to_string()),
+            (Object::Int(-1), "-1".yellow().to_string()),
+            (Object::Builtin(Builtin::Len), "builtin len".green().to_string()),
+            (Object::Null, "null".to_string()),
+        ];
+        for (value, want) in tests {
+            assert_eq!(object(&value), want, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn unchanged_text() {
+        let input = "let  f = fn(a) { // add one\n\ta + @ 1 };\n";
+        assert_eq!(strip_ansi(&highlight(input)), input);
+    }
+}
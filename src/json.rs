@@ -0,0 +1,332 @@
+use crate::ast::Program;
+use crate::lexer;
+use crate::token::{Token, Position, Span};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+type Error = Box<dyn std::error::Error>;
+
+/// VERSION is the version of the JSON schema written by this module. It
+/// changes whenever the encoding of a token or node changes in a way that
+/// older readers would misread.
+pub const VERSION: u32 = 2;
+
+// Every document is an object with a `version` field alongside its contents.
+// Tokens and nodes are objects with a `position` holding a 1-based `line` and
+// `column`; nodes are tagged with their `type` (`Let`, `Infix`, `Call`, ...),
+// and an expression statement is the bare expression. Statements and blocks
+// also have the `span` of source they cover, with a `start` and `end`
+// position.
+
+// TYPES are the tags that nodes are written with.
+const TYPES: [&str; 11] = [
+    "Let", "Return", "Identifier", "Int", "String", "Boolean", "Prefix", "Infix", "If", "Function", "Call",
+];
+
+// CHILDREN are the fields holding the nodes inside a node.
+const CHILDREN: [&str; 10] = [
+    "predicate", "success", "fail", "left", "right", "function", "arguments", "value", "body", "statements",
+];
+
+#[derive(Serialize)]
+struct Tokens<'a> {
+    version: u32,
+    tokens: &'a [Token],
+}
+
+#[derive(Serialize)]
+struct Tree {
+    version: u32,
+    program: Value,
+}
+
+// Header is read first so that a document from another version of the schema
+// is reported as such rather than as whatever it fails to decode as.
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Decoded {
+    program: Value,
+}
+
+/// encode_tokens writes the tokens as a JSON document.
+pub fn encode_tokens(tokens: &[Token]) -> Result<String, Error> {
+    Ok(serde_json::to_string(&Tokens { version: VERSION, tokens })?)
+}
+
+/// encode_program writes the program as a JSON document, giving each
+/// statement and block the span from `spans` that starts where it does.
+/// Nodes with no such span are written without one, so a program that was
+/// built by hand or rearranged never borrows another node's span. A
+/// statement opening with a parenthesis starts before any of its nodes, and
+/// so has no span either.
+pub fn encode_program(program: &Program, spans: &[Span]) -> Result<String, Error> {
+    let mut tree = serde_json::to_value(program)?;
+    if let Some(Value::Array(statements)) = tree.get_mut("statements") {
+        for stmt in statements {
+            annotate(stmt, spans, true)?;
+        }
+    }
+    Ok(serde_json::to_string(&Tree { version: VERSION, program: tree })?)
+}
+
+// annotate adds its span to `node` if it is a statement or block, and then to
+// the nodes inside it.
+fn annotate(node: &mut Value, spans: &[Span], statement: bool) -> Result<(), Error> {
+    if statement || node.get("statements").is_some() {
+        let span = start(node).and_then(|start| spans.iter().find(|span| span.start == start));
+        if let (Some(span), Value::Object(object)) = (span, &mut *node) {
+            object.insert("span".into(), serde_json::to_value(span)?);
+        }
+    }
+    let object = match node {
+        Value::Object(object) => object,
+        _ => return Ok(()),
+    };
+    for field in CHILDREN.iter() {
+        match object.get_mut(*field) {
+            Some(Value::Array(nodes)) => {
+                for child in nodes {
+                    annotate(child, spans, *field == "statements")?;
+                }
+            },
+            Some(child) => annotate(child, spans, false)?,
+            None => {},
+        }
+    }
+    Ok(())
+}
+
+// start finds the position of the first token of `node`, which for an infix
+// expression or a call is the first token of its left operand or function.
+fn start(node: &Value) -> Option<Position> {
+    match node.get("left").or_else(|| node.get("function")) {
+        Some(first) => start(first),
+        None => serde_json::from_value(node.get("position")?.clone()).ok(),
+    }
+}
+
+/// decode_program rebuilds a program from a document written by
+/// `encode_program`. Spans are ignored and positions may be left out, so
+/// tools can build documents by hand. Nodes of an unknown type and names that
+/// aren't identifiers are reported with the path to them, such as
+/// `program.statements[0].value`.
+pub fn decode_program(json: &str) -> Result<Program, Error> {
+    let header: Header = serde_json::from_str(json)?;
+    if header.version != VERSION {
+        return Err(format!("unsupported schema version {}, want {}", header.version, VERSION).into());
+    }
+    let decoded: Decoded = serde_json::from_str(json)?;
+    check(&decoded.program, "program")?;
+    Ok(serde_json::from_value(decoded.program)?)
+}
+
+// check looks for what serde would only report as a mismatch somewhere in a
+// statement: a node `type` that doesn't exist, or a `name` that the lexer
+// wouldn't read as an identifier.
+fn check(node: &Value, path: &str) -> Result<(), Error> {
+    match node {
+        Value::Object(object) => {
+            if let Some(kind) = object.get("type") {
+                if !kind.as_str().is_some_and(|kind| TYPES.contains(&kind)) {
+                    return Err(format!("{}: unknown node type {}", path, kind).into());
+                }
+            }
+            if let Some(Value::String(name)) = object.get("name") {
+                if !lexer::is_identifier(name) {
+                    return Err(format!("{}: {:?} is not an identifier", path, name).into());
+                }
+            }
+            for (field, child) in object {
+                check(child, &format!("{}.{}", path, field))?;
+            }
+        },
+        Value::Array(nodes) => {
+            for (ii, child) in nodes.iter().enumerate() {
+                check(child, &format!("{}[{}]", path, ii))?;
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Statement, Expression, Identifier, IntegerLiteral, CallExpression, Infix, InfixExpression, LetStatement};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::printer::{self, arbitrary::program};
+    use proptest::prelude::*;
+
+    use std::sync::Arc;
+
+    fn parse(input: &str) -> Result<(Program, Vec<Span>), Error> {
+        let mut parser = Parser::new(Lexer::new(input.chars()));
+        let program = parser.parse()?;
+        Ok((program, parser.spans().to_vec()))
+    }
+
+    #[test]
+    fn schema() -> Result<(), Error> {
+        let (program, spans) = parse("let x = -1;\nf(x)")?;
+        let want = concat!(
+            r#"{"version":2,"program":{"statements":["#,
+            r#"{"type":"Let","name":{"name":"x","position":{"line":1,"column":5}},"#,
+            r#""value":{"type":"Prefix","operator":"Negative","#,
+            r#""value":{"type":"Int","value":1,"position":{"line":1,"column":10}},"position":{"line":1,"column":9}},"#,
+            r#""position":{"line":1,"column":1},"#,
+            r#""span":{"start":{"line":1,"column":1},"end":{"line":1,"column":11}}},"#,
+            r#"{"type":"Call","function":{"type":"Identifier","name":"f","position":{"line":2,"column":1}},"#,
+            r#""arguments":[{"type":"Identifier","name":"x","position":{"line":2,"column":3}}],"#,
+            r#""position":{"line":2,"column":2},"#,
+            r#""span":{"start":{"line":2,"column":1},"end":{"line":2,"column":4}}}]}}"#,
+        );
+        assert_eq!(encode_program(&program, &spans)?, want);
+        Ok(())
+    }
+
+    // Nested statements and blocks get their spans too.
+    #[test]
+    fn spans() -> Result<(), Error> {
+        let (program, spans) = parse("let f = fn(x) { if (x) { 1 } else { 2 } };")?;
+        let tree: Value = serde_json::from_str(&encode_program(&program, &spans)?)?;
+        let columns = |node: &Value| (node["span"]["start"]["column"].as_u64(), node["span"]["end"]["column"].as_u64());
+        let stmt = &tree["program"]["statements"][0];
+        let body = &stmt["value"]["body"];
+        let condition = &body["statements"][0];
+        let tests = vec![
+            (stmt, (1, 42)),
+            (body, (15, 41)),
+            (condition, (17, 39)),
+            (&condition["success"], (24, 28)),
+            (&condition["success"]["statements"][0], (26, 26)),
+            (&condition["fail"], (35, 39)),
+            (&condition["fail"]["statements"][0], (37, 37)),
+        ];
+        for (node, (start, end)) in tests {
+            assert_eq!(columns(node), (Some(start), Some(end)), "{}", node);
+        }
+        assert!(condition["predicate"].get("span").is_none());
+        Ok(())
+    }
+
+    // Spans follow the nodes they belong to when the program is rearranged,
+    // and nodes that start where no span does get none.
+    #[test]
+    fn rearranged() -> Result<(), Error> {
+        let (mut program, spans) = parse("let a = 1;\nlet f = fn() { a; 2 };\n(a + 1) * 2;\nf()")?;
+        program.statements.reverse();
+        if let Statement::Let(stmt) = &mut program.statements[2] {
+            if let Expression::Function(function) = &mut stmt.value {
+                Arc::make_mut(&mut function.body).statements.reverse();
+            }
+        }
+        let tree: Value = serde_json::from_str(&encode_program(&program, &spans)?)?;
+        let span = |node: &Value| match &node["span"] {
+            Value::Null => None,
+            span => Some([&span["start"]["line"], &span["start"]["column"], &span["end"]["line"], &span["end"]["column"]].map(|n| n.as_u64().unwrap())),
+        };
+        let statements = &tree["program"]["statements"];
+        let body = &statements[2]["value"]["body"];
+        let tests = vec![
+            (&statements[0], Some([4, 1, 4, 3])),
+            (&statements[1], None),
+            (&statements[2], Some([2, 1, 2, 22])),
+            (body, Some([2, 14, 2, 21])),
+            (&body["statements"][0], Some([2, 19, 2, 19])),
+            (&body["statements"][1], Some([2, 16, 2, 17])),
+            (&statements[3], Some([1, 1, 1, 10])),
+        ];
+        for (node, want) in tests {
+            assert_eq!(span(node), want, "{}", node);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tokens() -> Result<(), Error> {
+        let tokens: Vec<Token> = Lexer::new("x + 1".chars()).collect();
+        let want = concat!(
+            r#"{"version":2,"tokens":["#,
+            r#"{"kind":"Ident","literal":"x","position":{"line":1,"column":1}},"#,
+            r#"{"kind":"Plus","literal":"+","position":{"line":1,"column":3}},"#,
+            r#"{"kind":"Int","literal":"1","position":{"line":1,"column":5}}]}"#,
+        );
+        assert_eq!(encode_tokens(&tokens)?, want);
+        Ok(())
+    }
+
+    // Documents written by hand need no positions or spans.
+    #[test]
+    fn decode() -> Result<(), Error> {
+        let json = r#"{"version": 2, "program": {"statements": [
+            {"type": "Let", "name": {"name": "y"}, "value": {"type": "Infix",
+                "left": {"type": "Int", "value": 2}, "operator": "Multiply", "right": {"type": "Int", "value": 3}}},
+            {"type": "Call", "function": {"type": "Identifier", "name": "puts"}, "arguments": [{"type": "Identifier", "name": "y"}]}
+        ]}}"#;
+        let want: Vec<Statement> = vec![
+            LetStatement::new("y", InfixExpression::new(IntegerLiteral::new(2), Infix::Multiply, IntegerLiteral::new(3))).into(),
+            CallExpression::new(Identifier::new("puts"), vec![Identifier::new("y").into()]).into(),
+        ];
+        let program = decode_program(json)?;
+        assert_eq!(program.statements, want);
+        assert_eq!(printer::print(&program), "let y = 2 * 3;\nputs(y);\n");
+        Ok(())
+    }
+
+    #[test]
+    fn decode_errors() {
+        let tests = vec![
+            (r#"{"version": 1, "program": {"statements": []}}"#, "unsupported schema version 1, want 2"),
+            (r#"{"program": {"statements": []}}"#, "missing field `version` at line 1 column 31"),
+            (r#"{"version": 2, "program": {"statements": [{"type": "Loop"}]}}"#, "program.statements[0]: unknown node type \"Loop\""),
+            (
+                r#"{"version": 2, "program": {"statements": [{"type": "Let", "name": {"name": "x"}, "value": {"type": "Prefix", "operator": "Not", "value": {"type": "Bool"}}}]}}"#,
+                "program.statements[0].value.value: unknown node type \"Bool\"",
+            ),
+            (
+                r#"{"version": 2, "program": {"statements": [{"type": "Let", "name": {"name": "two words"}, "value": {"type": "Int", "value": 1}}]}}"#,
+                "program.statements[0].name: \"two words\" is not an identifier",
+            ),
+            (
+                r#"{"version": 2, "program": {"statements": [{"type": "Function", "parameters": [{"name": "if"}], "body": {"statements": []}}]}}"#,
+                "program.statements[0].parameters[0]: \"if\" is not an identifier",
+            ),
+        ];
+        for (input, want) in tests {
+            match decode_program(input) {
+                Ok(program) => panic!("{}: expected error, got {:?}", input, program),
+                Err(err) => assert_eq!(err.to_string(), want, "{}", input),
+            }
+        }
+    }
+
+    // Decoding keeps positions.
+    #[test]
+    fn positions() -> Result<(), Error> {
+        let (program, spans) = parse("\n  answer")?;
+        let decoded = decode_program(&encode_program(&program, &spans)?)?;
+        match decoded.statements.as_slice() {
+            [Statement::Expression(Expression::Identifier(identifier))] => {
+                assert_eq!((identifier.position.line, identifier.position.column), (2, 3));
+            },
+            other => panic!("unexpected statements: {:?}", other),
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(program in program()) {
+            let json = encode_program(&program, &[]).map_err(|err| TestCaseError::fail(err.to_string()))?;
+            let decoded = decode_program(&json).map_err(|err| TestCaseError::fail(format!("{}\n{}", err, json)))?;
+            prop_assert_eq!(decoded, program);
+        }
+    }
+}
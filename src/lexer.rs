@@ -1,8 +1,11 @@
 #![allow(dead_code)]
-use crate::token::{Token, Kind};
+use crate::token::{Token, Kind, Position};
 
 use std::iter::Peekable;
 
+/// KEYWORDS are the words that `Token::ident` does not treat as identifiers.
+pub const KEYWORDS: [&str; 7] = ["fn", "let", "return", "if", "else", "true", "false"];
+
 impl Token {
     /// ident constructs the appropriate Token for the given multi-character
     /// word. 
@@ -15,7 +18,7 @@ impl Token {
             "else" => Token::new(Kind::Else, word),
             "true" => Token::new(Kind::Bool, word),
             "false" => Token::new(Kind::Bool, word),
-            word if word.parse::<i32>().is_ok() => {
+            word if word.chars().all(|c| c.is_ascii_digit()) => {
                 Token::new(Kind::Int, word)
             }
             _ => {
@@ -30,6 +33,9 @@ pub struct Lexer<I>
 {
     input: Peekable<I>,
     ch: char,
+    // position of `ch` in the input.
+    position: Position,
+    comments: bool,
 }
 
 impl<I> Lexer<I>
@@ -39,31 +45,66 @@ impl<I> Lexer<I>
         Lexer {
             input: input.peekable(),
             ch: '\0',
+            position: Position::new(1, 0),
+            comments: false,
         }
     }
 
+    /// lossless makes the lexer produce comment tokens rather than skipping
+    /// them. Along with token positions, which show where the blank lines
+    /// are, this is enough to recover the layout of the source.
+    pub fn lossless(mut self) -> Lexer<I> {
+        self.comments = true;
+        self
+    }
+
     fn read<P>(&mut self, predicate: P) -> Token
         where P: Fn(&char) -> bool
     {
         let mut ident = self.ch.to_string();
-        loop {
-            let ch = match self.input.peek() {
-                Some(ch) => ch,
-                None => return Token::new(Kind::Eof, "\0"),
-            }; 
-            if predicate(ch) {
-                self.ch = match self.input.next() {
-                    Some(ch) => ch,
-                    None => return Token::new(Kind::Eof, "\0"),
-                };
-                ident.push(self.ch);
-            } else {
+        while let Some(ch) = self.input.peek() {
+            if !predicate(ch) {
                 break;
             }
+            self.advance();
+            ident.push(self.ch);
         }
         Token::ident(ident)
     }
 
+    // read_string reads a string literal, quotes and escapes included, up to
+    // the closing quote. A string that is never closed is illegal.
+    fn read_string(&mut self) -> Token {
+        let mut literal = self.ch.to_string();
+        while self.input.peek().is_some() {
+            self.advance();
+            literal.push(self.ch);
+            match self.ch {
+                '\\' if self.input.peek().is_some() => {
+                    self.advance();
+                    literal.push(self.ch);
+                },
+                '"' => return Token::new(Kind::String, literal),
+                _ => {},
+            }
+        }
+        Token::new(Kind::Illegal, literal)
+    }
+
+    // read_comment reads a comment up to, but not including, the end of the
+    // line.
+    fn read_comment(&mut self) -> Token {
+        let mut literal = self.ch.to_string();
+        while let Some(&ch) = self.input.peek() {
+            if ch == '\n' {
+                break;
+            }
+            self.advance();
+            literal.push(self.ch);
+        }
+        Token::new(Kind::Comment, literal)
+    }
+
     fn eat_space(&mut self) {
         while self.ch.is_whitespace() {
             self.advance();
@@ -71,10 +112,12 @@ impl<I> Lexer<I>
     }
 
     fn advance(&mut self) {
-        self.ch = match self.input.next() {
-            Some(ch) => ch,
-            None => '\0',
-        };
+        if self.ch == '\n' {
+            self.position.line += 1;
+            self.position.column = 0;
+        }
+        self.ch = self.input.next().unwrap_or('\0');
+        self.position.column += 1;
     }
 }
 
@@ -84,8 +127,22 @@ impl<I> Iterator for Lexer<I>
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let token = self.scan()?;
+            if token.kind != Kind::Comment || self.comments {
+                return Some(token);
+            }
+        }
+    }
+}
+
+impl<I> Lexer<I>
+    where I: Iterator<Item=char>,
+{
+    fn scan(&mut self) -> Option<Token> {
         self.advance();
         self.eat_space();
+        let position = self.position;
         let tok = match self.ch {
             '+' => Token::new(Kind::Plus, "+"),
             '(' => Token::new(Kind::LeftParen, "("),
@@ -95,10 +152,12 @@ impl<I> Iterator for Lexer<I>
             ',' => Token::new(Kind::Comma, ","),
             ';' => Token::new(Kind::Semicolon, ";"),
             '-' => Token::new(Kind::Minus, "-"),
+            '/' if self.input.peek() == Some(&'/') => self.read_comment(),
             '/' => Token::new(Kind::Slash, "/"),
             '<' => Token::new(Kind::ArrowLeft, "<"),
             '>' => Token::new(Kind::ArrowRight, ">"),
             '*' => Token::new(Kind::Asterisk, "*"),
+            '"' => self.read_string(),
             '\0' => return None,
             '=' => {
                 match self.input.peek() {
@@ -129,20 +188,65 @@ impl<I> Iterator for Lexer<I>
             _ => {
                 if self.ch.is_alphabetic() {
                     self.read(|c: &char| c.is_alphabetic())
-                } else if self.ch.is_numeric() {
-                    self.read(|c: &char| c.is_numeric())
+                } else if self.ch.is_ascii_digit() {
+                    self.read(|c: &char| c.is_ascii_digit())
                 } else {
                     Token::new(Kind::Illegal, self.ch.to_string())
                 }
             }
         };
-        Some(tok)
+        Some(tok.at(position))
+    }
+}
+
+/// unescape returns the text of a string literal, as read by the lexer,
+/// without its quotes and with escape sequences replaced.
+pub fn unescape(literal: &str) -> Result<String, String> {
+    let inner = literal
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("not a string literal: {}", literal))?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some(other) => return Err(format!("unknown escape: \\{}", other)),
+            None => return Err("unterminated escape".into()),
+        }
+    }
+    Ok(out)
+}
+
+/// is_identifier reports whether the lexer reads `name`, as it is, as a
+/// single identifier rather than a keyword, a number or several tokens.
+#[cfg(any(test, feature = "json"))]
+pub fn is_identifier(name: &str) -> bool {
+    let mut tokens = Lexer::new(name.chars());
+    match (tokens.next(), tokens.next()) {
+        (Some(token), None) => token.kind == Kind::Ident && token.literal == name,
+        _ => false,
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    // strip forgets where each token was found, so tokens compare by kind
+    // and literal alone.
+    fn strip<I: Iterator<Item = Token>>(tokens: I) -> Vec<Token> {
+        tokens.map(|token| token.at(Position::default())).collect()
+    }
+
     #[test]
     fn tokens() {
         let input: &'static str = r#"
@@ -253,7 +357,96 @@ mod tests {
             Token::new(Kind::Int, "10"),
             Token::new(Kind::Semicolon, ";"),
         ];
-        let got: Vec<Token> = Lexer::new(input.chars()).collect();
+        let got = strip(Lexer::new(input.chars()));
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn strings() {
+        let input = r#"let s = "say \"hi\"\n"; "" "open"#;
+        let want = vec![
+            Token::new(Kind::Let, "let"),
+            Token::new(Kind::Ident, "s"),
+            Token::new(Kind::Assign, "="),
+            Token::new(Kind::String, r#""say \"hi\"\n""#),
+            Token::new(Kind::Semicolon, ";"),
+            Token::new(Kind::String, r#""""#),
+            Token::new(Kind::Illegal, r#""open"#),
+        ];
+        let got = strip(Lexer::new(input.chars()));
+        assert_eq!(want, got);
+        assert_eq!(unescape(r#""say \"hi\"\n""#), Ok("say \"hi\"\n".to_string()));
+        assert_eq!(unescape(r#""tab\t\\""#), Ok("tab\t\\".to_string()));
+        assert_eq!(unescape(r#""\q""#), Err("unknown escape: \\q".to_string()));
+    }
+
+    #[test]
+    fn comments() {
+        let input = "// one\nx / y; // two\n//";
+        let lossless: Vec<Token> = Lexer::new(input.chars()).lossless().collect();
+        let want = vec![
+            Token::new(Kind::Comment, "// one"),
+            Token::new(Kind::Ident, "x"),
+            Token::new(Kind::Slash, "/"),
+            Token::new(Kind::Ident, "y"),
+            Token::new(Kind::Semicolon, ";"),
+            Token::new(Kind::Comment, "// two"),
+            Token::new(Kind::Comment, "//"),
+        ];
+        assert_eq!(want, strip(lossless.iter().cloned()));
+        assert_eq!((lossless[5].position.line, lossless[5].position.column), (2, 8));
+        let skipped = strip(Lexer::new(input.chars()));
+        let want: Vec<Token> = want.into_iter().filter(|t| t.kind != Kind::Comment).collect();
+        assert_eq!(want, skipped);
+    }
+
+    #[test]
+    fn large_integers() {
+        let got = strip(Lexer::new("2147483648 99999999999999999999".chars()));
+        assert_eq!(got[0], Token::new(Kind::Int, "2147483648"));
+        assert_eq!(got[1], Token::new(Kind::Int, "99999999999999999999"));
+    }
+
+    // Only ASCII digits make integers; other numerals are illegal.
+    #[test]
+    fn digits() {
+        let got = strip(Lexer::new("٣ 3٣ ²".chars()));
+        let want = vec![
+            Token::new(Kind::Illegal, "٣"),
+            Token::new(Kind::Int, "3"),
+            Token::new(Kind::Illegal, "٣"),
+            Token::new(Kind::Illegal, "²"),
+        ];
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn keywords() {
+        for word in KEYWORDS.iter() {
+            assert_ne!(Token::ident(word.to_string()).kind, Kind::Ident, "{}", word);
+            assert!(!is_identifier(word), "{}", word);
+        }
+        for (name, want) in [("héllo", true), ("", false), ("x1", false), ("a b", false), (" a", false), ("1", false)] {
+            assert_eq!(is_identifier(name), want, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn positions() {
+        let input = "let x = 10;\n  x +\ny";
+        let want = vec![
+            (Kind::Let, 1, 1),
+            (Kind::Ident, 1, 5),
+            (Kind::Assign, 1, 7),
+            (Kind::Int, 1, 9),
+            (Kind::Semicolon, 1, 11),
+            (Kind::Ident, 2, 3),
+            (Kind::Plus, 2, 5),
+            (Kind::Ident, 3, 1),
+        ];
+        let got: Vec<(Kind, usize, usize)> = Lexer::new(input.chars())
+            .map(|t| (t.kind, t.position.line, t.position.column))
+            .collect();
         assert_eq!(want, got);
     }
 }
\ No newline at end of file
@@ -4,5 +4,24 @@ mod token;
 mod lexer;
 mod ast;
 mod parser;
+mod object;
+mod evaluator;
+mod tree;
+mod dot;
+mod printer;
+mod formatter;
+mod visit;
+mod optimizer;
+mod resolver;
+mod lint;
+mod codes;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "json")]
+pub mod lsp;
 mod repl;
-mod util;
\ No newline at end of file
+mod editor;
+mod highlight;
+mod server;
+mod cli;
+mod util;
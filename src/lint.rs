@@ -0,0 +1,425 @@
+use crate::ast::{
+    Program, Statement, BlockStatement, Expression, Identifier, InfixExpression,
+    IfExpression, FunctionLiteral, Infix,
+};
+use crate::lexer::Lexer;
+use crate::object::Builtin;
+use crate::optimizer;
+use crate::parser::Parser;
+use crate::printer;
+use crate::resolver;
+use crate::token::{Kind, Position, Span};
+use crate::util::{Diagnostic, Diagnostics, Label};
+use crate::visit::{self, Strip, Visitor, VisitMut};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+/// Rule is a kind of suspicious code that the linter looks for. Each can be
+/// turned off on its own.
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
+pub enum Rule {
+    // A let or parameter inside a function that is never used.
+    UnusedVariable,
+    // A name bound again inside a function, hiding an outer binding.
+    ShadowedName,
+    // A statement after a return in the same block.
+    UnreachableCode,
+    // A comparison whose sides are the same expression.
+    SelfComparison,
+    // An if whose condition doesn't depend on anything.
+    ConstantCondition,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::UnusedVariable,
+        Rule::ShadowedName,
+        Rule::UnreachableCode,
+        Rule::SelfComparison,
+        Rule::ConstantCondition,
+    ];
+
+    pub fn lookup(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().cloned().find(|rule| rule.name() == name)
+    }
+
+    /// code is the diagnostic code of the warnings for this rule.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "W0002",
+            Rule::ShadowedName => "W0003",
+            Rule::UnreachableCode => "W0004",
+            Rule::SelfComparison => "W0005",
+            Rule::ConstantCondition => "W0006",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused-variable",
+            Rule::ShadowedName => "shadowed-name",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::SelfComparison => "self-comparison",
+            Rule::ConstantCondition => "constant-condition",
+        }
+    }
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Config selects the rules that `lint` checks.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Config {
+    pub disabled: HashSet<Rule>,
+}
+
+/// lint parses `source` and returns the warnings for it, along with those
+/// from the parser, in source order. Source that doesn't parse gives its
+/// errors instead.
+///
+/// Rules can also be turned off by comments: `// lint: disable <rule>, ...`
+/// turns them off for the whole file, and `// lint: disable-next-line <rule>, ...`
+/// for the line after the comment.
+//
+// Names bound at the top level are globals, which code loaded later may use,
+// so only those bound inside functions are reported as unused.
+//
+pub fn lint(source: &str, config: &Config) -> Diagnostics {
+    let mut parser = Parser::new(Lexer::new(source.chars()));
+    let program = match parser.parse() {
+        Ok(program) => program,
+        Err(errors) => return errors,
+    };
+    let directives = match directives(source) {
+        Ok(directives) => directives,
+        Err(errors) => return errors,
+    };
+    let mut linter = Linter { scopes: vec![], warnings: vec![] };
+    linter.visit_program(&program);
+    let mut warnings = parser.warnings().clone();
+    for (rule, warning) in linter.warnings {
+        if !config.disabled.contains(&rule) && !directives.disables(rule, &warning) {
+            warnings.push(warning);
+        }
+    }
+    warnings.sort();
+    warnings
+}
+
+// Directives are the rules turned off by comments, for the file or for a line.
+#[derive(Default)]
+struct Directives {
+    file: HashSet<Rule>,
+    lines: HashMap<usize, HashSet<Rule>>,
+}
+
+impl Directives {
+    fn disables(&self, rule: Rule, warning: &Diagnostic) -> bool {
+        let line = warning.position().map(|position| position.line);
+        self.file.contains(&rule)
+            || line.and_then(|line| self.lines.get(&line)).is_some_and(|rules| rules.contains(&rule))
+    }
+}
+
+fn directives(source: &str) -> Result<Directives, Diagnostics> {
+    let mut directives = Directives::default();
+    let mut errors = Diagnostics::new();
+    for token in Lexer::new(source.chars()).lossless() {
+        if token.kind != Kind::Comment {
+            continue;
+        }
+        let directive = match token.literal.trim_start_matches('/').trim().strip_prefix("lint:") {
+            Some(directive) => directive.trim(),
+            None => continue,
+        };
+        let (rules, next_line) = if let Some(rules) = directive.strip_prefix("disable-next-line ") {
+            (rules, true)
+        } else if let Some(rules) = directive.strip_prefix("disable ") {
+            (rules, false)
+        } else {
+            errors.push(Diagnostic::error("E0007", format!("unknown lint directive: {}", directive)).at(token.position));
+            continue;
+        };
+        for name in rules.split(',').map(str::trim) {
+            let rule = match Rule::lookup(name) {
+                Some(rule) => rule,
+                None => {
+                    errors.push(Diagnostic::error("E0007", format!("unknown lint rule: {}", name)).at(token.position));
+                    continue;
+                },
+            };
+            if next_line {
+                directives.lines.entry(token.position.line + 1).or_default().insert(rule);
+            } else {
+                directives.file.insert(rule);
+            }
+        }
+    }
+    if !errors.is_empty() {
+        Err(errors)
+    } else {
+        Ok(directives)
+    }
+}
+
+struct Linter {
+    // scopes holds the names bound by the program and by each function being
+    // linted, innermost last.
+    scopes: Vec<Scope>,
+    warnings: Vec<(Rule, Diagnostic)>,
+}
+
+#[derive(Default)]
+struct Scope {
+    names: HashMap<String, Binding>,
+}
+
+struct Binding {
+    position: Position,
+    parameter: bool,
+    used: bool,
+}
+
+impl Linter {
+    fn warn(&mut self, rule: Rule, position: Position, message: String) -> &mut Diagnostic {
+        let warning = Diagnostic::warning(rule.code(), message)
+            .at(position)
+            .help(format!("`// lint: disable-next-line {}` on the line before allows this", rule));
+        self.warnings.push((rule, warning));
+        let last = self.warnings.len() - 1;
+        &mut self.warnings[last].1
+    }
+
+    // bind adds a name to the innermost scope, warning if it hides an outer
+    // binding or a builtin. Binding a name again in the same scope reuses it.
+    fn bind(&mut self, name: &Identifier, parameter: bool) {
+        let scope = self.scopes.len() - 1;
+        if self.scopes[scope].names.contains_key(&name.name) {
+            return;
+        }
+        let outer = self.scopes[..scope].iter().rev().find_map(|outer| outer.names.get(&name.name));
+        if let Some(outer) = outer {
+            let span = Span { start: outer.position, end: outer.position };
+            let label = Label { span, message: "the outer binding".into(), primary: false };
+            self.warn(Rule::ShadowedName, name.position, format!("{} shadows an outer binding", name.name)).labels.push(label);
+        } else if Builtin::lookup(&name.name).is_some() {
+            self.warn(Rule::ShadowedName, name.position, format!("{} shadows the builtin of the same name", name.name));
+        }
+        let binding = Binding { position: name.position, parameter, used: false };
+        self.scopes[scope].names.insert(name.name.clone(), binding);
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        // Only the first unreachable statement is reported.
+        let (mut returned, mut reported) = (false, false);
+        for stmt in statements {
+            if returned && !reported {
+                let position = match stmt {
+                    Statement::Expression(exp) => start(exp),
+                    stmt => stmt.position(),
+                };
+                self.warn(Rule::UnreachableCode, position, "unreachable code after return".into());
+                reported = true;
+            }
+            returned |= matches!(stmt, Statement::Return(_));
+            self.visit_statement(stmt);
+        }
+    }
+}
+
+impl Visitor for Linter {
+    fn visit_program(&mut self, program: &Program) {
+        self.scopes.push(Scope::default());
+        for name in resolver::lets(&program.statements) {
+            self.bind(&name, false);
+        }
+        self.statements(&program.statements);
+        self.scopes.pop();
+    }
+
+    fn visit_block(&mut self, block: &BlockStatement) {
+        self.statements(&block.statements);
+    }
+
+    fn visit_identifier(&mut self, identifier: &Identifier) {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.names.get_mut(&identifier.name) {
+                binding.used = true;
+                return;
+            }
+        }
+    }
+
+    fn visit_infix(&mut self, exp: &InfixExpression) {
+        let always = match exp.operator {
+            Infix::Eq => Some(true),
+            Infix::NotEq | Infix::LessThan | Infix::GreaterThan => Some(false),
+            _ => None,
+        };
+        if let Some(always) = always {
+            if same(&exp.left, &exp.right) && pure(&exp.left) {
+                let message = format!("comparing {} to itself is always {}", printer::print_expression(&exp.left), always);
+                self.warn(Rule::SelfComparison, exp.position, message);
+            }
+        }
+        visit::walk_infix(self, exp);
+    }
+
+    fn visit_if(&mut self, exp: &IfExpression) {
+        if let Some(truthy) = constant(&exp.predicate) {
+            let message = format!("condition is always {}", truthy);
+            self.warn(Rule::ConstantCondition, start(&exp.predicate), message);
+        }
+        visit::walk_if(self, exp);
+    }
+
+    fn visit_function(&mut self, exp: &FunctionLiteral) {
+        self.scopes.push(Scope::default());
+        for parameter in &exp.parameters {
+            self.bind(parameter, true);
+        }
+        for name in resolver::lets(&exp.body.statements) {
+            self.bind(&name, false);
+        }
+        self.visit_block(&exp.body);
+        let scope = self.scopes.pop().unwrap_or_default();
+        for (name, binding) in scope.names {
+            if binding.used {
+                continue;
+            }
+            let kind = if binding.parameter { "parameter" } else { "variable" };
+            self.warn(Rule::UnusedVariable, binding.position, format!("unused {}: {}", kind, name));
+        }
+    }
+}
+
+// start finds where an expression begins, as operators and calls are
+// positioned at the operator or parenthesis rather than their first operand.
+fn start(exp: &Expression) -> Position {
+    match exp {
+        Expression::Infix(exp) => start(&exp.left),
+        Expression::Call(exp) => start(&exp.function),
+        exp => exp.position(),
+    }
+}
+
+// same reports whether two expressions are written alike, wherever they are
+// in the source.
+fn same(left: &Expression, right: &Expression) -> bool {
+    let (mut left, mut right) = (left.clone(), right.clone());
+    Strip.visit_expression_mut(&mut left);
+    Strip.visit_expression_mut(&mut right);
+    left == right
+}
+
+// pure reports whether evaluating the expression twice gives the same value.
+// Calls might not, and functions are new values each time.
+fn pure(exp: &Expression) -> bool {
+    match exp {
+        Expression::Identifier(_) | Expression::Int(_) | Expression::String(_) | Expression::Boolean(_) => true,
+        Expression::Prefix(exp) => pure(&exp.value),
+        Expression::Infix(exp) => pure(&exp.left) && pure(&exp.right),
+        Expression::If(_) | Expression::Function(_) | Expression::Call(_) => false,
+    }
+}
+
+// constant returns whether the condition is truthy, if the optimizer can work
+// that out without running anything.
+fn constant(predicate: &Expression) -> Option<bool> {
+    let folded = optimizer::optimize(Program::new(vec![predicate.clone().into()]));
+    match folded.statements.as_slice() {
+        [Statement::Expression(Expression::Boolean(b))] => Some(b.value),
+        [Statement::Expression(Expression::Int(_) | Expression::String(_))] => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Error = Box<dyn std::error::Error>;
+
+    fn warnings(source: &str, config: &Config) -> Result<Vec<String>, Error> {
+        let diagnostics = lint(source, config);
+        if diagnostics.has_errors() {
+            return Err(diagnostics.into());
+        }
+        Ok(diagnostics.into_iter().map(|warning| format!("{} [{}]", warning, warning.code)).collect())
+    }
+
+    #[test]
+    fn rules() -> Result<(), Error> {
+        let tests = vec![
+            ("let f = fn(a, b) { let c = 1; a };", vec![
+                "1:15: unused parameter: b [W0002]",
+                "1:24: unused variable: c [W0002]",
+            ]),
+            ("let unused = 1; let f = fn(a) { let b = a; b };", vec![]),
+            ("let x = 1; let f = fn(x) { let len = x; len };", vec![
+                "1:23: x shadows an outer binding [W0003]",
+                "1:32: len shadows the builtin of the same name [W0003]",
+            ]),
+            ("let f = fn(n) { fn() { let n = 1; n } };", vec![
+                "1:12: unused parameter: n [W0002]",
+                "1:28: n shadows an outer binding [W0003]",
+            ]),
+            ("let a = 1; a < 2 < 3", vec!["1:18: comparison operators don't chain [W0001]"]),
+            ("let f = fn() { return 1; puts(2); return 3 };", vec!["1:26: unreachable code after return [W0004]"]),
+            ("let x = 1; x == x; x < x; -x != -x; f() == f(); x == y", vec![
+                "1:14: comparing x to itself is always true [W0005]",
+                "1:22: comparing x to itself is always false [W0005]",
+                "1:30: comparing -x to itself is always false [W0005]",
+            ]),
+            ("if (true) { 1 }; if (1 > 2) { 1 }; if (\"\") { 1 }; if (x) { 1 }; if (1 / 0) { 1 }", vec![
+                "1:5: condition is always true [W0006]",
+                "1:22: condition is always false [W0006]",
+                "1:40: condition is always true [W0006]",
+            ]),
+        ];
+        for (input, want) in tests {
+            assert_eq!(warnings(input, &Config::default())?, want, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn disabled() -> Result<(), Error> {
+        let source = "\
+// lint: disable shadowed-name
+let f = fn(len, x) {
+    // lint: disable-next-line unused-variable, constant-condition
+    let y = if (true) { 1 };
+    let z = if (false) { 2 };
+    x
+};
+";
+        assert_eq!(warnings(source, &Config::default())?, vec![
+            "2:12: unused parameter: len [W0002]",
+            "5:9: unused variable: z [W0002]",
+            "5:17: condition is always false [W0006]",
+        ]);
+        let config = Config { disabled: HashSet::from([Rule::UnusedVariable, Rule::ConstantCondition]) };
+        assert!(warnings(source, &config)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let tests = vec![
+            ("let = 1;", "1:5: expected Ident, got Assign"),
+            ("// lint: disable no-such-rule\n1", "1:1: unknown lint rule: no-such-rule"),
+            ("1 // lint: enable self-comparison", "1:3: unknown lint directive: enable self-comparison"),
+        ];
+        for (input, want) in tests {
+            let diagnostics = lint(input, &Config::default());
+            assert!(diagnostics.has_errors(), "{}: expected error, got {:?}", input, diagnostics);
+            assert_eq!(diagnostics.iter().next().map(|err| err.to_string()), Some(want.to_string()), "{}", input);
+        }
+    }
+}
@@ -0,0 +1,905 @@
+use crate::ast::{Program, Statement, LetStatement, Expression, Identifier, FunctionLiteral, Binding};
+use crate::lexer::{Lexer, KEYWORDS};
+use crate::lint::{self, Config};
+use crate::object::{Builtin, Env};
+use crate::parser::Parser;
+use crate::resolver;
+use crate::token::{Kind, Position, Span};
+use crate::util::{Diagnostic, Severity};
+use crate::visit::{self, Visitor};
+
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+// Error codes defined by JSON-RPC and the protocol.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+// QUERIES are the requests about a document that the server answers.
+const QUERIES: [&str; 5] = [
+    "textDocument/documentSymbol",
+    "textDocument/definition",
+    "textDocument/references",
+    "textDocument/hover",
+    "textDocument/completion",
+];
+
+// Kinds of symbol and completion item, as numbered by the protocol.
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+
+// MAX_MESSAGE is the longest message body the server will read, in bytes.
+const MAX_MESSAGE: usize = 64 << 20;
+
+/// main serves the Language Server Protocol on stdin and stdout until the
+/// client says to exit. The status is non-zero if it exits without first
+/// asking the server to shut down, as the protocol requires.
+pub fn main() -> i32 {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut lsp = Lsp::new();
+    match lsp.run(&mut stdin.lock(), &mut stdout.lock()) {
+        Ok(()) if lsp.shutdown => 0,
+        Ok(()) => 1,
+        Err(err) => {
+            eprintln!("lsp: {}", err);
+            1
+        }
+    }
+}
+
+/// Lsp is a language server for Monkey. It keeps the text of every open
+/// document, synced in full on each change, and works everything out from
+/// the text when asked: diagnostics from the parser, the resolver and the
+/// linter, the top-level lets as symbols, definitions and references of
+/// names as the resolver binds them, hover and completion.
+pub struct Lsp {
+    documents: HashMap<String, String>,
+    initialized: bool,
+    shutdown: bool,
+}
+
+// Failure is an error response: a code and a message.
+type Failure = (i64, String);
+
+impl Lsp {
+    pub fn new() -> Lsp {
+        Lsp {
+            documents: HashMap::new(),
+            initialized: false,
+            shutdown: false,
+        }
+    }
+
+    /// run answers the messages read from `r`, writing responses and
+    /// notifications to `w`, until the input ends or the client says to
+    /// exit.
+    pub fn run(&mut self, r: &mut dyn BufRead, w: &mut dyn Write) -> io::Result<()> {
+        while let Some(body) = read(r)? {
+            let message: Value = match serde_json::from_slice(&body) {
+                Ok(message) => message,
+                Err(err) => {
+                    write(w, &failure(&Value::Null, (PARSE_ERROR, err.to_string())))?;
+                    continue;
+                },
+            };
+            let method = message["method"].as_str().unwrap_or_default();
+            let params = &message["params"];
+            match message.get("id") {
+                // Responses to requests from the server; it makes none.
+                Some(_) if method.is_empty() => {},
+                Some(id) => {
+                    let response = match self.request(method, params) {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err(err) => failure(id, err),
+                    };
+                    write(w, &response)?;
+                },
+                None if method == "exit" => return Ok(()),
+                // Until the client has initialized the server, every other
+                // notification is dropped.
+                None if !self.initialized => {},
+                None => {
+                    for notification in self.notify(method, params) {
+                        write(w, &notification)?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, Failure> {
+        if method == "initialize" {
+            self.initialized = true;
+            return Ok(capabilities());
+        }
+        if !self.initialized {
+            return Err((SERVER_NOT_INITIALIZED, "the server has not been initialized".into()));
+        }
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "the server is shutting down".into()));
+        }
+        if method == "shutdown" {
+            self.shutdown = true;
+            return Ok(Value::Null);
+        }
+        if !QUERIES.contains(&method) {
+            return Err((METHOD_NOT_FOUND, format!("unknown method: {}", method)));
+        }
+        let (uri, text) = self.document(params)?;
+        let position = Text::new(text).position(&params["position"]);
+        match method {
+            "textDocument/documentSymbol" => Ok(symbols(text)),
+            "textDocument/definition" => Ok(definition(uri, text, position?)),
+            "textDocument/references" => {
+                let declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                Ok(references(uri, text, position?, declaration))
+            },
+            "textDocument/hover" => Ok(hover(text, position?)),
+            "textDocument/completion" => Ok(completion(text, position?)),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+        }
+    }
+
+    // document finds the open document named by the request.
+    fn document<'a>(&'a self, params: &'a Value) -> Result<(&'a str, &'a str), Failure> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get(uri) {
+            Some(text) => Ok((uri, text)),
+            None => Err((INVALID_PARAMS, format!("unknown document: {}", uri))),
+        }
+    }
+
+    // notify handles a notification, returning any it sends in reply. Opening,
+    // changing or closing a document publishes its diagnostics.
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Changes hold the whole text, since that is how the server
+            // asked to be synced; only the last one matters.
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, vec![])];
+            },
+            _ => return vec![],
+        };
+        match text {
+            Some(text) => {
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![publish(&uri, diagnostics(&uri, text))]
+            },
+            None => vec![],
+        }
+    }
+}
+
+impl Default for Lsp {
+    fn default() -> Lsp {
+        Lsp::new()
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": 1,
+            "documentSymbolProvider": true,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "hoverProvider": true,
+            "completionProvider": {},
+        },
+        "serverInfo": { "name": "monkey-lsp", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+fn failure(id: &Value, (code, message): Failure) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+// read reads the body of one message, which follows a header giving its
+// length. None means the input has ended.
+fn read(r: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            let value = value.trim().parse().map_err(|_| invalid(format!("bad Content-Length: {}", value.trim())))?;
+            length = Some(value);
+        }
+    }
+    let length = length.ok_or_else(|| invalid("missing Content-Length".into()))?;
+    if length > MAX_MESSAGE {
+        return Err(invalid(format!("Content-Length {} is over the limit of {}", length, MAX_MESSAGE)));
+    }
+    let mut body = vec![0; length];
+    r.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write(w: &mut dyn Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// diagnostics lists the problems with a document: its syntax errors if it
+// doesn't parse, and otherwise its warnings and the names it can't resolve.
+fn diagnostics(uri: &str, source: &str) -> Vec<Value> {
+    let text = Text::new(source);
+    let diagnostics = match Parser::new(Lexer::new(source.chars())).parse() {
+        Ok(mut program) => {
+            let mut diagnostics = lint::lint(source, &Config::default());
+            if let Err(errors) = resolver::resolve(&mut program, &Env::new()) {
+                diagnostics.extend(errors);
+            }
+            diagnostics.sort();
+            diagnostics
+        },
+        Err(errors) => errors,
+    };
+    diagnostics.iter().map(|diagnostic| text.diagnostic(uri, diagnostic)).collect()
+}
+
+// symbols lists the top-level lets of a document that parses.
+fn symbols(source: &str) -> Value {
+    let analysis = match Analysis::new(source) {
+        Some(analysis) => analysis,
+        None => return json!([]),
+    };
+    let text = Text::new(source);
+    let symbols: Vec<Value> = analysis.program.statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::Let(stmt) => Some(stmt),
+            _ => None,
+        })
+        .map(|stmt| {
+            let kind = match stmt.value {
+                Expression::Function(_) => SYMBOL_FUNCTION,
+                _ => SYMBOL_VARIABLE,
+            };
+            let end = analysis.span(stmt.position).map_or(stmt.name.position, |span| span.end);
+            json!({
+                "name": stmt.name.name,
+                "detail": describe(stmt),
+                "kind": kind,
+                "range": text.range(stmt.position, end),
+                "selectionRange": text.identifier(&stmt.name),
+            })
+        })
+        .collect();
+    json!(symbols)
+}
+
+fn definition(uri: &str, source: &str, position: Position) -> Value {
+    let analysis = match Analysis::new(source) {
+        Some(analysis) => analysis,
+        None => return Value::Null,
+    };
+    match analysis.at(position) {
+        Some((_, Some(declaration))) => json!({ "uri": uri, "range": Text::new(source).identifier(declaration) }),
+        _ => Value::Null,
+    }
+}
+
+// references lists every use of the name at `position`, along with its
+// declaration if `declaration` is set.
+fn references(uri: &str, source: &str, position: Position, declaration: bool) -> Value {
+    let analysis = match Analysis::new(source) {
+        Some(analysis) => analysis,
+        None => return json!([]),
+    };
+    let target = match analysis.at(position) {
+        Some((_, Some(target))) => target,
+        _ => return json!([]),
+    };
+    let text = Text::new(source);
+    let locations: Vec<Value> = analysis.uses
+        .iter()
+        .filter(|(_, declared)| declared.as_ref().is_some_and(|declared| declared.position == target.position))
+        .filter(|(used, _)| declaration || used.position != target.position)
+        .map(|(used, _)| json!({ "uri": uri, "range": text.identifier(used) }))
+        .collect();
+    json!(locations)
+}
+
+// hover describes the name at `position`: how it was declared, or the
+// signature of a builtin.
+fn hover(source: &str, position: Position) -> Value {
+    let analysis = match Analysis::new(source) {
+        Some(analysis) => analysis,
+        None => return Value::Null,
+    };
+    let (used, description) = match analysis.at(position) {
+        Some((used, Some(declared))) => match analysis.descriptions.iter().find(|(d, _)| d.position == declared.position) {
+            Some((_, description)) => (used, description.clone()),
+            None => return Value::Null,
+        },
+        Some((used, None)) => match used.binding {
+            Binding::Builtin(index) => (used, format!("builtin {}", signature(Builtin::ALL[index]))),
+            _ => return Value::Null,
+        },
+        None => return Value::Null,
+    };
+    json!({
+        "contents": { "kind": "markdown", "value": format!("```monkey\n{}\n```", description) },
+        "range": Text::new(source).identifier(used),
+    })
+}
+
+// completion offers the keywords, the builtins and the names in scope at
+// `position`. A document being typed often doesn't parse, in which case every
+// identifier in it is offered instead.
+fn completion(source: &str, position: Position) -> Value {
+    let mut items = vec![];
+    let mut seen = vec![];
+    let mut offer = |label: &str, kind: i64, detail: Option<String>| {
+        if !seen.contains(&label.to_string()) {
+            seen.push(label.to_string());
+            items.push(json!({ "label": label, "kind": kind, "detail": detail }));
+        }
+    };
+    match Analysis::new(source) {
+        Some(analysis) => {
+            for name in analysis.scope(position) {
+                let description = analysis.descriptions
+                    .iter()
+                    .find(|(d, _)| d.position == name.position)
+                    .map(|(_, description)| description.clone());
+                let kind = match &description {
+                    Some(description) if description.contains("= fn(") => COMPLETION_FUNCTION,
+                    _ => COMPLETION_VARIABLE,
+                };
+                offer(&name.name, kind, description);
+            }
+        },
+        None => {
+            for token in Lexer::new(source.chars()).filter(|token| token.kind == Kind::Ident) {
+                offer(&token.literal, COMPLETION_VARIABLE, None);
+            }
+        },
+    }
+    for builtin in Builtin::ALL {
+        offer(builtin.name(), COMPLETION_FUNCTION, Some(format!("builtin {}", signature(builtin))));
+    }
+    for keyword in KEYWORDS {
+        offer(keyword, COMPLETION_KEYWORD, None);
+    }
+    json!(items)
+}
+
+// signature shows how a builtin is called.
+fn signature(builtin: Builtin) -> &'static str {
+    match builtin {
+        Builtin::Len => "len(string)",
+        Builtin::Puts => "puts(value, ...)",
+        Builtin::Argc => "argc()",
+        Builtin::Argv => "argv(index)",
+    }
+}
+
+// describe shows a let as hover does, with the parameters of a function.
+fn describe(stmt: &LetStatement) -> String {
+    match &stmt.value {
+        Expression::Function(function) => {
+            let parameters: Vec<String> = function.parameters.iter().map(|p| p.name.clone()).collect();
+            format!("let {} = fn({})", stmt.name.name, parameters.join(", "))
+        },
+        _ => format!("let {}", stmt.name.name),
+    }
+}
+
+// Analysis is what is known about a document that parses, resolved as though
+// it were run on its own.
+struct Analysis {
+    program: Program,
+    spans: Vec<Span>,
+    // uses pairs every occurrence of a name, declarations included, with the
+    // identifier that declares it, if it isn't a builtin or unbound.
+    uses: Vec<(Identifier, Option<Identifier>)>,
+    // descriptions says how each name was declared, for hover.
+    descriptions: Vec<(Identifier, String)>,
+    // functions pairs the position of the body of every function with the
+    // names declared by its parameters and lets.
+    functions: Vec<(Position, Vec<Identifier>)>,
+}
+
+impl Analysis {
+    fn new(source: &str) -> Option<Analysis> {
+        let mut parser = Parser::new(Lexer::new(source.chars()));
+        let mut program = parser.parse().ok()?;
+        // Names that can't be resolved are left unbound, which is all that
+        // matters here.
+        let _ = resolver::resolve(&mut program, &Env::new());
+        let mut index = Index::default();
+        index.visit_program(&program);
+        Some(Analysis {
+            program,
+            spans: parser.spans().to_vec(),
+            uses: index.uses,
+            descriptions: index.descriptions,
+            functions: index.functions,
+        })
+    }
+
+    // at finds the occurrence of a name that `position` is on, or just after.
+    fn at(&self, position: Position) -> Option<(&Identifier, Option<&Identifier>)> {
+        self.uses
+            .iter()
+            .find(|(used, _)| {
+                let end = used.position.column + used.name.chars().count();
+                used.position.line == position.line && (used.position.column..=end).contains(&position.column)
+            })
+            .map(|(used, declared)| (used, declared.as_ref()))
+    }
+
+    // span finds the span of the statement or block that starts at `start`.
+    fn span(&self, start: Position) -> Option<Span> {
+        self.spans.iter().find(|span| span.start == start).copied()
+    }
+
+    // scope lists the names that can be used at `position`, innermost first.
+    fn scope(&self, position: Position) -> Vec<Identifier> {
+        let mut names = vec![];
+        for (body, declared) in self.functions.iter().rev() {
+            let inside = self.span(*body).is_some_and(|span| span.start <= position && position <= span.end);
+            if inside {
+                names.extend(declared.iter().cloned());
+            }
+        }
+        names.extend(resolver::lets(&self.program.statements));
+        names
+    }
+}
+
+// Index walks a resolved program, pairing each name with its declaration by
+// following the bindings the resolver gave it.
+#[derive(Default)]
+struct Index {
+    // scopes holds the declarations of the functions being walked, innermost
+    // last, in slot order.
+    scopes: Vec<Vec<Identifier>>,
+    globals: HashMap<String, Identifier>,
+    uses: Vec<(Identifier, Option<Identifier>)>,
+    descriptions: Vec<(Identifier, String)>,
+    functions: Vec<(Position, Vec<Identifier>)>,
+}
+
+impl Index {
+    fn declaration(&self, identifier: &Identifier) -> Option<Identifier> {
+        match identifier.binding {
+            Binding::Local(slot) => self.scopes.last()?.get(slot).cloned(),
+            Binding::Free { depth, slot } => self.scopes.iter().rev().nth(depth)?.get(slot).cloned(),
+            Binding::Global(_) => self.globals.get(&identifier.name).cloned(),
+            Binding::Builtin(_) | Binding::Unresolved => None,
+        }
+    }
+}
+
+impl Visitor for Index {
+    fn visit_program(&mut self, program: &Program) {
+        for name in resolver::lets(&program.statements) {
+            self.globals.entry(name.name.clone()).or_insert(name);
+        }
+        visit::walk_program(self, program);
+    }
+
+    fn visit_let(&mut self, stmt: &LetStatement) {
+        let declaration = self.declaration(&stmt.name);
+        if declaration.as_ref().is_some_and(|declared| declared.position == stmt.name.position) {
+            self.descriptions.push((stmt.name.clone(), describe(stmt)));
+        }
+        self.uses.push((stmt.name.clone(), declaration));
+        visit::walk_let(self, stmt);
+    }
+
+    fn visit_identifier(&mut self, identifier: &Identifier) {
+        let declaration = self.declaration(identifier);
+        self.uses.push((identifier.clone(), declaration));
+    }
+
+    // Slots are given as the resolver gives them: parameters first, then the
+    // lets in the body that don't share a parameter's name.
+    fn visit_function(&mut self, exp: &FunctionLiteral) {
+        let mut scope: Vec<Identifier> = vec![];
+        for parameter in &exp.parameters {
+            self.uses.push((parameter.clone(), Some(parameter.clone())));
+            self.descriptions.push((parameter.clone(), format!("(parameter) {}", parameter.name)));
+            scope.push(parameter.clone());
+        }
+        for name in resolver::lets(&exp.body.statements) {
+            if !scope.iter().any(|declared| declared.name == name.name) {
+                scope.push(name);
+            }
+        }
+        self.functions.push((exp.body.position, scope.clone()));
+        self.scopes.push(scope);
+        self.visit_block(&exp.body);
+        self.scopes.pop();
+    }
+}
+
+// Text converts between positions in the source, with lines and columns
+// counted in characters from 1, and positions in the protocol, counted from
+// 0 with columns in UTF-16 code units.
+struct Text<'a> {
+    lines: Vec<&'a str>,
+}
+
+impl<'a> Text<'a> {
+    fn new(source: &'a str) -> Text<'a> {
+        Text { lines: source.split('\n').collect() }
+    }
+
+    fn line(&self, line: usize) -> &str {
+        self.lines.get(line.saturating_sub(1)).copied().unwrap_or_default()
+    }
+
+    fn encode(&self, position: Position) -> Value {
+        let character: usize = self.line(position.line)
+            .chars()
+            .take(position.column.saturating_sub(1))
+            .map(char::len_utf16)
+            .sum();
+        json!({ "line": position.line.saturating_sub(1), "character": character })
+    }
+
+    fn position(&self, value: &Value) -> Result<Position, Failure> {
+        let number = |key: &str| match value[key].as_u64() {
+            Some(n) => usize::try_from(n).map_err(|_| (INVALID_PARAMS, format!("{} out of range: {}", key, n))),
+            None => Err((INVALID_PARAMS, "missing position".to_string())),
+        };
+        let line = number("line")?;
+        let line = line.checked_add(1).ok_or_else(|| (INVALID_PARAMS, format!("line out of range: {}", line)))?;
+        let character = number("character")?;
+        let mut units = 0;
+        let mut column = 1;
+        for ch in self.line(line).chars() {
+            if units >= character {
+                break;
+            }
+            units += ch.len_utf16();
+            column += 1;
+        }
+        Ok(Position::new(line, column))
+    }
+
+    // range covers the source from `start` to the end of the token that
+    // starts at `end`.
+    fn range(&self, start: Position, end: Position) -> Value {
+        let length = self.token(end);
+        json!({ "start": self.encode(start), "end": self.encode(Position::new(end.line, end.column + length)) })
+    }
+
+    fn identifier(&self, identifier: &Identifier) -> Value {
+        let end = Position::new(identifier.position.line, identifier.position.column + identifier.name.chars().count());
+        json!({ "start": self.encode(identifier.position), "end": self.encode(end) })
+    }
+
+    // token measures the token at `position` in characters: a word, or a
+    // single character otherwise.
+    fn token(&self, position: Position) -> usize {
+        let mut rest = self.line(position.line).chars().skip(position.column.saturating_sub(1)).peekable();
+        let word = |ch: &char| ch.is_alphanumeric() || *ch == '_';
+        match rest.peek() {
+            Some(ch) if word(ch) => rest.take_while(word).count(),
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+
+    fn diagnostic(&self, uri: &str, diagnostic: &Diagnostic) -> Value {
+        let span = diagnostic.labels
+            .iter()
+            .find(|label| label.primary)
+            .map_or(Span::default(), |label| label.span);
+        let severity = match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Note => 3,
+        };
+        let mut message = diagnostic.message.clone();
+        if let Some(help) = &diagnostic.help {
+            message.push_str(&format!("\nhelp: {}", help));
+        }
+        let related: Vec<Value> = diagnostic.labels
+            .iter()
+            .filter(|label| !label.primary)
+            .map(|label| json!({
+                "location": { "uri": uri, "range": self.range(label.span.start, label.span.end) },
+                "message": label.message,
+            }))
+            .collect();
+        json!({
+            "range": self.range(span.start, span.end),
+            "severity": severity,
+            "code": diagnostic.code,
+            "source": "monkey",
+            "message": message,
+            "relatedInformation": related,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///calls.monkey";
+
+    // session plays a client: it sends each message to a new server, framed
+    // as a client would, and reads back every message the server writes.
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+        for message in messages {
+            write(&mut input, message).unwrap();
+        }
+        let mut output = vec![];
+        Lsp::new().run(&mut &input[..], &mut output).unwrap();
+        let mut r = &output[..];
+        let mut replies = vec![];
+        while let Some(body) = read(&mut r).unwrap() {
+            replies.push(serde_json::from_slice(&body).unwrap());
+        }
+        replies
+    }
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn open(text: &str) -> Value {
+        notification("textDocument/didOpen", json!({
+            "textDocument": { "uri": URI, "languageId": "monkey", "version": 1, "text": text },
+        }))
+    }
+
+    fn at(line: u64, character: u64) -> Value {
+        json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+    }
+
+    fn range(line: u64, start: u64, end: u64) -> Value {
+        json!({ "start": { "line": line, "character": start }, "end": { "line": line, "character": end } })
+    }
+
+    #[test]
+    fn lifecycle() {
+        let replies = session(&[
+            request(1, "textDocument/hover", at(0, 0)),
+            open("let x = 1;"),
+            request(2, "initialize", json!({ "capabilities": {} })),
+            notification("initialized", json!({})),
+            request(3, "textDocument/rename", at(0, 0)),
+            request(4, "textDocument/hover", at(0, 0)),
+            request(5, "shutdown", Value::Null),
+            request(6, "textDocument/hover", at(0, 0)),
+            notification("exit", Value::Null),
+            request(7, "shutdown", Value::Null),
+        ]);
+        let errors: Vec<(Value, Value)> = replies
+            .iter()
+            .map(|reply| (reply["id"].clone(), reply["error"]["code"].clone()))
+            .collect();
+        let want = vec![
+            (json!(1), json!(SERVER_NOT_INITIALIZED)),
+            (json!(2), Value::Null),
+            (json!(3), json!(METHOD_NOT_FOUND)),
+            (json!(4), json!(INVALID_PARAMS)),
+            (json!(5), Value::Null),
+            (json!(6), json!(INVALID_REQUEST)),
+        ];
+        assert_eq!(errors, want);
+        assert_eq!(replies[1]["result"]["capabilities"]["hoverProvider"], json!(true));
+        assert_eq!(replies[4]["result"], Value::Null);
+
+        let mut input = b"Content-Length: 5\r\n\r\n{nope".to_vec();
+        write(&mut input, &request(1, "initialize", json!({}))).unwrap();
+        let mut output = vec![];
+        Lsp::new().run(&mut &input[..], &mut output).unwrap();
+        let reply: Value = serde_json::from_slice(&read(&mut &output[..]).unwrap().unwrap()).unwrap();
+        assert_eq!((&reply["id"], &reply["error"]["code"]), (&Value::Null, &json!(PARSE_ERROR)));
+    }
+
+    // Numbers from the client too large to use are refused, not trusted.
+    #[test]
+    fn limits() {
+        let replies = session(&[
+            request(0, "initialize", json!({})),
+            open("let x = 1;"),
+            request(1, "textDocument/hover", at(u64::MAX, 0)),
+        ]);
+        assert_eq!(replies[2]["error"]["code"], json!(INVALID_PARAMS));
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", u64::MAX);
+        let err = read(&mut input.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", MAX_MESSAGE + 1);
+        assert!(read(&mut input.as_bytes()).is_err());
+    }
+
+    // Diagnostics are published for every version of a document, and cleared
+    // when it is closed.
+    #[test]
+    fn diagnostics() {
+        let change = |text: &str| notification("textDocument/didChange", json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": text }],
+        }));
+        let replies = session(&[
+            request(1, "initialize", json!({})),
+            open("let f = fn(a, b) { a };\nretrun 1"),
+            change("let = 1;"),
+            notification("textDocument/didClose", json!({ "textDocument": { "uri": URI } })),
+        ]);
+        let published: Vec<Vec<(Value, Value, Value, Value)>> = replies[1..]
+            .iter()
+            .map(|reply| {
+                assert_eq!(reply["method"], "textDocument/publishDiagnostics");
+                assert_eq!(reply["params"]["uri"], URI);
+                reply["params"]["diagnostics"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|d| (d["range"].clone(), d["severity"].clone(), d["code"].clone(), d["message"].clone()))
+                    .collect()
+            })
+            .collect();
+        let want = vec![
+            vec![
+                (
+                    range(0, 14, 15), json!(2), json!("W0002"),
+                    json!("unused parameter: b\nhelp: `// lint: disable-next-line unused-variable` on the line before allows this"),
+                ),
+                (range(1, 0, 6), json!(1), json!("E0005"), json!("identifier not found: retrun\nhelp: did you mean `return`?")),
+            ],
+            vec![
+                (range(0, 4, 5), json!(1), json!("E0003"), json!("expected Ident, got Assign")),
+                (range(0, 4, 5), json!(1), json!("E0001"), json!("unexpected: =")),
+            ],
+            vec![],
+        ];
+        assert_eq!(published, want);
+    }
+
+    #[test]
+    fn navigation() {
+        let text = "let add = fn(a, b) { a + b };\nlet twice = fn(x) { add(x, x) };\ntwice(add(1, 2));\nputs(twice(1))";
+        let references = |character, declaration| {
+            let mut params = at(1, character);
+            params["context"] = json!({ "includeDeclaration": declaration });
+            params
+        };
+        let hover = |contents: &str, range: Value| json!({
+            "contents": { "kind": "markdown", "value": format!("```monkey\n{}\n```", contents) },
+            "range": range,
+        });
+        let location = |range: Value| json!({ "uri": URI, "range": range });
+        let tests = vec![
+            (
+                "textDocument/documentSymbol",
+                json!({ "textDocument": { "uri": URI } }),
+                json!([
+                    {
+                        "name": "add", "detail": "let add = fn(a, b)", "kind": SYMBOL_FUNCTION,
+                        "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 29 } },
+                        "selectionRange": range(0, 4, 7),
+                    },
+                    {
+                        "name": "twice", "detail": "let twice = fn(x)", "kind": SYMBOL_FUNCTION,
+                        "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 32 } },
+                        "selectionRange": range(1, 4, 9),
+                    },
+                ]),
+            ),
+            ("textDocument/definition", at(2, 7), location(range(0, 4, 7))),
+            ("textDocument/definition", at(1, 25), location(range(1, 15, 16))),
+            ("textDocument/definition", at(3, 1), Value::Null),
+            (
+                "textDocument/references",
+                references(15, true),
+                json!([location(range(1, 15, 16)), location(range(1, 24, 25)), location(range(1, 27, 28))]),
+            ),
+            ("textDocument/references", references(15, false), json!([location(range(1, 24, 25)), location(range(1, 27, 28))])),
+            ("textDocument/references", references(24, false), json!([location(range(1, 24, 25)), location(range(1, 27, 28))])),
+            ("textDocument/hover", at(2, 2), hover("let twice = fn(x)", range(2, 0, 5))),
+            ("textDocument/hover", at(0, 21), hover("(parameter) a", range(0, 21, 22))),
+            ("textDocument/hover", at(3, 0), hover("builtin puts(value, ...)", range(3, 0, 4))),
+            ("textDocument/hover", at(0, 9), Value::Null),
+        ];
+        let mut messages = vec![request(0, "initialize", json!({})), open(text)];
+        for (id, (method, params, _)) in tests.iter().enumerate() {
+            messages.push(request(id as i64 + 1, method, params.clone()));
+        }
+        let replies = session(&messages);
+        for (reply, (method, params, want)) in replies[2..].iter().zip(tests) {
+            assert_eq!(reply["result"], want, "{} {}", method, params);
+        }
+    }
+
+    #[test]
+    fn completion() {
+        let tests = vec![
+            // Inside `twice`, its parameter comes before the globals.
+            ("let add = fn(a, b) { a + b };\nlet twice = fn(x) { add(x, x) };", at(1, 21), vec!["x", "add", "twice"]),
+            ("let add = fn(a, b) { a + b };\nlet twice = fn(x) { add(x, x) };", at(1, 0), vec!["add", "twice"]),
+            // Text that doesn't parse offers the identifiers in it.
+            ("let total = 1;\nlet sum = tot +", at(1, 13), vec!["total", "sum", "tot"]),
+        ];
+        for (text, position, names) in tests {
+            let replies = session(&[
+                request(0, "initialize", json!({})),
+                open(text),
+                request(1, "textDocument/completion", position),
+            ]);
+            let labels: Vec<&str> = replies[2]["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["label"].as_str().unwrap())
+                .collect();
+            let mut want = names.clone();
+            want.extend(["len", "puts", "argc", "argv"]);
+            want.extend(KEYWORDS);
+            assert_eq!(labels, want, "{}", text);
+        }
+    }
+
+    // Columns count characters in the source and UTF-16 code units in the
+    // protocol, so they part ways after a character outside the BMP.
+    #[test]
+    fn text() {
+        let text = Text::new("let s = \"😀\" + é;\né");
+        let tests = vec![
+            (Position::new(1, 1), json!({ "line": 0, "character": 0 })),
+            (Position::new(1, 10), json!({ "line": 0, "character": 9 })),
+            (Position::new(1, 11), json!({ "line": 0, "character": 11 })),
+            (Position::new(1, 15), json!({ "line": 0, "character": 15 })),
+            (Position::new(1, 17), json!({ "line": 0, "character": 17 })),
+            (Position::new(2, 2), json!({ "line": 1, "character": 1 })),
+        ];
+        for (position, encoded) in tests {
+            assert_eq!(text.encode(position), encoded, "{}", position);
+            assert_eq!(text.position(&encoded).unwrap(), position, "{}", encoded);
+        }
+        // A column inside a surrogate pair moves on to the next character.
+        assert_eq!(text.position(&json!({ "line": 0, "character": 10 })).unwrap(), Position::new(1, 11));
+    }
+}
@@ -4,18 +4,32 @@ mod token;
 mod lexer;
 mod ast;
 mod parser;
+mod object;
+mod evaluator;
+mod tree;
+mod dot;
+mod printer;
+mod formatter;
+mod visit;
+mod optimizer;
+mod resolver;
+mod lint;
+mod codes;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "json")]
+mod lsp;
 mod repl;
+mod editor;
+mod highlight;
+mod server;
+mod cli;
 mod util;
 
-use whoami;
-use std::io;
+use std::env;
+use std::process;
 
 fn main() {
-    let stdin = io::stdin();
-    let stdout = io::stdout();
-    let mut stdin = stdin.lock();
-    let mut stdout = stdout.lock();
-    println!("Hello {}! This is the Monkey programming language.", whoami::username());
-    println!("Feel free to type in commands.");
-    repl::start(&mut stdin, &mut stdout);
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(cli::main(&args));
 }
@@ -0,0 +1,289 @@
+use crate::ast::{Identifier, BlockStatement};
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Object is a value produced by evaluating Monkey code.
+#[derive(Debug, Clone)]
+pub enum Object {
+    Null,
+    Int(i64),
+    Boolean(bool),
+    String(String),
+    // Return wraps the value of a return statement while it unwinds to the
+    // enclosing function call.
+    Return(Box<Object>),
+    // frame is the frame of the call that created the function, which is None
+    // for functions created at the top level. name is the name the function
+    // was first bound to by a let, for tracebacks.
+    Function { name: Option<String>, parameters: Vec<Identifier>, body: Arc<BlockStatement>, frame: Option<Frame> },
+    Builtin(Builtin),
+}
+
+impl Object {
+    /// type_name is the name of the object's type as shown in errors.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Null => "NULL",
+            Object::Int(_) => "INTEGER",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Return(value) => value.type_name(),
+            Object::Function { .. } => "FUNCTION",
+            Object::Builtin(_) => "BUILTIN",
+        }
+    }
+
+    /// truthy reports whether the object counts as true in a condition.
+    pub fn truthy(&self) -> bool {
+        match self {
+            Object::Null => false,
+            Object::Boolean(b) => *b,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Object {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Object::Null => write!(f, "null"),
+            Object::Int(n) => write!(f, "{}", n),
+            Object::Boolean(b) => write!(f, "{}", b),
+            Object::String(s) => write!(f, "{}", s),
+            Object::Return(value) => write!(f, "{}", value),
+            Object::Function { parameters, body, .. } => {
+                let parameters = parameters
+                    .iter()
+                    .map(|p| p.to_string()).collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "fn({}) {}", parameters, body)
+            },
+            Object::Builtin(builtin) => write!(f, "builtin {}", builtin.name()),
+        }
+    }
+}
+
+/// Builtin is a function provided by the interpreter rather than defined in
+/// Monkey code.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Builtin {
+    Len,
+    Puts,
+    Argc,
+    Argv,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 4] = [Builtin::Len, Builtin::Puts, Builtin::Argc, Builtin::Argv];
+
+    pub fn lookup(name: &str) -> Option<Builtin> {
+        Builtin::ALL.iter().cloned().find(|b| b.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Len => "len",
+            Builtin::Puts => "puts",
+            Builtin::Argc => "argc",
+            Builtin::Argv => "argv",
+        }
+    }
+}
+
+/// Env is a handle to the global environment. Clones share the same
+/// bindings, and handles can be sent between threads so that sessions may
+/// share globals.
+#[derive(Clone, Default)]
+pub struct Env(Arc<Mutex<Environment>>);
+
+/// Environment holds the globals. Each name is given a slot when it is first
+/// resolved, which code refers to it by from then on; the slot stays empty
+/// until a value is bound.
+#[derive(Default)]
+pub struct Environment {
+    slots: HashMap<String, usize>,
+    values: Vec<Option<Object>>,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env::default()
+    }
+
+    /// slot returns the slot for `name`, creating one if it has none.
+    pub fn slot(&self, name: &str) -> usize {
+        let mut env = self.lock();
+        if let Some(slot) = env.slots.get(name) {
+            return *slot;
+        }
+        let slot = env.values.len();
+        env.slots.insert(name.to_string(), slot);
+        env.values.push(None);
+        slot
+    }
+
+    /// lookup returns the slot for `name`, if it has one.
+    pub fn lookup(&self, name: &str) -> Option<usize> {
+        self.lock().slots.get(name).copied()
+    }
+
+    pub fn load(&self, slot: usize) -> Option<Object> {
+        self.lock().values.get(slot).cloned().flatten()
+    }
+
+    pub fn store(&self, slot: usize, value: Object) {
+        let mut env = self.lock();
+        if slot >= env.values.len() {
+            env.values.resize(slot + 1, None);
+        }
+        env.values[slot] = Some(value);
+    }
+
+    /// unset empties a slot, as though its name had never been bound.
+    pub fn unset(&self, slot: usize) {
+        if let Some(value) = self.lock().values.get_mut(slot) {
+            *value = None;
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.lookup(name).and_then(|slot| self.load(slot))
+    }
+
+    pub fn set(&self, name: &str, value: Object) {
+        let slot = self.slot(name);
+        self.store(slot, value);
+    }
+
+    /// bindings lists the names that are bound to a value, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, Object)> {
+        let env = self.lock();
+        let mut bindings: Vec<(String, Object)> = env.slots
+            .iter()
+            .filter_map(|(name, slot)| Some((name.clone(), env.values[*slot].clone()?)))
+            .collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    /// clear removes every binding. Names keep their slots, since code that
+    /// was resolved against them may still run.
+    pub fn clear(&self) {
+        for value in self.lock().values.iter_mut() {
+            *value = None;
+        }
+    }
+
+    // A thread that panicked while holding the lock can't have left the map
+    // half updated, so the poison is ignored rather than spreading the panic.
+    fn lock(&self) -> MutexGuard<'_, Environment> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+// Environments are cyclic when a function refers to itself, so only the
+// names are printed.
+impl Debug for Env {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let env = self.lock();
+        f.debug_set().entries(env.slots.keys()).finish()
+    }
+}
+
+/// Frame holds the parameters and local bindings of a function call, by the
+/// slots the resolver gave them. A function keeps the frame it was created
+/// in, which is where the free identifiers in its body are found.
+///
+/// A function bound by a `let` in the frame it was created in, as a recursive
+/// local function is, would keep that frame alive from one of its own slots.
+/// So the slot holds the function without its frame, and `load` puts the
+/// frame back. Cycles through more than one frame, such as a function stored
+/// in a frame that a closure it captured was created in, are still leaked.
+#[derive(Clone)]
+pub struct Frame(Arc<Mutex<Slots>>);
+
+pub struct Slots {
+    values: Vec<Option<Slot>>,
+    outer: Option<Frame>,
+}
+
+#[derive(Clone)]
+enum Slot {
+    Value(Object),
+    // Own is a function created in the frame that holds it.
+    Own { name: Option<String>, parameters: Vec<Identifier>, body: Arc<BlockStatement> },
+}
+
+impl Frame {
+    /// new creates the frame for a call to a function created in `outer`,
+    /// which is None for functions created at the top level.
+    pub fn new(values: Vec<Object>, outer: Option<Frame>) -> Frame {
+        let values = values.into_iter().map(|value| Some(Slot::Value(value))).collect();
+        Frame(Arc::new(Mutex::new(Slots { values, outer })))
+    }
+
+    /// outer returns the frame `depth` functions out from this one.
+    pub fn outer(&self, depth: usize) -> Option<Frame> {
+        let mut frame = self.clone();
+        for _ in 0..depth {
+            let outer = frame.lock().outer.clone()?;
+            frame = outer;
+        }
+        Some(frame)
+    }
+
+    pub fn load(&self, slot: usize) -> Option<Object> {
+        let value = self.lock().values.get(slot).cloned().flatten()?;
+        match value {
+            Slot::Value(value) => Some(value),
+            Slot::Own { name, parameters, body } => Some(Object::Function { name, parameters, body, frame: Some(self.clone()) }),
+        }
+    }
+
+    pub fn store(&self, slot: usize, value: Object) {
+        let value = match value {
+            Object::Function { name, parameters, body, frame: Some(frame) } if Arc::ptr_eq(&frame.0, &self.0) => {
+                Slot::Own { name, parameters, body }
+            },
+            value => Slot::Value(value),
+        };
+        let mut frame = self.lock();
+        if slot >= frame.values.len() {
+            frame.values.resize(slot + 1, None);
+        }
+        frame.values[slot] = Some(value);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Debug for Frame {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Frame({} slots)", self.lock().values.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A function stored in the frame it was created in gets the frame back
+    // when loaded, without keeping it alive.
+    #[test]
+    fn own_functions() {
+        let frame = Frame::new(vec![], None);
+        let body = Arc::new(BlockStatement::new(vec![]));
+        frame.store(0, Object::Function { name: Some("f".into()), parameters: vec![], body, frame: Some(frame.clone()) });
+        match frame.load(0) {
+            Some(Object::Function { frame: Some(outer), .. }) => assert!(Arc::ptr_eq(&outer.0, &frame.0)),
+            other => panic!("unexpected value: {:?}", other),
+        }
+        let weak = Arc::downgrade(&frame.0);
+        drop(frame);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,231 @@
+use crate::ast::{
+    Program, Statement, BlockStatement, Expression, IntegerLiteral, BooleanLiteral,
+    PrefixExpression, InfixExpression, IfExpression, Prefix, Infix,
+};
+use crate::token::Position;
+use crate::visit::{self, Fold};
+
+/// optimize simplifies the program without changing what it does: operators
+/// applied to literals are worked out ahead of time, conditionals on literals
+/// lose the branch that can't be taken, and arithmetic identities such as
+/// `x * 1` are dropped. Anything that would fail at runtime, such as `1 / 0`
+/// or an overflow, is left in place to fail as it would have.
+pub fn optimize(program: Program) -> Program {
+    Optimizer.fold_program(program)
+}
+
+struct Optimizer;
+
+impl Fold for Optimizer {
+    fn fold_prefix(&mut self, exp: PrefixExpression) -> Expression {
+        let exp = match visit::fold_prefix(self, exp) {
+            Expression::Prefix(exp) => exp,
+            other => return other,
+        };
+        let position = exp.position;
+        match (&exp.operator, exp.value.as_ref()) {
+            (Prefix::Not, Expression::Boolean(b)) => boolean(!b.value, position),
+            // Every other literal is truthy.
+            (Prefix::Not, Expression::Int(_) | Expression::String(_)) => boolean(false, position),
+            (Prefix::Negative, Expression::Int(n)) => match n.value.checked_neg() {
+                Some(value) => int(value, position),
+                None => Expression::Prefix(exp),
+            },
+            _ => Expression::Prefix(exp),
+        }
+    }
+
+    fn fold_infix(&mut self, exp: InfixExpression) -> Expression {
+        let exp = match visit::fold_infix(self, exp) {
+            Expression::Infix(exp) => exp,
+            other => return other,
+        };
+        let position = exp.left.position();
+        let folded = match (exp.left.as_ref(), exp.right.as_ref()) {
+            (Expression::Int(l), Expression::Int(r)) => integers(&exp.operator, l.value, r.value, position),
+            (Expression::Boolean(l), Expression::Boolean(r)) => match exp.operator {
+                Infix::Eq => Some(boolean(l.value == r.value, position)),
+                Infix::NotEq => Some(boolean(l.value != r.value, position)),
+                _ => None,
+            },
+            _ => None,
+        };
+        match folded {
+            Some(folded) => folded,
+            None => identity(exp),
+        }
+    }
+
+    fn fold_if(&mut self, exp: IfExpression) -> Expression {
+        let exp = match visit::fold_if(self, exp) {
+            Expression::If(exp) => exp,
+            other => return other,
+        };
+        let truthy = match exp.predicate.as_ref() {
+            Expression::Boolean(b) => b.value,
+            Expression::Int(_) | Expression::String(_) => true,
+            _ => return Expression::If(exp),
+        };
+        let IfExpression { predicate, success, fail, position } = exp;
+        let (predicate, branch) = match (truthy, fail) {
+            (true, _) => (*predicate, success),
+            (false, Some(fail)) => (boolean(true, predicate.position()), fail),
+            // Without an else the value is null, which has no literal.
+            (false, None) => (*predicate, BlockStatement { statements: vec![], ..success }),
+        };
+        // A branch holding a single expression has the value of that
+        // expression.
+        let mut branch = branch;
+        if let [Statement::Expression(_)] = branch.statements.as_slice() {
+            if let Some(Statement::Expression(exp)) = branch.statements.pop() {
+                return exp;
+            }
+        }
+        Expression::If(IfExpression { predicate: Box::new(predicate), success: branch, fail: None, position })
+    }
+}
+
+// integers applies an operator to two integers, unless doing so would fail.
+fn integers(operator: &Infix, l: i64, r: i64, position: Position) -> Option<Expression> {
+    let value = match operator {
+        Infix::Add => l.checked_add(r),
+        Infix::Subtract => l.checked_sub(r),
+        Infix::Multiply => l.checked_mul(r),
+        Infix::Divide if r == 0 => None,
+        Infix::Divide => l.checked_div(r),
+        Infix::LessThan => return Some(boolean(l < r, position)),
+        Infix::GreaterThan => return Some(boolean(l > r, position)),
+        Infix::Eq => return Some(boolean(l == r, position)),
+        Infix::NotEq => return Some(boolean(l != r, position)),
+    };
+    value.map(|value| int(value, position))
+}
+
+// identity drops an operand of zero from addition and subtraction, and of one
+// from multiplication and division. The other operand must be an integer, as
+// otherwise the operator could fail or, for `+`, join strings.
+fn identity(exp: InfixExpression) -> Expression {
+    let is = |exp: &Expression, n: i64| matches!(exp, Expression::Int(int) if int.value == n);
+    let keep_left = match exp.operator {
+        Infix::Add | Infix::Subtract => is(&exp.right, 0),
+        Infix::Multiply | Infix::Divide => is(&exp.right, 1),
+        _ => false,
+    };
+    let keep_right = match exp.operator {
+        Infix::Add => is(&exp.left, 0),
+        Infix::Multiply => is(&exp.left, 1),
+        _ => false,
+    };
+    if keep_left && integer(&exp.left) {
+        *exp.left
+    } else if keep_right && integer(&exp.right) {
+        *exp.right
+    } else {
+        Expression::Infix(exp)
+    }
+}
+
+// integer reports whether the expression can only evaluate to an integer, if
+// it evaluates at all. Only integers can be negated, subtracted, multiplied
+// or divided.
+fn integer(exp: &Expression) -> bool {
+    match exp {
+        Expression::Int(_) => true,
+        Expression::Prefix(exp) => exp.operator == Prefix::Negative,
+        Expression::Infix(exp) => match exp.operator {
+            Infix::Subtract | Infix::Multiply | Infix::Divide => true,
+            Infix::Add => integer(&exp.left) && integer(&exp.right),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn int(value: i64, position: Position) -> Expression {
+    Expression::Int(IntegerLiteral { value, position })
+}
+
+fn boolean(value: bool, position: Position) -> Expression {
+    Expression::Boolean(BooleanLiteral { value, position })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluator::Evaluator;
+    use crate::parser::parse;
+    use crate::printer;
+
+    type Error = Box<dyn std::error::Error>;
+
+    #[test]
+    fn folded() -> Result<(), Error> {
+        let tests = vec![
+            ("60 * 60 * 24", "86400;\n"),
+            ("let day = 60 * 60 * 24; day * 7", "let day = 86400;\nday * 7;\n"),
+            ("-(2 - 5); !true; !!0; !\"\"", "3;\nfalse;\ntrue;\nfalse;\n"),
+            ("1 < 2 == true; true != false; 3 == 4", "true;\ntrue;\nfalse;\n"),
+            ("fn(x) { x * (2 + 2) }", "fn(x) {\n    x * 4;\n};\n"),
+            ("if (1 < 2) { a } else { b }", "a;\n"),
+            ("if (false) { a } else { b }", "b;\n"),
+            ("if (0) { let a = 1; a } else { b }", "if (0) {\n    let a = 1;\n    a;\n};\n"),
+            ("if (false) { a; b } else { c; d }", "if (true) {\n    c;\n    d;\n};\n"),
+            ("if (false) { a }", "if (false) {};\n"),
+            ("if (x) { 1 + 1 } else { 2 * 2 }", "if (x) {\n    2;\n} else {\n    4;\n};\n"),
+            ("-x * 1; 0 + (a - b); (a * b) / 1; 1 * (2 + -y)", "-x;\na - b;\na * b;\n2 + -y;\n"),
+        ];
+        for (input, want) in tests {
+            assert_eq!(printer::print(&optimize(parse(input)?)), want, "{}", input);
+        }
+        Ok(())
+    }
+
+    // Expressions that fail, or might, are left for the evaluator.
+    #[test]
+    fn unchanged() -> Result<(), Error> {
+        let inputs = vec![
+            "1 / 0;\n",
+            "9223372036854775807 + 1;\n",
+            "true + false;\n",
+            "1 + true;\n",
+            "x + 0;\n",
+            "s * 1;\n",
+            "0 - x;\n",
+            "\"a\" + 0;\n",
+            "f() * 0;\n",
+        ];
+        for input in inputs {
+            let want = printer::print(&parse(input)?);
+            assert_eq!(printer::print(&optimize(parse(input)?)), want, "{}", input);
+        }
+        Ok(())
+    }
+
+    // Optimized programs evaluate to the same value, or fail the same way.
+    #[test]
+    fn equivalent() -> Result<(), Error> {
+        let inputs = vec![
+            "let f = fn(x) { if (x > 10 - 10) { x * 1 + f(x - 1) } else { 0 + 0 } }; f(10)",
+            "if (2 > 1) { return 60 * 60; 1 }",
+            "let s = \"a\"; s + 0",
+            "let x = true; -x * 1",
+            "if (false) { 1 }",
+            "let n = 5 / (3 - 3); n",
+            "len(\"abc\") * 1 + 0",
+            "puts(!1, !!true, -(-1)); 9223372036854775807 * 2",
+            "-(-9223372036854775807 - 1)",
+        ];
+        for input in inputs {
+            let (mut evaluated, mut optimized) = (Evaluator::new(), Evaluator::new());
+            let mut program = parse(input)?;
+            evaluated.resolve(&mut program)?;
+            optimized.resolve(&mut program)?;
+            let (mut want, mut got) = (vec![], vec![]);
+            let want_value = evaluated.eval(&program, &mut want).map(|v| v.to_string()).map_err(|e| e.to_string());
+            let got_value = optimized.eval(&optimize(program), &mut got).map(|v| v.to_string()).map_err(|e| e.to_string());
+            assert_eq!(got_value, want_value, "{}", input);
+            assert_eq!(got, want, "{}", input);
+        }
+        Ok(())
+    }
+}
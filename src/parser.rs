@@ -1,19 +1,39 @@
-use crate::token::{Token, Kind};
-use crate::ast::{Program, Node, Precedence, Prefix, Infix};
-use crate::util::MultiError;
+use crate::token::{Token, Kind, Span};
+use crate::ast::{
+    Program, Statement, LetStatement, ReturnStatement, BlockStatement, Expression, Identifier, Binding,
+    IntegerLiteral, StringLiteral, BooleanLiteral, PrefixExpression, InfixExpression, IfExpression,
+    FunctionLiteral, CallExpression, Precedence, Prefix, Infix,
+};
+use crate::lexer::{unescape, KEYWORDS};
+use crate::util::{self, Diagnostic, Diagnostics};
 
 use std::iter::Peekable;
 use std::cell::RefCell;
+use std::sync::Arc;
 
-// TODO: Error type that can display line number and line context.
 type Error = Box<dyn std::error::Error>;
 
+/// MAX_NESTING is how deeply expressions may nest inside one another before
+/// the parser gives up, rather than run out of stack.
+pub const MAX_NESTING: usize = 256;
+
 /// Parser transforms a stream of tokens into an AST for the monkey language.
 pub struct Parser<Lexer>
     where Lexer: Iterator<Item=Token>,
 {
     lexer: RefCell<Peekable<Lexer>>,
     token: RefCell<Token>,
+    // previous is the kind of token before the current one.
+    previous: RefCell<Kind>,
+    // run is the first token of the statements since the last one that
+    // ended in a semicolon or brace, which the parser reads as separate
+    // statements when a keyword is misspelt: `lett x = 1;` is `lett`, `x`
+    // and then an unexpected `=`.
+    run: Option<Token>,
+    // depth is how many expressions the current token is nested inside.
+    depth: usize,
+    spans: Vec<Span>,
+    warnings: Diagnostics,
 }
 
 impl<Lexer> Parser<Lexer>
@@ -24,67 +44,112 @@ impl<Lexer> Parser<Lexer>
         Parser {
             lexer: RefCell::new(lexer.peekable()),
             token: RefCell::new(Token::new(Kind::Illegal, "")),
+            previous: RefCell::new(Kind::Illegal),
+            run: None,
+            depth: 0,
+            spans: vec![],
+            warnings: Diagnostics::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Program, MultiError> {
-        let mut nodes: Vec<Node> = vec![];
-        let mut errors: MultiError = MultiError::new();
+    /// spans lists the source range of every statement and block parsed, in
+    /// the order they start. A walk over the program that meets each
+    /// statement or block before its children sees them in the same order.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// warnings lists the warnings found by `parse`, which doesn't fail
+    /// because of them.
+    pub fn warnings(&self) -> &Diagnostics {
+        &self.warnings
+    }
+
+    /// parse reads the whole program. If it has syntax errors, they are
+    /// returned along with any warnings, in source order.
+    pub fn parse(&mut self) -> Result<Program, Diagnostics> {
+        let mut statements: Vec<Statement> = vec![];
+        let mut errors = Diagnostics::new();
         loop {
             self.advance();
             if self.token().kind == Kind::Eof {
                 break;
             }
             match self.parse_statement() {
-                Ok(node) => nodes.push(node),
-                Err(err) => errors.push(err),
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => {
+                    let diagnostic = self.locate(err);
+                    // The rest of a program nested too deeply only repeats
+                    // the error, so parsing stops.
+                    let nested = diagnostic.code == "E0008";
+                    errors.push(diagnostic);
+                    if nested {
+                        break;
+                    }
+                },
             }
         }
-        if errors.len() > 0 {
-            Err(errors)
+        if errors.is_empty() {
+            Ok(Program::new(statements))
         } else {
-            Ok(Program::new(nodes))
+            errors.extend(self.warnings.clone());
+            errors.sort();
+            Err(errors)
         }
     }
 
-    fn parse_statement(&mut self) -> Result<Node, Error> {
-        let node = match self.token().kind {
+    fn parse_statement(&mut self) -> Result<Statement, Error> {
+        let span = self.open_span();
+        let first = self.token();
+        if self.run.is_none() || matches!(*self.previous.borrow(), Kind::Semicolon | Kind::LeftBrace | Kind::RightBrace) {
+            self.run = Some(first.clone());
+        }
+        let run = self.run.clone();
+        let stmt = match first.kind {
             Kind::Let => {
-                self.parse_let_statement()?
+                self.parse_let_statement().map(Statement::Let)
             },
             Kind::Return => {
-                self.parse_return_statement()?
+                self.parse_return_statement().map(Statement::Return)
             },
             _ => {
-                self.parse_expression_statement()?
+                self.parse_expression_statement().map(Statement::Expression)
             },
         };
-        Ok(node)
+        let stmt = stmt.map_err(|err| misspelt(run.as_ref(), err))?;
+        self.close_span(span);
+        Ok(stmt)
     }
 
-    fn parse_let_statement(&mut self) -> Result<Node, Error> {
-        let name = self.expect(Kind::Ident)?.literal;
+    fn parse_let_statement(&mut self) -> Result<LetStatement, Error> {
+        let position = self.token().position;
+        let name = self.expect(Kind::Ident)?;
         self.advance();
         self.expect(Kind::Assign)?;
         self.advance();
         self.advance();
         let value = self.parse_expression(Precedence::Lowest)?;
-        while self.token().kind != Kind::Semicolon {
+        while self.token().kind != Kind::Semicolon && self.token().kind != Kind::Eof {
             self.advance();
         }
-        Ok(Node::Let{name: name, value: Box::new(value)})
+        Ok(LetStatement {
+            name: Identifier { name: name.literal, position: name.position, binding: Binding::Unresolved },
+            value,
+            position,
+        })
     }
 
-    fn parse_return_statement(&mut self) -> Result<Node, Error> {
+    fn parse_return_statement(&mut self) -> Result<ReturnStatement, Error> {
+        let position = self.token().position;
         self.advance();
         let value = self.parse_expression(Precedence::Lowest)?;
         if self.expect(Kind::Semicolon).is_ok() {
             self.advance();
         }
-        Ok(Node::Return { value: Box::new(value) })
+        Ok(ReturnStatement { value, position })
     }
 
-    fn parse_expression_statement(&mut self) -> Result<Node, Error> {
+    fn parse_expression_statement(&mut self) -> Result<Expression, Error> {
         let exp = self.parse_expression(Precedence::Lowest)?;
         if self.expect(Kind::Semicolon).is_ok() {
             self.advance();
@@ -92,42 +157,70 @@ impl<Lexer> Parser<Lexer>
         Ok(exp)
     }
 
-    fn parse_expression(&mut self, p: Precedence) -> Result<Node, Error> {
+    fn parse_expression(&mut self, p: Precedence) -> Result<Expression, Error> {
+        if self.depth == MAX_NESTING {
+            let token = self.token();
+            return Err(self.error(&token, "E0008", format!("expression nested more than {} deep", MAX_NESTING)));
+        }
+        self.depth += 1;
+        let exp = self.parse_operators(p);
+        self.depth -= 1;
+        exp
+    }
+
+    // parse_operators reads a prefix expression and any infix operators that
+    // bind more tightly than `p` after it.
+    fn parse_operators(&mut self, p: Precedence) -> Result<Expression, Error> {
         let mut left = self.parse_prefix()?;
-        while !self.expect(Kind::Semicolon).is_ok() && p < Precedence::from(self.peek()?.kind) {
+        while self.expect(Kind::Semicolon).is_err() && p < Precedence::from(self.peek()?.kind) {
             self.advance();
             left = self.parse_infix(left)?;
         }
         Ok(left)
     }
 
-    fn parse_prefix(&mut self) -> Result<Node, Error> {
+    fn parse_prefix(&mut self) -> Result<Expression, Error> {
         let token = self.token();
-        let node = match token.kind {
+        let position = token.position;
+        let exp = match token.kind {
             Kind::Ident => {
-                Node::Identifier {
-                    value: token.literal,
-                }
+                Expression::Identifier(Identifier {
+                    name: token.literal,
+                    position,
+                    binding: Binding::Unresolved,
+                })
             }
             Kind::Int => {
-                Node::Int(token.literal.parse()?)
+                Expression::Int(IntegerLiteral { value: token.literal.parse()?, position })
             }
             Kind::Bool => {
-                Node::Boolean(token.literal.parse()?)
+                Expression::Boolean(BooleanLiteral { value: token.literal.parse()?, position })
+            }
+            Kind::String => {
+                Expression::String(StringLiteral { value: unescape(&token.literal)?, position })
+            }
+            Kind::LeftParen => {
+                self.advance();
+                let exp = self.parse_expression(Precedence::Lowest)?;
+                self.expect(Kind::RightParen)?;
+                self.advance();
+                exp
             }
             Kind::Bang => {
                 self.advance();
-                Node::Prefix {
+                Expression::Prefix(PrefixExpression {
                     operator: Prefix::Not,
                     value: Box::new(self.parse_expression(Precedence::Prefix)?),
-                }
+                    position,
+                })
             }
             Kind::Minus => {
                 self.advance();
-                Node::Prefix {
+                Expression::Prefix(PrefixExpression {
                     operator: Prefix::Negative,
                     value: Box::new(self.parse_expression(Precedence::Prefix)?),
-                }
+                    position,
+                })
             }
             Kind::If => {
                 self.expect(Kind::LeftParen)?;
@@ -139,32 +232,32 @@ impl<Lexer> Parser<Lexer>
                 self.expect(Kind::LeftBrace)?;
                 self.advance();
                 let success = self.parse_block()?;
-                if self.expect(Kind::Else).is_ok() {
+                let fail = if self.expect(Kind::Else).is_ok() {
                     self.advance();
                     self.expect(Kind::LeftBrace)?;
                     self.advance();
-                    let failure = self.parse_block()?;
-                    Node::If {
-                        predicate: Box::new(predicate),
-                        success: Box::new(success),
-                        fail: Some(Box::new(failure)),
-                    }
+                    Some(self.parse_block()?)
                 } else {
-                    Node::If {
-                        predicate: Box::new(predicate),
-                        success: Box::new(success),
-                        fail: None,
-                    }
-                }
+                    None
+                };
+                Expression::If(IfExpression {
+                    predicate: Box::new(predicate),
+                    success,
+                    fail,
+                    position,
+                })
             }
             Kind::Function => {
                 self.expect(Kind::LeftParen)?;
                 self.advance();
-                let mut params = vec![];
+                let mut parameters = vec![];
                 while self.expect(Kind::Ident).is_ok() {
                     self.advance();
-                    params.push(Node::Identifier {
-                        value: self.token().literal,
+                    let token = self.token();
+                    parameters.push(Identifier {
+                        name: token.literal,
+                        position: token.position,
+                        binding: Binding::Unresolved,
                     });
                     if self.expect(Kind::Comma).is_err() {
                         break;
@@ -175,134 +268,111 @@ impl<Lexer> Parser<Lexer>
                 self.advance();
                 self.advance();
                 let body = self.parse_block()?;
-                Node::Function {
-                    parameters: params,
-                    body: Box::new(body),
-                }
+                Expression::Function(FunctionLiteral {
+                    parameters,
+                    body: Arc::new(body),
+                    position,
+                })
             }
             _ => {
-                return Err(format!("unexpected: {}", token.literal).into());
+                return Err(self.error(&token, "E0001", format!("unexpected: {}", describe(&token))));
             }
         };
-        Ok(node)
+        Ok(exp)
     }
 
-    fn parse_infix(&mut self, left: Node) -> Result<Node, Error> {
+    fn parse_infix(&mut self, left: Expression) -> Result<Expression, Error> {
         let token = self.token();
-        let node = match token.kind {
-            Kind::Plus => {
-                self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::Add,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
-            }
-            Kind::Minus => {
-                self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::Subtract,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
-            }
-            Kind::Slash => {
-                self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::Divide,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
-            }
-            Kind::Asterisk => {
-                self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::Multiply,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
-            }
-            Kind::Equal => {
-                self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::Eq,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
+        let operator = match token.kind {
+            Kind::Plus => Infix::Add,
+            Kind::Minus => Infix::Subtract,
+            Kind::Slash => Infix::Divide,
+            Kind::Asterisk => Infix::Multiply,
+            Kind::Equal => Infix::Eq,
+            Kind::NotEqual => Infix::NotEq,
+            Kind::ArrowLeft => Infix::LessThan,
+            Kind::ArrowRight => Infix::GreaterThan,
+            Kind::LeftParen => return self.parse_call(left),
+            _ => {
+                return Err(self.error(&token, "E0001", format!("unexpected {}", describe(&token))));
             }
-            Kind::NotEqual => {
-                self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::NotEq,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
+        };
+        self.advance();
+        // Comparisons are left associative, so the second compares the
+        // boolean result of the first, which can only fail.
+        let comparison = |operator: &Infix| matches!(operator, Infix::LessThan | Infix::GreaterThan);
+        if let Expression::Infix(first) = &left {
+            if comparison(&first.operator) && comparison(&operator) {
+                let warning = Diagnostic::warning("W0001", "comparison operators don't chain")
+                    .at(token.position)
+                    .secondary(Span { start: first.position, end: first.position }, "this comparison is worked out first, giving a boolean")
+                    .help("a boolean can't be compared with `<` or `>`, so this fails when run");
+                self.warnings.push(warning);
             }
-            Kind::ArrowLeft => {
+        }
+        Ok(Expression::Infix(InfixExpression {
+            left: Box::new(left),
+            operator,
+            right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
+            position: token.position,
+        }))
+    }
+
+    fn parse_call(&mut self, function: Expression) -> Result<Expression, Error> {
+        let position = self.token().position;
+        let mut arguments = vec![];
+        if self.expect(Kind::RightParen).is_ok() {
+            self.advance();
+        } else {
+            self.advance();
+            arguments.push(self.parse_expression(Precedence::Lowest)?);
+            while self.expect(Kind::Comma).is_ok() {
                 self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::LessThan,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
-            }
-            Kind::ArrowRight => {
                 self.advance();
-                Node::Infix {
-                    left: Box::new(left),
-                    operator: Infix::GreaterThan,
-                    right: Box::new(self.parse_expression(Precedence::from(token.kind))?),
-                }
+                arguments.push(self.parse_expression(Precedence::Lowest)?);
             }
-            Kind::LeftParen => {
-                let function = Box::new(left.clone());
-                let mut arguments = vec![];
-                if self.expect(Kind::RightParen).is_ok() {
-                    self.advance();
-                    Node::Call {
-                        function,
-                        arguments,
-                    }                    
-                } else {
-                    self.advance();
-                    arguments.push(self.parse_expression(Precedence::Lowest)?);
-                    while self.expect(Kind::Comma).is_ok() {
-                        self.advance();
-                        self.advance();
-                        arguments.push(self.parse_expression(Precedence::Lowest)?);
-                    }
-                    self.expect(Kind::RightParen)?;
-                    self.advance();
-                    Node::Call {
-                        function,
-                        arguments,
-                    }
-                }
-            }
-            _ => {
-                return Err(format!("unexpected {}", token.literal).into());
-            }
-        };
-        Ok(node)
+            self.expect(Kind::RightParen)?;
+            self.advance();
+        }
+        Ok(Expression::Call(CallExpression {
+            function: Box::new(function),
+            arguments,
+            position,
+        }))
     }
 
-    fn parse_block(&mut self) -> Result<Node, Error> {
+    fn parse_block(&mut self) -> Result<BlockStatement, Error> {
+        let span = self.open_span();
+        let position = self.token().position;
         self.advance();
         let mut statements = vec![];
         while self.token().kind != Kind::RightBrace && self.token().kind != Kind::Eof {
             statements.push(self.parse_statement()?);
             self.advance();
         }
-        let block = Node::Block(statements);
-        Ok(block)
+        self.close_span(span);
+        Ok(BlockStatement { statements, position })
+    }
+
+    // open_span starts a span at the current token, returning its index so
+    // that close_span can end it once the node is parsed.
+    fn open_span(&mut self) -> usize {
+        let start = self.token().position;
+        self.spans.push(Span { start, end: start });
+        self.spans.len() - 1
+    }
+
+    fn close_span(&mut self, index: usize) {
+        self.spans[index].end = self.token().position;
     }
 
     fn advance(&self) {
         let mut token = self.token.borrow_mut();
         let mut lexer = self.lexer.borrow_mut();
+        *self.previous.borrow_mut() = token.kind.clone();
         *token = match lexer.next() {
             Some(token) => token,
-            None => Token::new(Kind::Eof, "\0"),
+            None => Token::new(Kind::Eof, "\0").at(token.position),
         };
     }
 
@@ -311,33 +381,80 @@ impl<Lexer> Parser<Lexer>
     }
 
     fn expect(&self, kind: Kind) -> Result<Token, Error> {
-        let mut lexer = self.lexer.borrow_mut();
-        match lexer.peek() {
-            Some(t) => {
-                if t.kind == kind {
-                    Ok((*t).clone())
-                } else {
-                    Err(format!("expected {:?}, got {:?}", kind, t.kind).into())
-                }
-            },
-            None => Err(format!("expected {:?}, got {:?}", kind, Kind::Eof).into()),
+        let t = self.peek()?;
+        if t.kind == kind {
+            Ok(t)
+        } else {
+            Err(self.error(&t, "E0003", format!("expected {:?}, got {:?}", kind, t.kind)))
         }
     }
 
+    // peek returns the next token, or Eof if the input is exhausted. End of
+    // input terminates an expression just like a semicolon does.
     fn peek(&self) -> Result<Token, Error> {
         let mut lexer = self.lexer.borrow_mut();
         match lexer.peek() {
             Some(t) => Ok((*t).clone()),
-            None => Err(format!("unexpected EOF").into()),
+            None => Ok(Token::new(Kind::Eof, "\0").at(self.token.borrow().position)),
+        }
+    }
+
+    // error reports a problem with `token`. Running out of input has a code
+    // of its own, since more input could make the program valid.
+    fn error<S: Into<String>>(&self, token: &Token, code: &'static str, message: S) -> Error {
+        let code = if token.kind == Kind::Eof { "E0002" } else { code };
+        Box::new(Diagnostic::error(code, message).at(token.position))
+    }
+
+    // locate turns an error into a diagnostic, placing those that were raised
+    // without a position, such as failing to parse an integer literal, at the
+    // current token.
+    fn locate(&self, err: Error) -> Diagnostic {
+        match err.downcast::<Diagnostic>() {
+            Ok(diagnostic) => *diagnostic,
+            Err(err) => Diagnostic::error("E0004", err.to_string()).at(self.token().position),
         }
     }
 
 }
 
+// misspelt hints at the keyword meant when a run of statements that fails to
+// parse starts with an identifier spelt like one.
+fn misspelt(first: Option<&Token>, err: Error) -> Error {
+    let keyword = match first {
+        Some(first) if first.kind == Kind::Ident => util::suggest(&first.literal, KEYWORDS).map(|keyword| (first, keyword)),
+        _ => None,
+    };
+    match (keyword, err.downcast::<Diagnostic>()) {
+        (Some((first, keyword)), Ok(diagnostic)) if diagnostic.help.is_none() => {
+            Box::new(diagnostic.suggest(first.position, first.literal.as_str(), keyword))
+        },
+        (_, Ok(diagnostic)) => diagnostic,
+        (_, Err(err)) => err,
+    }
+}
+
+// describe names a token for an error message.
+fn describe(token: &Token) -> &str {
+    match token.kind {
+        Kind::Eof => "end of input",
+        _ => &token.literal,
+    }
+}
+
+/// parse reads a whole program for tests, which only want to know whether
+/// it had errors.
+#[cfg(test)]
+pub(crate) fn parse(input: &str) -> Result<Program, Box<dyn std::error::Error>> {
+    Ok(Parser::new(crate::lexer::Lexer::new(input.chars())).parse()?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::lexer::Lexer;
+    use crate::token::Position;
+    use crate::visit::strip;
 
     #[test]
     fn let_statement() {
@@ -345,12 +462,12 @@ mod tests {
             let five = 5;
             let ten = 10;
         "#;
-        let want = vec![
-            Node::Let { name: "five".to_string(), value: Box::new(Node::Int(5)) },
-            Node::Let { name: "ten".to_string(), value: Box::new(Node::Int(10)) },
+        let want: Vec<Statement> = vec![
+            LetStatement::new("five", IntegerLiteral::new(5)).into(),
+            LetStatement::new("ten", IntegerLiteral::new(10)).into(),
         ];
         let mut parser = Parser::new(Lexer::new(input.chars()));
-        let Program { statements } = parser.parse()
+        let Program { statements } = parser.parse().map(strip)
             .map_err(|err| format!("parsing: {}", err))
             .unwrap();
         assert_eq!(want.len(), statements.len());
@@ -362,28 +479,24 @@ mod tests {
         let tests = vec![
             (
                 "return a + b;",
-                Node::Return { value: Box::new(Node::Infix {
-                    left: Box::new(Node::Identifier { value: "a".into() }),
-                    operator: Infix::Add,
-                    right: Box::new(Node::Identifier { value: "b".into() }),
-                })},
+                ReturnStatement::new(InfixExpression::new(Identifier::new("a"), Infix::Add, Identifier::new("b"))),
             ),
             (
                 "return 10;",
-                Node::Return { value: Box::new(Node::Int(10))},
+                ReturnStatement::new(IntegerLiteral::new(10)),
             ),
             (
                 "return foobar;",
-                Node::Return { value: Box::new(Node::Identifier { value: "foobar".into() })},
+                ReturnStatement::new(Identifier::new("foobar")),
             ),
         ];
         for (input, want) in tests {
-            let program = Parser::new(Lexer::new(input.chars())).parse();
+            let program = Parser::new(Lexer::new(input.chars())).parse().map(strip);
             let Program { statements } = match program {
                 Ok(p) => p,
                 Err(err) => panic!("{}", err),
             };
-            assert_eq!(want, statements[0]);
+            assert_eq!(Statement::from(want), statements[0]);
         }
     }
 
@@ -393,12 +506,12 @@ mod tests {
             foo;
             5;
         "#;
-        let want = vec![
-            Node::Identifier { value: "foo".to_owned() },
-            Node::Int(5),
+        let want: Vec<Statement> = vec![
+            Identifier::new("foo").into(),
+            IntegerLiteral::new(5).into(),
         ];
         let mut parser = Parser::new(Lexer::new(input.chars()));
-        match parser.parse() {
+        match parser.parse().map(strip) {
             Ok(Program { statements }) => {
                 assert_eq!(want, statements);
             }
@@ -416,14 +529,14 @@ mod tests {
             !true;
             !false;
         "#;
-        let want = vec![
-            Node::Prefix { operator: Prefix::Not, value: Box::new(Node::Identifier { value: "foo".to_owned() } ) },
-            Node::Prefix { operator: Prefix::Negative, value: Box::new(Node::Int(5)) },
-            Node::Prefix { operator: Prefix::Not, value: Box::new(Node::Boolean(true)) },
-            Node::Prefix { operator: Prefix::Not, value: Box::new(Node::Boolean(false)) },
+        let want: Vec<Statement> = vec![
+            PrefixExpression::new(Prefix::Not, Identifier::new("foo")).into(),
+            PrefixExpression::new(Prefix::Negative, IntegerLiteral::new(5)).into(),
+            PrefixExpression::new(Prefix::Not, BooleanLiteral::new(true)).into(),
+            PrefixExpression::new(Prefix::Not, BooleanLiteral::new(false)).into(),
         ];
         let mut parser = Parser::new(Lexer::new(input.chars()));
-        match parser.parse() {
+        match parser.parse().map(strip) {
             Ok(Program { statements }) => {
                 assert_eq!(want, statements);
             }
@@ -438,97 +551,53 @@ mod tests {
         let tests = vec![
             (
                 "5 + 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::Add,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::Add, IntegerLiteral::new(5)),
             ),
             (
                 "5 - 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::Subtract,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::Subtract, IntegerLiteral::new(5)),
             ),
             (
                 "5 * 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::Multiply,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::Multiply, IntegerLiteral::new(5)),
             ),
             (
                 "5 / 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::Divide,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::Divide, IntegerLiteral::new(5)),
             ),
             (
                 "5 > 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::GreaterThan,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::GreaterThan, IntegerLiteral::new(5)),
             ),
             (
                 "5 < 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::LessThan,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::LessThan, IntegerLiteral::new(5)),
             ),
             (
                 "5 == 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::Eq,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::Eq, IntegerLiteral::new(5)),
             ),
             (
                 "5 != 5;",
-                Node::Infix {
-                    left: Box::new(Node::Int(5)),
-                    operator: Infix::NotEq,
-                    right: Box::new(Node::Int(5)),
-                },
+                InfixExpression::new(IntegerLiteral::new(5), Infix::NotEq, IntegerLiteral::new(5)),
             ),
             (
                 "true != false;",
-                Node::Infix {
-                    left: Box::new(Node::Boolean(true)),
-                    operator: Infix::NotEq,
-                    right: Box::new(Node::Boolean(false)),
-                },
+                InfixExpression::new(BooleanLiteral::new(true), Infix::NotEq, BooleanLiteral::new(false)),
             ),
             (
                 "true == true;",
-                Node::Infix {
-                    left: Box::new(Node::Boolean(true)),
-                    operator: Infix::Eq,
-                    right: Box::new(Node::Boolean(true)),
-                },
+                InfixExpression::new(BooleanLiteral::new(true), Infix::Eq, BooleanLiteral::new(true)),
             ),
             (
                 "false == false;",
-                Node::Infix {
-                    left: Box::new(Node::Boolean(false)),
-                    operator: Infix::Eq,
-                    right: Box::new(Node::Boolean(false)),
-                },
+                InfixExpression::new(BooleanLiteral::new(false), Infix::Eq, BooleanLiteral::new(false)),
             ),
         ];
         for (ii, (input, want)) in tests.into_iter().enumerate() {
-            let Program { statements } = Parser::new(Lexer::new(input.chars())).parse()
+            let Program { statements } = Parser::new(Lexer::new(input.chars())).parse().map(strip)
                 .map_err(|err| format!("{}: {}", ii, err))?;
-            assert_eq!(want, statements[0]);
+            assert_eq!(Statement::from(want), statements[0]);
         }
         Ok(())
     }
@@ -551,6 +620,11 @@ mod tests {
             ("false;", "false"),
             ("3 > 5 == false;", "((3 > 5) == false)"),
             ("3 < 5 == true;", "((3 < 5) == true)"),
+            ("1 + (2 + 3) + 4;", "((1 + (2 + 3)) + 4)"),
+            ("(5 + 5) * 2;", "((5 + 5) * 2)"),
+            ("2 / (5 + 5);", "(2 / (5 + 5))"),
+            ("-(5 + 5);", "(-(5 + 5))"),
+            ("!(true == true);", "(!(true == true))"),
         ];
         for (ii, test) in tests.iter().enumerate() {
             let program = Parser::new(Lexer::new(test.0.chars())).parse()
@@ -562,19 +636,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn literals() -> Result<(), Error> {
+        let tests = vec![
+            (r#""hello world";"#, StringLiteral::new("hello world").into()),
+            (r#""say \"hi\"\n";"#, StringLiteral::new("say \"hi\"\n").into()),
+            ("9223372036854775807;", IntegerLiteral::new(i64::MAX).into()),
+        ];
+        for (input, want) in tests {
+            let program = Parser::new(Lexer::new(input.chars())).parse().map(strip)
+                .map_err(|err| format!("{}: {}", input, err))?;
+            assert_eq!(program.statements, vec![Statement::Expression(want)]);
+        }
+        let err = Parser::new(Lexer::new("9223372036854775808;".chars())).parse();
+        assert!(err.is_err());
+        Ok(())
+    }
+
     #[test]
     fn if_expression() -> Result<(), Error> {
         let input = "if (x < y) { x };";
-        let want = Node::If {
-            predicate: Box::new(Node::Infix {
-                left: Box::new(Node::Identifier { value: "x".into() }),
-                operator: Infix::LessThan,
-                right: Box::new(Node::Identifier { value: "y".into() }),
-            }),
-            success: Box::new(Node::Block(vec![Node::Identifier {value: "x".into() }])),
-            fail: None,
-        };
-        let program = Parser::new(Lexer::new(input.chars())).parse()
+        let want: Statement = IfExpression::new(
+            InfixExpression::new(Identifier::new("x"), Infix::LessThan, Identifier::new("y")),
+            BlockStatement::new(vec![Identifier::new("x").into()]),
+            None,
+        ).into();
+        let program = Parser::new(Lexer::new(input.chars())).parse().map(strip)
             .map_err(|err| format!("parsing if statement: {}", err))?;
         assert!(program.statements.len() == 1);
         assert!(program.statements[0] == want);
@@ -584,16 +671,12 @@ mod tests {
     #[test]
     fn if_else_expression() -> Result<(), Error> {
         let input = "if (x < y) { x } else { y };";
-        let want = Node::If {
-            predicate: Box::new(Node::Infix {
-                left: Box::new(Node::Identifier { value: "x".into() }),
-                operator: Infix::LessThan,
-                right: Box::new(Node::Identifier { value: "y".into() }),
-            }),
-            success: Box::new(Node::Block(vec![Node::Identifier {value: "x".into() }])),
-            fail: Some(Box::new(Node::Block(vec![Node::Identifier {value: "y".into() }]))),
-        };
-        let program = Parser::new(Lexer::new(input.chars())).parse()
+        let want: Statement = IfExpression::new(
+            InfixExpression::new(Identifier::new("x"), Infix::LessThan, Identifier::new("y")),
+            BlockStatement::new(vec![Identifier::new("x").into()]),
+            Some(BlockStatement::new(vec![Identifier::new("y").into()])),
+        ).into();
+        let program = Parser::new(Lexer::new(input.chars())).parse().map(strip)
             .map_err(|err| format!("parsing if statement: {}", err))?;
         assert!(program.statements.len() == 1);
         assert!(program.statements[0] == want);
@@ -605,55 +688,34 @@ mod tests {
         let tests = vec![
             (
                 "fn(x, y) { return x + y; };",
-                Node::Function {
-                    parameters: vec![
-                        Node::Identifier { value: "x".into() },
-                        Node::Identifier { value: "y".into() },
-                    ],
-                    body: Box::new(Node::Block(vec![
-                        Node::Return {
-                            value: Box::new(Node::Infix {
-                                left: Box::new(Node::Identifier { value: "x".into() }),
-                                operator: Infix::Add,
-                                right: Box::new(Node::Identifier { value: "y".into() }),
-                            }),
-                        },
-                    ])),
-                }
+                FunctionLiteral::new(
+                    vec![Identifier::new("x"), Identifier::new("y")],
+                    BlockStatement::new(vec![
+                        ReturnStatement::new(InfixExpression::new(Identifier::new("x"), Infix::Add, Identifier::new("y"))).into(),
+                    ]),
+                ),
             ),
             (
                 "fn() {};",
-                Node::Function {
-                    parameters: vec![],
-                    body: Box::new(Node::Block(vec![])),
-                },
+                FunctionLiteral::new(vec![], BlockStatement::new(vec![])),
             ),
             (
                 "fn(x) {};",
-                Node::Function {
-                    parameters: vec![
-                        Node::Identifier { value: "x".into() },
-                    ],
-                    body: Box::new(Node::Block(vec![])),
-                },
+                FunctionLiteral::new(vec![Identifier::new("x")], BlockStatement::new(vec![])),
             ),
             (
                 "fn(x, y, z) {};",
-                Node::Function {
-                    parameters: vec![
-                        Node::Identifier { value: "x".into() },
-                        Node::Identifier { value: "y".into() },
-                        Node::Identifier { value: "z".into() },
-                    ],
-                    body: Box::new(Node::Block(vec![])),
-                },
+                FunctionLiteral::new(
+                    vec![Identifier::new("x"), Identifier::new("y"), Identifier::new("z")],
+                    BlockStatement::new(vec![]),
+                ),
             ),
         ];
         for (input, want) in tests {
-            let program = Parser::new(Lexer::new(input.chars())).parse()
+            let program = Parser::new(Lexer::new(input.chars())).parse().map(strip)
                 .map_err(|err| format!("parsing function literal: {}", err))?;
             assert!(program.statements.len() == 1);
-            assert!(program.statements[0] == want);
+            assert!(program.statements[0] == want.into());
         }
         Ok(())
     }
@@ -663,65 +725,162 @@ mod tests {
         let tests = vec![
             (
                 "foo();",
-                Node::Call {
-                    function: Box::new(Node::Identifier { value: "foo".into() }),
-                    arguments: vec![],
-                },
+                CallExpression::new(Identifier::new("foo"), vec![]),
             ),
             (
                 "add(1, 2);",
-                Node::Call {
-                    function: Box::new(Node::Identifier { value: "add".into() }),
-                    arguments: vec![Node::Int(1), Node::Int(2)],
-                },
+                CallExpression::new(Identifier::new("add"), vec![IntegerLiteral::new(1).into(), IntegerLiteral::new(2).into()]),
             ),
             (
                 "add(1, fn() { return 1; });",
-                Node::Call {
-                    function: Box::new(Node::Identifier { value: "add".into() }),
-                    arguments: vec![
-                        Node::Int(1),
-                        Node::Function { 
-                            parameters: vec![],
-                            body: Box::new(Node::Block(vec![Node::Return { value: Box::new(Node::Int(1)) }])),
-                        },
-                    ],
-                },
+                CallExpression::new(Identifier::new("add"), vec![
+                    IntegerLiteral::new(1).into(),
+                    FunctionLiteral::new(vec![], BlockStatement::new(vec![
+                        ReturnStatement::new(IntegerLiteral::new(1)).into(),
+                    ])).into(),
+                ]),
             ),
             (
                 "fn(a, b) { return a + b; }(1, 2);",
-                Node::Call {
-                    function: Box::new(Node::Function {
-                        parameters: vec![
-                            Node::Identifier { value: "a".into() },
-                            Node::Identifier { value: "b".into() },
-                        ],
-                        body: Box::new(Node::Block(vec![Node::Return {
-                            value: Box::new(Node::Infix { 
-                                left: Box::new(Node::Identifier { value: "a".into() }),
-                                operator: Infix::Add,
-                                right: Box::new(Node::Identifier { value: "b".into() }),
-                            }),
-                        }]))
-                    }),
-                    arguments: vec![
-                        Node::Int(1),
-                        Node::Int(2),
-                    ],
-                },
+                CallExpression::new(
+                    FunctionLiteral::new(
+                        vec![Identifier::new("a"), Identifier::new("b")],
+                        BlockStatement::new(vec![
+                            ReturnStatement::new(InfixExpression::new(Identifier::new("a"), Infix::Add, Identifier::new("b"))).into(),
+                        ]),
+                    ),
+                    vec![IntegerLiteral::new(1).into(), IntegerLiteral::new(2).into()],
+                ),
             ),
         ];
         for (input, want) in tests {
-            let program = Parser::new(Lexer::new(input.chars())).parse()
+            let program = Parser::new(Lexer::new(input.chars())).parse().map(strip)
                 .map_err(|err| format!("parsing function call: {}", err));
             let program = match program {
                 Ok(p) => p,
                 Err(err) => panic!("{}", err),
             };
             assert!(program.statements.len() == 1);
-            assert!(program.statements[0] == want);
+            assert!(program.statements[0] == want.into());
         }
         Ok(())
     }
 
-}
\ No newline at end of file
+    // Nodes record where they start, except infix expressions and calls,
+    // which record their operator and opening parenthesis.
+    #[test]
+    fn errors() {
+        let tests = vec![
+            ("let = 1;", vec!["error E0003 1:5: expected Ident, got Assign", "error E0001 1:5: unexpected: ="]),
+            ("f(1", vec!["error E0002 1:3: expected RightParen, got Eof"]),
+            ("99999999999999999999", vec!["error E0004 1:1: number too large to fit in target type"]),
+            ("1 < 2 > 3; let", vec!["warning W0001 1:7: comparison operators don't chain", "error E0002 1:12: expected Ident, got Eof"]),
+        ];
+        for (input, want) in tests {
+            match Parser::new(Lexer::new(input.chars())).parse() {
+                Ok(program) => panic!("{}: expected errors, got {:?}", input, program),
+                Err(diagnostics) => {
+                    let got: Vec<String> = diagnostics
+                        .iter()
+                        .map(|d| format!("{} {} {}", d.severity, d.code, d))
+                        .collect();
+                    assert_eq!(got, want, "{}", input);
+                },
+            }
+        }
+    }
+
+    // A statement that fails to parse after starting with a misspelt keyword
+    // suggests the keyword.
+    #[test]
+    fn misspelt() {
+        let tests = vec![
+            ("lett x = 1;", Some("did you mean `let`?")),
+            ("fn() { retrun x + }", Some("did you mean `return`?")),
+            ("iff (x) { 1 }", Some("did you mean `if`?")),
+            ("x = 1;", None),
+        ];
+        for (input, want) in tests {
+            match Parser::new(Lexer::new(input.chars())).parse() {
+                Ok(program) => panic!("{}: expected errors, got {:?}", input, program),
+                Err(diagnostics) => {
+                    let help: Vec<Option<&str>> = diagnostics.iter().map(|d| d.help.as_deref()).collect();
+                    assert_eq!(help[0], want, "{}", input);
+                },
+            }
+        }
+    }
+
+    // Expressions nested past MAX_NESTING are reported once, however deep
+    // they go.
+    #[test]
+    fn nesting() -> Result<(), Error> {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        parse(&nested(MAX_NESTING - 1))?;
+        match Parser::new(Lexer::new(nested(5000).chars())).parse() {
+            Ok(_) => panic!("expected an error"),
+            Err(diagnostics) => {
+                let got: Vec<String> = diagnostics.iter().map(|d| format!("{} {}", d.code, d)).collect();
+                assert_eq!(got, vec![format!("E0008 1:{}: expression nested more than {} deep", MAX_NESTING + 1, MAX_NESTING)]);
+            },
+        }
+        Ok(())
+    }
+
+    // Warnings alone don't stop the program from parsing.
+    #[test]
+    fn warnings() -> Result<(), Error> {
+        let mut parser = Parser::new(Lexer::new("a < b < c; a < b == true".chars()));
+        let program = parser.parse()?;
+        assert_eq!(program.statements.len(), 2);
+        let warnings: Vec<&Diagnostic> = parser.warnings().iter().collect();
+        match warnings.as_slice() {
+            [warning] => {
+                assert_eq!((warning.code, warning.to_string()), ("W0001", "1:7: comparison operators don't chain".into()));
+                assert_eq!(warning.labels.iter().filter(|label| !label.primary).count(), 1);
+            },
+            other => panic!("unexpected warnings: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn positions() -> Result<(), Error> {
+        let program = Parser::new(Lexer::new("let f = fn(x) { x * 2 };\nf(1)".chars())).parse()?;
+        let at = |position: Position| (position.line, position.column);
+        let (stmt, call) = match program.statements.as_slice() {
+            [Statement::Let(stmt), Statement::Expression(call)] => (stmt, call),
+            other => panic!("unexpected statements: {:?}", other),
+        };
+        assert_eq!(at(stmt.position), (1, 1));
+        assert_eq!(at(stmt.name.position), (1, 5));
+        let function = match &stmt.value {
+            Expression::Function(function) => function,
+            other => panic!("not a function: {:?}", other),
+        };
+        assert_eq!(at(function.position), (1, 9));
+        assert_eq!(at(function.parameters[0].position), (1, 12));
+        assert_eq!(at(function.body.position), (1, 15));
+        match &function.body.statements[0] {
+            Statement::Expression(exp) => assert_eq!(at(exp.position()), (1, 19)),
+            other => panic!("not an expression: {:?}", other),
+        }
+        assert_eq!(at(call.position()), (2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn spans() -> Result<(), Error> {
+        let input = "let f = fn(x) {\n  x * 2\n};\nf(1)";
+        let mut parser = Parser::new(Lexer::new(input.chars()));
+        parser.parse()?;
+        let got: Vec<(usize, usize, usize, usize)> = parser.spans()
+            .iter()
+            .map(|s| (s.start.line, s.start.column, s.end.line, s.end.column))
+            .collect();
+        // The let statement, the function body, `x * 2` and the call.
+        let want = vec![(1, 1, 3, 2), (1, 15, 3, 1), (2, 3, 2, 7), (4, 1, 4, 4)];
+        assert_eq!(want, got);
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use crate::ast::{Program, Statement, BlockStatement, Expression, Identifier, Precedence};
+
+/// print renders the program as canonical Monkey source: one statement per
+/// line, each terminated with a semicolon, blocks indented by four spaces and
+/// only the parentheses that precedence requires. Parsing the output gives
+/// back the same program.
+//
+// Not every tree has a source form. Negative integer literals print as a
+// prefix expression, which parses as `Prefix::Negative` applied to a positive
+// literal.
+//
+pub fn print(program: &Program) -> String {
+    let mut printer = Printer::new();
+    for stmt in &program.statements {
+        printer.statement(stmt);
+    }
+    printer.out
+}
+
+/// print_expression renders a single expression. Blocks within it span
+/// several lines.
+pub fn print_expression(exp: &Expression) -> String {
+    let mut printer = Printer::new();
+    printer.expression(exp);
+    printer.out
+}
+
+struct Printer {
+    out: String,
+    depth: usize,
+}
+
+impl Printer {
+    fn new() -> Printer {
+        Printer { out: String::new(), depth: 0 }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        self.out.push_str(&"    ".repeat(self.depth));
+        match stmt {
+            Statement::Let(stmt) => {
+                self.out.push_str("let ");
+                self.out.push_str(&stmt.name.name);
+                self.out.push_str(" = ");
+                self.expression(&stmt.value);
+            },
+            Statement::Return(stmt) => {
+                self.out.push_str("return ");
+                self.expression(&stmt.value);
+            },
+            Statement::Expression(exp) => self.expression(exp),
+        }
+        self.out.push_str(";\n");
+    }
+
+    fn expression(&mut self, exp: &Expression) {
+        match exp {
+            Expression::Int(n) => self.out.push_str(&n.value.to_string()),
+            Expression::String(s) => self.out.push_str(&quote(&s.value)),
+            Expression::Boolean(b) => self.out.push_str(&b.value.to_string()),
+            Expression::Identifier(identifier) => self.out.push_str(&identifier.name),
+            Expression::Prefix(exp) => {
+                self.out.push_str(&exp.operator.to_string());
+                self.operand(&exp.value, Precedence::Prefix, false);
+            },
+            Expression::Infix(exp) => {
+                let precedence = exp.operator.precedence();
+                self.operand(&exp.left, precedence.clone(), false);
+                self.out.push_str(&format!(" {} ", exp.operator));
+                self.operand(&exp.right, precedence, true);
+            },
+            Expression::If(exp) => {
+                self.out.push_str("if (");
+                self.expression(&exp.predicate);
+                self.out.push_str(") ");
+                self.block(&exp.success);
+                if let Some(fail) = &exp.fail {
+                    self.out.push_str(" else ");
+                    self.block(fail);
+                }
+            },
+            Expression::Function(exp) => {
+                self.out.push_str("fn(");
+                self.out.push_str(&parameters(&exp.parameters));
+                self.out.push_str(") ");
+                self.block(&exp.body);
+            },
+            Expression::Call(exp) => {
+                if parenthesize_callee(&exp.function) {
+                    self.grouped(&exp.function);
+                } else {
+                    self.expression(&exp.function);
+                }
+                self.out.push('(');
+                for (ii, argument) in exp.arguments.iter().enumerate() {
+                    if ii > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expression(argument);
+                }
+                self.out.push(')');
+            },
+        }
+    }
+
+    // operand prints an expression that an operator of the given precedence
+    // applies to, parenthesised if it would otherwise bind differently.
+    fn operand(&mut self, exp: &Expression, outer: Precedence, right: bool) {
+        if parenthesize(exp, &outer, right) {
+            self.grouped(exp);
+        } else {
+            self.expression(exp);
+        }
+    }
+
+    fn grouped(&mut self, exp: &Expression) {
+        self.out.push('(');
+        self.expression(exp);
+        self.out.push(')');
+    }
+
+    fn block(&mut self, block: &BlockStatement) {
+        if block.statements.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{\n");
+        self.depth += 1;
+        for stmt in &block.statements {
+            self.statement(stmt);
+        }
+        self.depth -= 1;
+        self.out.push_str(&"    ".repeat(self.depth));
+        self.out.push('}');
+    }
+}
+
+/// parameters lists a function's parameter names separated by commas.
+pub fn parameters(parameters: &[Identifier]) -> String {
+    parameters.iter().map(|p| p.name.as_str()).collect::<Vec<&str>>().join(", ")
+}
+
+/// parenthesize reports whether `operand` needs parentheses as an operand of
+/// an operator with the given precedence. Operators are left associative, so
+/// a right operand also needs them when its precedence is equal.
+pub fn parenthesize(operand: &Expression, outer: &Precedence, right: bool) -> bool {
+    let inner = match operand {
+        Expression::Infix(exp) => exp.operator.precedence(),
+        Expression::Prefix(_) => Precedence::Prefix,
+        Expression::Int(n) if n.value < 0 => Precedence::Prefix,
+        _ => Precedence::Call,
+    };
+    inner < *outer || (right && inner == *outer)
+}
+
+/// parenthesize_callee reports whether the function being called needs
+/// parentheses.
+pub fn parenthesize_callee(function: &Expression) -> bool {
+    !matches!(function, Expression::Identifier(_) | Expression::Function(_) | Expression::Call(_))
+}
+
+/// quote writes `s` as a string literal that the lexer reads back as `s`.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// arbitrary generates syntax trees for property tests.
+#[cfg(test)]
+pub mod arbitrary {
+    use crate::ast::{
+        Program, Statement, LetStatement, ReturnStatement, BlockStatement, Expression, Identifier,
+        IntegerLiteral, StringLiteral, BooleanLiteral, PrefixExpression, InfixExpression,
+        IfExpression, FunctionLiteral, CallExpression, Prefix, Infix,
+    };
+    use crate::lexer::KEYWORDS;
+    use proptest::prelude::*;
+
+    fn identifier() -> impl Strategy<Value = String> {
+        "[a-z]{1,6}".prop_filter("keyword", |s| !KEYWORDS.contains(&s.as_str()))
+    }
+
+    fn prefix() -> impl Strategy<Value = Prefix> {
+        prop_oneof![Just(Prefix::Not), Just(Prefix::Negative)]
+    }
+
+    fn infix() -> impl Strategy<Value = Infix> {
+        prop_oneof![
+            Just(Infix::Eq), Just(Infix::NotEq), Just(Infix::LessThan), Just(Infix::GreaterThan),
+            Just(Infix::Add), Just(Infix::Subtract), Just(Infix::Divide), Just(Infix::Multiply),
+        ]
+    }
+
+    // expression generates the trees that the parser can produce for an
+    // expression. Integer literals are never negative since the grammar has
+    // no negative literals.
+    fn expression() -> BoxedStrategy<Expression> {
+        let leaf = prop_oneof![
+            (0..=i64::MAX).prop_map(|n| IntegerLiteral::new(n).into()),
+            any::<bool>().prop_map(|b| BooleanLiteral::new(b).into()),
+            any::<String>().prop_map(|s| StringLiteral::new(s).into()),
+            identifier().prop_map(|name| Identifier::new(name).into()),
+        ];
+        leaf.prop_recursive(4, 48, 4, |inner| {
+            let block = prop::collection::vec(statement(inner.clone()), 0..3).prop_map(BlockStatement::new);
+            prop_oneof![
+                (prefix(), inner.clone()).prop_map(|(operator, value)| {
+                    PrefixExpression::new(operator, value).into()
+                }),
+                (inner.clone(), infix(), inner.clone()).prop_map(|(left, operator, right)| {
+                    InfixExpression::new(left, operator, right).into()
+                }),
+                (inner.clone(), block.clone(), prop::option::of(block.clone())).prop_map(|(predicate, success, fail)| {
+                    IfExpression::new(predicate, success, fail).into()
+                }),
+                (prop::collection::vec(identifier(), 0..3), block).prop_map(|(parameters, body)| {
+                    FunctionLiteral::new(parameters.into_iter().map(Identifier::new).collect(), body).into()
+                }),
+                (inner.clone(), prop::collection::vec(inner, 0..3)).prop_map(|(function, arguments)| {
+                    CallExpression::new(function, arguments).into()
+                }),
+            ]
+        }).boxed()
+    }
+
+    fn statement(expression: BoxedStrategy<Expression>) -> BoxedStrategy<Statement> {
+        prop_oneof![
+            (identifier(), expression.clone()).prop_map(|(name, value)| LetStatement::new(name, value).into()),
+            expression.clone().prop_map(|value| ReturnStatement::new(value).into()),
+            expression.prop_map(Statement::Expression),
+        ].boxed()
+    }
+
+    /// program generates programs that the parser could have produced.
+    pub fn program() -> impl Strategy<Value = Program> {
+        prop::collection::vec(statement(expression()), 0..4).prop_map(Program::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::arbitrary::program;
+    use crate::ast::{Prefix, Infix, IntegerLiteral, PrefixExpression, InfixExpression};
+    use crate::parser::parse;
+    use crate::visit::strip;
+    use proptest::prelude::*;
+
+    type Error = Box<dyn std::error::Error>;
+
+    #[test]
+    fn canonical() -> Result<(), Error> {
+        let tests = vec![
+            ("let x = 5", "let x = 5;\n"),
+            ("a+b*c;(a+b)*c;a-(b-c);(a-b)-c", "a + b * c;\n(a + b) * c;\na - (b - c);\na - b - c;\n"),
+            ("-(a+b);!-a;-f(x);(-f)(x)", "-(a + b);\n!-a;\n-f(x);\n(-f)(x);\n"),
+            (
+                "let max = fn(a, b) { if (a > b) { return a; } else { b } };",
+                "let max = fn(a, b) {\n    if (a > b) {\n        return a;\n    } else {\n        b;\n    };\n};\n",
+            ),
+            ("fn(){}(); f(1, g(2))(3)", "fn() {}();\nf(1, g(2))(3);\n"),
+            (r#"puts("say \"hi\"\n")"#, "puts(\"say \\\"hi\\\"\\n\");\n"),
+        ];
+        for (input, want) in tests {
+            assert_eq!(print(&parse(input)?), want, "{}", input);
+        }
+        Ok(())
+    }
+
+    // Operands of operators print parenthesised whenever the parser would
+    // otherwise group them differently.
+    #[test]
+    fn constructed() -> Result<(), Error> {
+        let exp = PrefixExpression::new(
+            Prefix::Negative,
+            InfixExpression::new(IntegerLiteral::new(1), Infix::Eq, IntegerLiteral::new(-2)),
+        );
+        assert_eq!(print_expression(&exp.into()), "-(1 == -2)");
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(program in program()) {
+            let source = print(&program);
+            let parsed = parse(&source).map_err(|err| TestCaseError::fail(format!("{}\n{}", err, source)))?;
+            prop_assert_eq!(&strip(parsed.clone()).statements, &program.statements, "{}", source);
+            prop_assert_eq!(print(&parsed), source);
+        }
+    }
+}
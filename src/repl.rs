@@ -1,37 +1,532 @@
+use crate::ast::Binding;
 use crate::lexer::Lexer;
 use crate::parser::Parser;
+use crate::resolver;
+use crate::token::Kind;
+use crate::evaluator::Evaluator;
+use crate::object::{Object, Env};
+use crate::tree;
+use crate::highlight;
+use crate::util::{Diagnostic, Diagnostics};
 
 use std::io::prelude::*;
-use std::error::Error;
+use std::io;
+use std::fs;
 use colored::*;
 
-const PROMPT: &'static str = ">>";
+const PROMPT: &str = ">>";
+const CONTINUE: &str = "..";
 
-pub fn start<R, W>(r: &mut R, w: &mut W) 
-    where R: BufRead, W: Write,
-{
-    let mut line = String::new();
-    loop {
-        line.clear();
-        if let Err(err) = input(r, w, &mut line) {
-            println!("{}: {}", "input".red(), err);
+const HELP: &str = "\
+:help           show this message
+:quit           end the session (as does end of input)
+:tokens <src>   print the tokens of <src>
+:ast <src>      print the syntax tree of <src>
+:load <file>    evaluate a file in this session
+:env            list the bindings in this session
+:reset          forget every binding and the session history
+:save <file>    write the accepted input of this session to <file>
+";
+
+/// COMMANDS are the names of the colon commands understood by `Meta::parse`.
+pub const COMMANDS: [&str; 10] = [
+    ":help", ":quit", ":tokens", ":ast", ":load", ":env", ":reset", ":save", ":q", ":h",
+];
+
+/// Meta is a REPL command, entered with a leading colon, that acts on the
+/// session rather than being evaluated as Monkey code.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Meta {
+    Quit,
+    Help,
+    Tokens(String),
+    Ast(String),
+    Load(String),
+    Env,
+    Reset,
+    Save(String),
+}
+
+impl Meta {
+    pub fn parse(line: &str) -> Result<Meta, String> {
+        let line = line.trim();
+        let (name, arg) = match line.find(char::is_whitespace) {
+            Some(ii) => (&line[..ii], line[ii..].trim()),
+            None => (line, ""),
         };
-        match Parser::new(Lexer::new(line.chars())).parse() {
-            Ok(program) => println!("{}", program),
-            Err(errors) => {
-                for err in errors.into_iter() {
-                    println!("{}: {}", "error".red(), err);
-                }
+        let meta = match name {
+            ":quit" | ":q" => Meta::Quit,
+            ":help" | ":h" => Meta::Help,
+            ":tokens" => Meta::Tokens(arg.to_string()),
+            ":ast" => Meta::Ast(arg.to_string()),
+            ":load" if arg.is_empty() => return Err(":load: missing file".into()),
+            ":load" => Meta::Load(arg.to_string()),
+            ":env" => Meta::Env,
+            ":reset" => Meta::Reset,
+            ":save" if arg.is_empty() => return Err(":save: missing file".into()),
+            ":save" => Meta::Save(arg.to_string()),
+            _ => return Err(format!("unknown command {}, try :help", name)),
+        };
+        Ok(meta)
+    }
+}
+
+/// Session is the state kept for the lifetime of a REPL: the evaluator, so
+/// that bindings persist from one input to the next, and the history of
+/// input that was accepted, so that the session can be replayed.
+pub struct Session {
+    evaluator: Evaluator,
+    history: Vec<String>,
+    // served is set for sessions run for a network client, which must not
+    // read or write files on the server.
+    served: bool,
+    // shared is set when the globals belong to other sessions too.
+    shared: bool,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            evaluator: Evaluator::new(),
+            history: vec![],
+            served: false,
+            shared: false,
+        }
+    }
+
+    /// shared creates a session whose globals live in `env`, so that they
+    /// are visible to every other session sharing it.
+    pub fn shared(env: Env) -> Session {
+        Session {
+            evaluator: Evaluator::with_env(env),
+            history: vec![],
+            served: false,
+            shared: true,
+        }
+    }
+
+    /// served marks the session as run for a network client, which turns off
+    /// the commands that read and write files, and `:reset` if the globals
+    /// are shared with other clients.
+    pub fn served(mut self) -> Session {
+        self.served = true;
+        self
+    }
+
+    /// eval parses, resolves and evaluates `source`, writing its value or errors to
+    /// `w`. Input that evaluates without error is added to the history. Input
+    /// that fails part way has the globals it bound put back, so that the
+    /// session stays as replaying its history would leave it.
+    pub fn eval(&mut self, source: &str, w: &mut dyn Write) -> io::Result<bool> {
+        let mut parser = Parser::new(Lexer::new(source.chars()));
+        let mut program = match parser.parse() {
+            Ok(program) => program,
+            Err(diagnostics) => {
+                report(&diagnostics, w)?;
+                return Ok(false);
             },
         };
+        report(parser.warnings(), w)?;
+        if let Err(errors) = self.evaluator.resolve(&mut program) {
+            report(&errors, w)?;
+            return Ok(false);
+        }
+        let env = self.evaluator.env();
+        let bound: Vec<(usize, Option<Object>)> = resolver::lets(&program.statements)
+            .iter()
+            .filter_map(|name| match name.binding {
+                Binding::Global(slot) => Some((slot, env.load(slot))),
+                _ => None,
+            })
+            .collect();
+        match self.evaluator.eval(&program, w) {
+            Ok(value) => {
+                if !matches!(value, Object::Null) {
+                    writeln!(w, "{}", highlight::object(&value))?;
+                }
+                let source = source.trim();
+                if !source.is_empty() {
+                    self.history.push(source.to_string());
+                }
+                Ok(true)
+            },
+            Err(err) => {
+                for (slot, value) in bound.into_iter().rev() {
+                    match value {
+                        Some(value) => env.store(slot, value),
+                        None => env.unset(slot),
+                    }
+                }
+                match err.downcast_ref::<Diagnostic>() {
+                    Some(diagnostic) => write!(w, "{}", diagnostic.human(None))?,
+                    None => writeln!(w, "{}: {}", "error".red(), err)?,
+                }
+                Ok(false)
+            },
+        }
+    }
+
+    /// meta runs a colon command, writing any output to `w`.
+    pub fn meta(&mut self, meta: Meta, w: &mut dyn Write) -> io::Result<()> {
+        match meta {
+            Meta::Quit => {},
+            Meta::Help => write!(w, "{}", HELP)?,
+            Meta::Tokens(source) => {
+                for token in Lexer::new(source.chars()) {
+                    writeln!(w, "{:?} {}", token.kind, token.literal)?;
+                }
+            },
+            Meta::Ast(source) => {
+                match Parser::new(Lexer::new(source.chars())).parse() {
+                    Ok(program) => write!(w, "{}", tree::render(&program))?,
+                    Err(diagnostics) => report(&diagnostics, w)?,
+                }
+            },
+            Meta::Load(_) | Meta::Save(_) if self.served => {
+                let name = if matches!(meta, Meta::Load(_)) { ":load" } else { ":save" };
+                writeln!(w, "{}: {}: not available over serve", "error".red(), name)?;
+            },
+            Meta::Load(path) => {
+                match fs::read_to_string(&path) {
+                    Ok(source) => { self.eval(&source, w)?; },
+                    Err(err) => writeln!(w, "{}: {}: {}", "error".red(), path, err)?,
+                }
+            },
+            Meta::Env => {
+                for (name, value) in self.evaluator.env().bindings() {
+                    writeln!(w, "{} = {}", name, value)?;
+                }
+            },
+            Meta::Reset if self.served && self.shared => {
+                writeln!(w, "{}: :reset: the globals are shared with other clients", "error".red())?;
+            },
+            Meta::Reset => {
+                self.evaluator.reset();
+                self.history.clear();
+            },
+            Meta::Save(path) => {
+                if let Err(err) = fs::write(&path, self.script()) {
+                    writeln!(w, "{}: {}: {}", "error".red(), path, err)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// run reads and evaluates input until it ends or the user quits,
+    /// writing all output to `w`. Bindings persist from one entry to the
+    /// next.
+    ///
+    /// Input that is incomplete, such as a function body missing its closing
+    /// brace, is continued on the next line. An empty line submits whatever
+    /// has been entered so far.
+    pub fn run<I, W>(&mut self, input: &mut I, w: &mut W) -> io::Result<()>
+        where I: Input, W: Write,
+    {
+        let mut buffer = String::new();
+        loop {
+            let prompt = if buffer.is_empty() { PROMPT } else { CONTINUE };
+            let mut line = match input.read(prompt, w) {
+                Ok(Some(line)) => line,
+                Ok(None) => {
+                    if !buffer.is_empty() {
+                        self.eval(&buffer, w)?;
+                    }
+                    writeln!(w)?;
+                    return w.flush();
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+                    buffer.clear();
+                    continue;
+                },
+                Err(err) => return Err(err),
+            };
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                match Meta::parse(&line) {
+                    Ok(Meta::Quit) => return w.flush(),
+                    Ok(meta) => self.meta(meta, w)?,
+                    Err(err) => writeln!(w, "{}: {}", "error".red(), err)?,
+                }
+                input.accepted(line.trim_end(), self);
+                continue;
+            }
+            let submit = line.trim().is_empty();
+            buffer.push_str(&line);
+            if !submit && incomplete(&buffer) {
+                continue;
+            }
+            self.eval(&buffer, w)?;
+            if !buffer.trim().is_empty() {
+                input.accepted(buffer.trim_end(), self);
+            }
+            buffer.clear();
+        }
+    }
+
+    /// names lists the names bound in this session.
+    pub fn names(&self) -> Vec<String> {
+        self.evaluator.env().bindings()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
     }
+
+    /// script is the accepted history as a Monkey script which, when run,
+    /// rebuilds the bindings of this session.
+    pub fn script(&self) -> String {
+        self.history
+            .iter()
+            .map(|source| format!("{}\n", source))
+            .collect()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+/// Input is a source of lines for the REPL.
+pub trait Input {
+    /// read shows the prompt and returns the next line of input, or None once
+    /// the input has ended. An `Interrupted` error abandons the current entry.
+    /// Inputs that don't draw their own prompt write it to `w`.
+    fn read(&mut self, prompt: &str, w: &mut dyn Write) -> io::Result<Option<String>>;
+
+    /// accepted is told about every complete entry, after it has been run in
+    /// the session.
+    fn accepted(&mut self, _entry: &str, _session: &Session) {}
+}
+
+/// Lines reads input with `read_line`, for when the REPL is not attached to
+/// a terminal.
+pub struct Lines<'a, R> {
+    r: &'a mut R,
 }
 
-// Display prompt and read line of input.
-fn input<R, W>(r: &mut R, w: &mut W, line_buffer: &mut String) -> Result<(), Box<dyn Error>>
+impl<'a, R: BufRead> Lines<'a, R> {
+    pub fn new(r: &'a mut R) -> Lines<'a, R> {
+        Lines { r }
+    }
+}
+
+impl<'a, R: BufRead> Input for Lines<'a, R> {
+    fn read(&mut self, prompt: &str, w: &mut dyn Write) -> io::Result<Option<String>> {
+        write!(w, "{} ", prompt)?;
+        w.flush()?;
+        let mut line = String::new();
+        match self.r.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+}
+
+/// start runs a session on plain lines read from `r`. All output, prompts
+/// included, is written to `w`.
+pub fn start<R, W>(r: &mut R, w: &mut W) -> io::Result<()>
     where R: BufRead, W: Write,
 {
-    write!(w, "{} ", PROMPT)?; w.flush()?;
-    r.read_line(line_buffer)?;
+    run(&mut Lines { r }, w)
+}
+
+/// run runs a new session until the input ends or the user quits, writing
+/// all output to `w`.
+pub fn run<I, W>(input: &mut I, w: &mut W) -> io::Result<()>
+    where I: Input, W: Write,
+{
+    Session::new().run(input, w)
+}
+
+// report writes each diagnostic, with its help.
+fn report(diagnostics: &Diagnostics, w: &mut dyn Write) -> io::Result<()> {
+    for diagnostic in diagnostics {
+        write!(w, "{}", diagnostic.human(None))?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// incomplete reports whether `source` ends part way through a statement:
+/// brackets are left open, it ends with an operator, or the parser ran out of
+/// input.
+pub fn incomplete(source: &str) -> bool {
+    let mut depth = 0;
+    let mut last = None;
+    for token in Lexer::new(source.chars()) {
+        match token.kind {
+            Kind::LeftParen | Kind::LeftBrace => depth += 1,
+            Kind::RightParen | Kind::RightBrace => depth -= 1,
+            _ => {},
+        }
+        last = Some(token.kind);
+    }
+    if depth > 0 {
+        return true;
+    }
+    match last {
+        Some(Kind::Assign) | Some(Kind::Plus) | Some(Kind::Minus) | Some(Kind::Asterisk)
+        | Some(Kind::Slash) | Some(Kind::Bang) | Some(Kind::Comma) | Some(Kind::ArrowLeft)
+        | Some(Kind::ArrowRight) | Some(Kind::Equal) | Some(Kind::NotEqual) => return true,
+        _ => {},
+    }
+    match Parser::new(Lexer::new(source.chars())).parse() {
+        Ok(_) => false,
+        // E0002 is running out of input.
+        Err(errors) => errors.iter().any(|err| err.code == "E0002"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::strip_ansi;
+
+    // transcript runs a session over `input` and returns everything it wrote.
+    fn transcript(input: &str) -> String {
+        let mut out = vec![];
+        start(&mut input.as_bytes(), &mut out).unwrap();
+        strip_ansi(&String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn transcripts() {
+        let tests = vec![
+            (
+                "let x = 5;\nx * 2\nputs(x, true)\n",
+                ">> >> 10\n>> 5\ntrue\n>> \n",
+            ),
+            (
+                "let max = fn(a, b) {\n  if (a > b) { a } else { b }\n};\nmax(3, 7)\n",
+                ">> .. .. >> 7\n>> \n",
+            ),
+            (
+                "1 < 2 < 3\n",
+                ">> 1:7: warning[W0001]: comparison operators don't chain\n\
+                 1:3: note: this comparison is worked out first, giving a boolean\n\
+                 \x20 help: a boolean can't be compared with `<` or `>`, so this fails when run\n\
+                 1:7: error[E0101]: type mismatch: BOOLEAN < INTEGER\n>> \n",
+            ),
+            (
+                "1 +\n\nlet = 1;\nnope\n5 / 0\n",
+                ">> .. 1:3: error[E0002]: unexpected: end of input\n>> 1:5: error[E0003]: expected Ident, got Assign\n\
+                 1:5: error[E0001]: unexpected: =\n>> 1:1: error[E0005]: identifier not found: nope\n\
+                 >> 1:3: error[E0104]: division by zero\n>> \n",
+            ),
+            (
+                "let add = fn(a, b) { a + b }; let twice = fn(x) { add(x, true) }; twice(1)\n",
+                ">> 1:24: error[E0101]: type mismatch: INTEGER + BOOLEAN\n\
+                 \x20 traceback, innermost call first:\n\
+                 \x20   1:24: in `add`\n\
+                 \x20   1:51: in `twice`\n\
+                 \x20   1:67: at the top level\n>> \n",
+            ),
+            (
+                "let a = 1;\n:env\n:reset\n:env\n:tokens a+1\n:ast -a\n:frob\n:quit\n1\n",
+                ">> >> a = 1\n>> >> >> Ident a\nPlus +\nInt 1\n>> Program\n  Prefix -\n    value: Identifier a\n\
+                 >> error: unknown command :frob, try :help\n>> ",
+            ),
+            (
+                "if (true) {\n  10",
+                ">> .. .. 10\n\n",
+            ),
+        ];
+        for (input, want) in tests {
+            assert_eq!(transcript(input), want, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn meta() {
+        let tests = vec![
+            (":quit", Ok(Meta::Quit)),
+            (":q\n", Ok(Meta::Quit)),
+            (":help", Ok(Meta::Help)),
+            (":h", Ok(Meta::Help)),
+            (":tokens let x = 1;", Ok(Meta::Tokens("let x = 1;".into()))),
+            (":ast  1 + 2 ", Ok(Meta::Ast("1 + 2".into()))),
+            (":load scripts/fib.monkey", Ok(Meta::Load("scripts/fib.monkey".into()))),
+            (":load", Err(":load: missing file".to_string())),
+            (":env", Ok(Meta::Env)),
+            (":reset", Ok(Meta::Reset)),
+            (":save session.monkey", Ok(Meta::Save("session.monkey".into()))),
+            (":save", Err(":save: missing file".to_string())),
+            (":frob", Err("unknown command :frob, try :help".to_string())),
+        ];
+        for (line, want) in tests {
+            assert_eq!(Meta::parse(line), want, "{}", line);
+        }
+        for name in COMMANDS.iter() {
+            if let Err(err) = Meta::parse(&format!("{} arg", name)) {
+                assert!(!err.starts_with("unknown command"), "{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn session() {
+        let mut w = io::sink();
+        let mut session = Session::new();
+        assert!(session.eval("let x = 5;", &mut w).unwrap());
+        assert!(session.eval("let double = fn(n) {\n  n * 2\n};\n", &mut w).unwrap());
+        assert!(!session.eval("let y = x + true;", &mut w).unwrap());
+        assert!(!session.eval("let = 1;", &mut w).unwrap());
+        assert!(session.eval("let y = double(x);", &mut w).unwrap());
+        let bindings: Vec<String> = session.evaluator.env().bindings()
+            .into_iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        assert_eq!(bindings, vec!["double = fn(n) {(n * 2)}", "x = 5", "y = 10"]);
+        let script = session.script();
+        assert_eq!(script, "let x = 5;\nlet double = fn(n) {\n  n * 2\n};\nlet y = double(x);\n");
+
+        let mut replay = Session::new();
+        assert!(replay.eval(&script, &mut w).unwrap());
+        assert_eq!(replay.evaluator.env().get("y").map(|y| y.to_string()), Some("10".into()));
+
+        session.meta(Meta::Reset, &mut w).unwrap();
+        assert!(session.evaluator.env().bindings().is_empty());
+        assert_eq!(session.script(), "");
+    }
+
+    // An entry that fails part way is undone, so saving after it and
+    // replaying the file rebuilds the same bindings.
+    #[test]
+    fn save_after_failure() {
+        let path = std::env::temp_dir().join(format!("monkey-test-{}.monkey", std::process::id()));
+        let input = format!("let a = 1;\nlet a = 2; let b = a / 0;\nlet c = a;\n:save {}\n", path.display());
+        assert_eq!(transcript(&input), ">> >> 1:22: error[E0104]: division by zero\n>> >> >> \n");
+        let script = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(script, "let a = 1;\nlet c = a;\n");
+        let mut replay = Session::new();
+        assert!(replay.eval(&script, &mut io::sink()).unwrap());
+        assert_eq!(replay.names(), vec!["a", "c"]);
+        assert_eq!(replay.evaluator.env().get("c").map(|c| c.to_string()), Some("1".into()));
+    }
+
+    #[test]
+    fn incomplete_input() {
+        let tests = vec![
+            ("let x = 5;", false),
+            ("1 + 2", false),
+            ("", false),
+            ("let add = fn(a, b) {", true),
+            ("let add = fn(a, b) {\n a + b\n", true),
+            ("let add = fn(a, b) {\n a + b\n};", false),
+            ("add(1,", true),
+            ("1 +", true),
+            ("let x =", true),
+            ("if (x)", true),
+            ("if (x) { 1 } else", true),
+            ("let = 5;", false),
+            ("1 + 2 }", false),
+        ];
+        for (input, want) in tests {
+            assert_eq!(incomplete(input), want, "{:?}", input);
+        }
+    }
+}
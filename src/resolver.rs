@@ -0,0 +1,295 @@
+use crate::ast::{Program, Statement, LetStatement, Identifier, FunctionLiteral, Binding};
+use crate::object::{Builtin, Env};
+use crate::token::Span;
+use crate::lexer::KEYWORDS;
+use crate::util::{self, Diagnostic, Diagnostics};
+use crate::visit::{self, Visitor, VisitMut};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// resolve binds every identifier in the program to the slot its value will
+/// be found in, so the evaluator doesn't have to look names up. Names bound
+/// at the top level are given slots in `globals`.
+///
+/// A `let` binds its name throughout the function it is in, or throughout the
+/// program at the top level, so functions may refer to each other whatever
+/// order they are defined in. Using a name that is bound nowhere, or naming
+/// two parameters alike, is an error, and leaves `globals` as it was.
+pub fn resolve(program: &mut Program, globals: &Env) -> Result<(), Diagnostics> {
+    let names = lets(&program.statements);
+    // Names without a slot are first resolved to a placeholder, and only
+    // given slots once the program is known to resolve.
+    let defined = names
+        .iter()
+        .map(|name| (name.name.clone(), globals.lookup(&name.name).unwrap_or(usize::MAX)))
+        .collect();
+    bind(program, globals, defined)?;
+    if names.iter().all(|name| globals.lookup(&name.name).is_some()) {
+        return Ok(());
+    }
+    let defined = names
+        .iter()
+        .map(|name| (name.name.clone(), globals.slot(&name.name)))
+        .collect();
+    bind(program, globals, defined)
+}
+
+// bind resolves the program with the globals it defines in the given slots.
+fn bind(program: &mut Program, globals: &Env, defined: HashMap<String, usize>) -> Result<(), Diagnostics> {
+    let mut resolver = Resolver {
+        globals,
+        defined,
+        scopes: vec![],
+        errors: Diagnostics::new(),
+    };
+    resolver.visit_program_mut(program);
+    if !resolver.errors.is_empty() {
+        Err(resolver.errors)
+    } else {
+        Ok(())
+    }
+}
+
+struct Resolver<'a> {
+    globals: &'a Env,
+    // defined holds the globals bound by this program, which have no value
+    // until it runs.
+    defined: HashMap<String, usize>,
+    // scopes holds the slots of the functions being resolved, innermost last.
+    scopes: Vec<HashMap<String, usize>>,
+    errors: Diagnostics,
+}
+
+impl Resolver<'_> {
+    fn binding(&self, name: &str) -> Option<Binding> {
+        let depth = self.scopes.len();
+        for (i, scope) in self.scopes.iter().enumerate().rev() {
+            if let Some(slot) = scope.get(name) {
+                return Some(match depth - 1 - i {
+                    0 => Binding::Local(*slot),
+                    depth => Binding::Free { depth, slot: *slot },
+                });
+            }
+        }
+        if let Some(slot) = self.defined.get(name) {
+            return Some(Binding::Global(*slot));
+        }
+        if let Some(slot) = self.globals.lookup(name) {
+            if self.globals.load(slot).is_some() {
+                return Some(Binding::Global(slot));
+            }
+        }
+        Builtin::ALL.iter().position(|b| b.name() == name).map(Binding::Builtin)
+    }
+
+    // names lists every name that could be bound where the resolver is, the
+    // closest scopes first, along with the keywords, for suggesting a name
+    // in place of one that isn't bound.
+    fn names(&self) -> Vec<String> {
+        let by_slot = |scope: &HashMap<String, usize>| {
+            let mut names: Vec<(&String, &usize)> = scope.iter().collect();
+            names.sort_by_key(|(_, slot)| **slot);
+            names.into_iter().map(|(name, _)| name.clone()).collect::<Vec<String>>()
+        };
+        let mut names: Vec<String> = self.scopes.iter().rev().flat_map(by_slot).collect();
+        names.extend(by_slot(&self.defined));
+        names.extend(self.globals.bindings().into_iter().map(|(name, _)| name));
+        names.extend(Builtin::ALL.iter().map(|b| b.name().to_string()));
+        names.extend(KEYWORDS.iter().map(|k| k.to_string()));
+        names
+    }
+}
+
+impl VisitMut for Resolver<'_> {
+    fn visit_let_mut(&mut self, stmt: &mut LetStatement) {
+        stmt.name.binding = self.binding(&stmt.name.name).unwrap_or_default();
+        visit::walk_let_mut(self, stmt);
+    }
+
+    fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+        match self.binding(&identifier.name) {
+            Some(binding) => identifier.binding = binding,
+            None => {
+                let mut error = Diagnostic::error("E0005", format!("identifier not found: {}", identifier.name))
+                    .at(identifier.position);
+                let names = self.names();
+                if let Some(name) = util::suggest(&identifier.name, names.iter().map(String::as_str)) {
+                    error = error.suggest(identifier.position, identifier.name.as_str(), name);
+                }
+                self.errors.push(error);
+            },
+        }
+    }
+
+    // Parameters take the first slots of a call, in order, followed by the
+    // names bound by lets in the body.
+    fn visit_function_mut(&mut self, exp: &mut FunctionLiteral) {
+        let mut scope = HashMap::new();
+        let mut first = HashMap::new();
+        for (slot, parameter) in exp.parameters.iter_mut().enumerate() {
+            match first.get(&parameter.name) {
+                Some(&position) => {
+                    let error = Diagnostic::error("E0006", format!("duplicate parameter: {}", parameter.name))
+                        .at(parameter.position)
+                        .secondary(Span { start: position, end: position }, "first declared here");
+                    self.errors.push(error);
+                },
+                None => {
+                    first.insert(parameter.name.clone(), parameter.position);
+                },
+            }
+            scope.insert(parameter.name.clone(), slot);
+            parameter.binding = Binding::Local(slot);
+        }
+        for name in lets(&exp.body.statements) {
+            let slot = scope.len();
+            scope.entry(name.name).or_insert(slot);
+        }
+        self.scopes.push(scope);
+        self.visit_block_mut(Arc::make_mut(&mut exp.body));
+        self.scopes.pop();
+    }
+}
+
+/// lets lists the names bound by let statements among `statements`, in
+/// order, leaving out those in nested functions, which have scopes of their
+/// own.
+pub fn lets(statements: &[Statement]) -> Vec<Identifier> {
+    let mut lets = Lets::default();
+    for stmt in statements {
+        lets.visit_statement(stmt);
+    }
+    lets.names
+}
+
+#[derive(Default)]
+struct Lets {
+    names: Vec<Identifier>,
+}
+
+impl Visitor for Lets {
+    fn visit_let(&mut self, stmt: &LetStatement) {
+        self.names.push(stmt.name.clone());
+        visit::walk_let(self, stmt);
+    }
+
+    fn visit_function(&mut self, _exp: &FunctionLiteral) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Expression;
+    use crate::parser::parse;
+
+    type Error = Box<dyn std::error::Error>;
+
+    // Bindings collects the bindings of the identifiers used in a program.
+    #[derive(Default)]
+    struct Bindings(Vec<String>);
+
+    impl Visitor for Bindings {
+        fn visit_identifier(&mut self, identifier: &Identifier) {
+            self.0.push(format!("{} {:?}", identifier.name, identifier.binding));
+        }
+    }
+
+    #[test]
+    fn bindings() -> Result<(), Error> {
+        let tests = vec![
+            ("let a = 1; a", vec!["a Global(0)"]),
+            ("len; puts", vec!["len Builtin(0)", "puts Builtin(1)"]),
+            ("let len = 1; len", vec!["len Global(0)"]),
+            ("f(); let f = fn() { 1 }", vec!["f Global(0)"]),
+            ("fn(a, b) { let c = a; b + c }", vec!["a Local(0)", "b Local(1)", "c Local(2)"]),
+            ("fn(a) { if (a) { let a = 2; a } }", vec!["a Local(0)", "a Local(0)"]),
+            (
+                "fn(x) { let y = 1; fn(z) { fn() { x + y + z } } }",
+                vec!["x Free { depth: 2, slot: 0 }", "y Free { depth: 2, slot: 1 }", "z Free { depth: 1, slot: 0 }"],
+            ),
+            ("let x = 1; fn() { let x = 2; x }; x", vec!["x Local(0)", "x Global(0)"]),
+        ];
+        for (input, want) in tests {
+            let mut program = parse(input)?;
+            resolve(&mut program, &Env::new())?;
+            let mut bindings = Bindings::default();
+            bindings.visit_program(&program);
+            assert_eq!(bindings.0, want, "{}", input);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> Result<(), Error> {
+        let tests = vec![
+            ("foobar", vec!["1:1: identifier not found: foobar"]),
+            ("let f = fn(a, b, a) { a }", vec!["1:18: duplicate parameter: a"]),
+            ("fn() { x }; fn() { let x = 1 }; y + z", vec!["1:8: identifier not found: x", "1:33: identifier not found: y", "1:37: identifier not found: z"]),
+        ];
+        for (input, want) in tests {
+            match resolve(&mut parse(input)?, &Env::new()) {
+                Ok(()) => panic!("{}: expected errors", input),
+                Err(errors) => {
+                    let errors: Vec<String> = errors.into_iter().map(|err| err.to_string()).collect();
+                    assert_eq!(errors, want, "{}", input);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn suggestions() -> Result<(), Error> {
+        let tests = vec![
+            ("retrun 5", Some("did you mean `return`?")),
+            ("lenght(\"abc\")", Some("did you mean `len`?")),
+            ("let length = len; lenght(\"abc\")", Some("did you mean `length`?")),
+            ("let f = fn(count) { fn() { conut } }", Some("did you mean `count`?")),
+            ("putz(1)", Some("did you mean `puts`?")),
+            ("let total = 1; x", None),
+        ];
+        for (input, want) in tests {
+            match resolve(&mut parse(input)?, &Env::new()) {
+                Ok(()) => panic!("{}: expected errors", input),
+                Err(errors) => {
+                    let help: Vec<Option<&str>> = errors.iter().map(|err| err.help.as_deref()).collect();
+                    assert_eq!(help, vec![want], "{}", input);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Globals bound by earlier programs can be used, but not those that were
+    // only named, by programs that failed before binding them.
+    #[test]
+    fn globals() -> Result<(), Error> {
+        let globals = Env::new();
+        globals.set("x", crate::object::Object::Int(1));
+        globals.slot("y");
+        let mut program = parse("x")?;
+        resolve(&mut program, &globals)?;
+        match program.statements.as_slice() {
+            [Statement::Expression(Expression::Identifier(x))] => assert_eq!(x.binding, Binding::Global(0)),
+            other => panic!("unexpected statements: {:?}", other),
+        }
+        assert!(resolve(&mut parse("y")?, &globals).is_err());
+        Ok(())
+    }
+
+    // A program that fails to resolve gives none of its names slots.
+    #[test]
+    fn failures() -> Result<(), Error> {
+        let globals = Env::new();
+        assert!(resolve(&mut parse("let a = 1; let b = a + c;")?, &globals).is_err());
+        assert_eq!((globals.lookup("a"), globals.lookup("b")), (None, None));
+        let mut program = parse("let b = 2; let a = b;")?;
+        resolve(&mut program, &globals)?;
+        assert_eq!((globals.lookup("b"), globals.lookup("a")), (Some(0), Some(1)));
+        let mut bindings = Bindings::default();
+        bindings.visit_program(&program);
+        assert_eq!(bindings.0, vec!["b Global(0)"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use crate::object::Env;
+use crate::repl::{Lines, Session};
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::thread;
+
+const GREETING: &str = "This is the Monkey programming language. Type :help for commands.\n";
+
+/// Server runs a REPL session for every client that connects, each on its
+/// own thread. Sessions either get globals of their own or all share one
+/// set, in which case a binding made by one client is seen by the others.
+pub struct Server {
+    // shared holds the globals when they are shared between sessions.
+    shared: Option<Env>,
+}
+
+impl Server {
+    pub fn new(shared: bool) -> Server {
+        Server {
+            shared: if shared { Some(Env::new()) } else { None },
+        }
+    }
+
+    /// serve_tcp accepts connections until the listener fails.
+    pub fn serve_tcp(&self, listener: TcpListener) -> io::Result<()> {
+        self.accept(listener.incoming(), TcpStream::try_clone);
+        Ok(())
+    }
+
+    /// serve_unix accepts connections until the listener fails.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, listener: UnixListener) -> io::Result<()> {
+        self.accept(listener.incoming(), UnixStream::try_clone);
+        Ok(())
+    }
+
+    // accept spawns a session for every incoming stream, reading from a clone
+    // of it. A connection that fails is logged and dropped; the others carry
+    // on.
+    fn accept<S, I>(&self, incoming: I, clone: fn(&S) -> io::Result<S>)
+        where S: Read + Write + Send + 'static, I: Iterator<Item = io::Result<S>>,
+    {
+        for stream in incoming {
+            let (reader, stream) = match stream.and_then(|stream| Ok((clone(&stream)?, stream))) {
+                Ok(streams) => streams,
+                Err(err) => {
+                    eprintln!("accept: {}", err);
+                    continue;
+                }
+            };
+            self.spawn(reader, stream);
+        }
+    }
+
+    fn session(&self) -> Session {
+        match &self.shared {
+            Some(env) => Session::shared(env.clone()),
+            None => Session::new(),
+        }
+    }
+
+    // spawn runs a session on its own thread, reading from `r` and writing
+    // to `w` until the client disconnects or quits.
+    fn spawn<R, W>(&self, r: R, mut w: W)
+        where R: Read + Send + 'static, W: Write + Send + 'static,
+    {
+        let mut session = self.session().served();
+        thread::spawn(move || {
+            let mut r = BufReader::new(r);
+            let result = w.write_all(GREETING.as_bytes())
+                .and_then(|_| session.run(&mut Lines::new(&mut r), &mut w));
+            if let Err(err) = result {
+                eprintln!("session: {}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::strip_ansi;
+    use std::net::Shutdown;
+
+    // client sends `input` to the server at `address` and returns everything
+    // the server wrote before closing the connection.
+    fn client(address: &str, input: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(input.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        strip_ansi(&out)
+    }
+
+    fn listen(shared: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || Server::new(shared).serve_tcp(listener));
+        address
+    }
+
+    #[test]
+    fn isolated() {
+        let address = listen(false);
+        let first = client(&address, "let x = 1;\nputs(x + 1)\n");
+        assert_eq!(first, format!("{}>> >> 2\n>> \n", GREETING));
+        let second = client(&address, "x\n");
+        assert_eq!(second, format!("{}>> 1:1: error[E0005]: identifier not found: x\n>> \n", GREETING));
+    }
+
+    #[test]
+    fn shared() {
+        let address = listen(true);
+        client(&address, "let x = 1;\n");
+        assert_eq!(client(&address, "x\n:quit\n"), format!("{}>> 1\n>> ", GREETING));
+        // No client can clear the others' globals.
+        let want = format!("{}>> error: :reset: the globals are shared with other clients\n>> 1\n>> \n", GREETING);
+        assert_eq!(client(&address, ":reset\nx\n"), want);
+    }
+
+    // Clients can't use the server's files.
+    #[test]
+    fn files() {
+        let address = listen(false);
+        let want = format!(
+            "{}>> error: :load: not available over serve\n>> error: :save: not available over serve\n>> \n",
+            GREETING,
+        );
+        assert_eq!(client(&address, ":load /etc/passwd\n:save /tmp/monkey-served\n"), want);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let path = std::env::temp_dir().join(format!("monkey-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || Server::new(false).serve_unix(listener));
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"1 + 2\n").unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(strip_ansi(&out), format!("{}>> 3\n>> \n", GREETING));
+    }
+}
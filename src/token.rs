@@ -1,23 +1,70 @@
+use std::fmt::{self, Display, Formatter};
+
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub struct Token {
     pub kind: Kind,
     pub literal: String,
+    pub position: Position,
 }
 
 impl Token {
     pub fn new<S: Into<String>>(kind: Kind, literal: S) -> Token {
-        Token { kind, literal: literal.into() }
+        Token { kind, literal: literal.into(), position: Position::default() }
+    }
+
+    /// at places the token at the given position in the source.
+    pub fn at(mut self, position: Position) -> Token {
+        self.position = position;
+        self
+    }
+}
+
+/// Position is the 1-based line and column of a character in the source.
+/// The zero position means "unknown", for tokens and nodes that were not
+/// produced by the lexer. Positions order by line, then column.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+}
+
+
+/// Span is the range of source covered by a statement or block, from the
+/// start of its first token to the start of its last.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
     }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
 pub enum Kind {
     Illegal,
     Eof,
+    // Comment runs from `//` to the end of the line. The lexer only produces
+    // comments when asked to.
+    Comment,
 
     Ident,
     Int,
     Bool,
+    String,
 
     Assign,
     Plus,
@@ -44,4 +91,4 @@ pub enum Kind {
 
     Equal,
     NotEqual,
-}
\ No newline at end of file
+}
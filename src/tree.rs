@@ -0,0 +1,153 @@
+use crate::ast::{Program, Statement, BlockStatement, Expression, Identifier};
+
+use std::fmt::Write;
+
+/// render draws the program as an indented tree, one node per line, with
+/// each child labelled by the role it plays in its parent.
+pub fn render(program: &Program) -> String {
+    let mut out = String::from("Program\n");
+    for stmt in &program.statements {
+        write_node(&mut out, 1, None, Tree::Statement(stmt));
+    }
+    out
+}
+
+/// Tree is any node of the syntax tree, so that they can all be drawn alike.
+#[derive(Clone, Copy)]
+pub enum Tree<'a> {
+    Statement(&'a Statement),
+    Block(&'a BlockStatement),
+    Expression(&'a Expression),
+    Parameter(&'a Identifier),
+}
+
+fn write_node(out: &mut String, depth: usize, edge: Option<&str>, node: Tree) {
+    let indent = "  ".repeat(depth);
+    let edge = match edge {
+        Some(edge) => format!("{}: ", edge),
+        None => String::new(),
+    };
+    let _ = writeln!(out, "{}{}{}", indent, edge, label(node));
+    for (edge, child) in children(node) {
+        write_node(out, depth + 1, edge, child);
+    }
+}
+
+/// label describes the node itself, without its children.
+pub fn label(node: Tree) -> String {
+    match node {
+        Tree::Statement(Statement::Let(stmt)) => format!("Let {}", stmt.name),
+        Tree::Statement(Statement::Return(_)) => "Return".into(),
+        Tree::Statement(Statement::Expression(exp)) | Tree::Expression(exp) => match exp {
+            Expression::Int(n) => format!("Int {}", n.value),
+            Expression::String(s) => format!("String {:?}", s.value),
+            Expression::Boolean(b) => format!("Boolean {}", b.value),
+            Expression::Identifier(identifier) => format!("Identifier {}", identifier),
+            Expression::If(_) => "If".into(),
+            Expression::Prefix(exp) => format!("Prefix {}", exp.operator),
+            Expression::Infix(exp) => format!("Infix {}", exp.operator),
+            Expression::Function(_) => "Function".into(),
+            Expression::Call(_) => "Call".into(),
+        },
+        Tree::Block(_) => "Block".into(),
+        Tree::Parameter(identifier) => format!("Identifier {}", identifier),
+    }
+}
+
+/// children lists the children of the node in source order, each with the
+/// role it plays in the node. Statements in a block have no role.
+pub fn children(node: Tree) -> Vec<(Option<&'static str>, Tree)> {
+    let exp = match node {
+        Tree::Statement(Statement::Let(stmt)) => return vec![(Some("value"), Tree::Expression(&stmt.value))],
+        Tree::Statement(Statement::Return(stmt)) => return vec![(Some("value"), Tree::Expression(&stmt.value))],
+        Tree::Statement(Statement::Expression(exp)) | Tree::Expression(exp) => exp,
+        Tree::Block(block) => return block.statements.iter().map(|s| (None, Tree::Statement(s))).collect(),
+        Tree::Parameter(_) => return vec![],
+    };
+    match exp {
+        Expression::If(exp) => {
+            let mut children = vec![
+                (Some("predicate"), Tree::Expression(&exp.predicate)),
+                (Some("success"), Tree::Block(&exp.success)),
+            ];
+            if let Some(fail) = &exp.fail {
+                children.push((Some("fail"), Tree::Block(fail)));
+            }
+            children
+        },
+        Expression::Prefix(exp) => vec![(Some("value"), Tree::Expression(&exp.value))],
+        Expression::Infix(exp) => vec![
+            (Some("left"), Tree::Expression(&exp.left)),
+            (Some("right"), Tree::Expression(&exp.right)),
+        ],
+        Expression::Function(exp) => {
+            let mut children: Vec<(Option<&str>, Tree)> = exp.parameters
+                .iter()
+                .map(|p| (Some("parameter"), Tree::Parameter(p)))
+                .collect();
+            children.push((Some("body"), Tree::Block(&exp.body)));
+            children
+        },
+        Expression::Call(exp) => {
+            let mut children = vec![(Some("function"), Tree::Expression(&exp.function))];
+            children.extend(exp.arguments.iter().map(|a| (Some("argument"), Tree::Expression(a))));
+            children
+        },
+        Expression::Int(_)
+        | Expression::String(_)
+        | Expression::Boolean(_)
+        | Expression::Identifier(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn indented() -> Result<(), Box<dyn std::error::Error>> {
+        let input = "let f = fn(x) { if (x < 1) { x } else { -x } }; f(2);";
+        let want = "\
+Program
+  Let f
+    value: Function
+      parameter: Identifier x
+      body: Block
+        If
+          predicate: Infix <
+            left: Identifier x
+            right: Int 1
+          success: Block
+            Identifier x
+          fail: Block
+            Prefix -
+              value: Identifier x
+  Call
+    function: Identifier f
+    argument: Int 2
+";
+        let program = Parser::new(Lexer::new(input.chars())).parse()?;
+        assert_eq!(render(&program), want);
+        Ok(())
+    }
+
+    // Leaves are labelled with their value, and optional children are left
+    // out when they are missing.
+    #[test]
+    fn labels() -> Result<(), Box<dyn std::error::Error>> {
+        let tests = vec![
+            ("return \"a\\tb\";", "Return\n    value: String \"a\\tb\"\n"),
+            ("!true", "Prefix !\n    value: Boolean true\n"),
+            ("if (x) { }", "If\n    predicate: Identifier x\n    success: Block\n"),
+            ("fn() { 1 }", "Function\n    body: Block\n      Int 1\n"),
+            ("f()", "Call\n    function: Identifier f\n"),
+        ];
+        for (input, want) in tests {
+            let program = crate::parser::parse(input)?;
+            assert_eq!(render(&program), format!("Program\n  {}", want), "{}", input);
+        }
+        Ok(())
+    }
+}
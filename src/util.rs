@@ -1,37 +1,447 @@
+use crate::token::{Position, Span};
+
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
+use colored::*;
+
+/// Severity is how serious a diagnostic is. Only errors stop a program from
+/// running.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Label marks a span of the source with an explanation. A diagnostic's
+/// primary label is where the problem is; secondary labels point at code
+/// that helps explain it.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+/// Diagnostic is a problem found in a program, identified by a stable code
+/// such as `E0003`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub help: Option<String>,
+    pub suggestions: Vec<Suggestion>,
+    // trace lists the calls a runtime error unwound through, innermost
+    // first.
+    pub trace: Vec<Call>,
+}
+
+/// Call is a call of a Monkey function that a runtime error unwound through:
+/// the name of the function, if a let gave it one, and the span of the call
+/// from the start of the function expression to its opening parenthesis.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Call {
+    pub function: Option<String>,
+    pub span: Span,
+}
+
+/// Suggestion is an edit that may fix a diagnostic: the text `replaces`,
+/// starting at `position`, is replaced by `replacement`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Suggestion {
+    pub position: Position,
+    pub replaces: String,
+    pub replacement: String,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(severity: Severity, code: &'static str, message: S) -> Diagnostic {
+        Diagnostic { severity, code, message: message.into(), labels: vec![], help: None, suggestions: vec![], trace: vec![] }
+    }
+
+    pub fn error<S: Into<String>>(code: &'static str, message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Error, code, message)
+    }
+
+    pub fn warning<S: Into<String>>(code: &'static str, message: S) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, code, message)
+    }
+
+    /// at adds the primary label, at a single position.
+    pub fn at(self, position: Position) -> Diagnostic {
+        self.label(Span { start: position, end: position }, "")
+    }
+
+    /// label adds the primary label.
+    pub fn label<S: Into<String>>(mut self, span: Span, message: S) -> Diagnostic {
+        self.labels.push(Label { span, message: message.into(), primary: true });
+        self
+    }
+
+    pub fn secondary<S: Into<String>>(mut self, span: Span, message: S) -> Diagnostic {
+        self.labels.push(Label { span, message: message.into(), primary: false });
+        self
+    }
+
+    pub fn help<S: Into<String>>(mut self, help: S) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// suggest offers `replacement` for the word `replaces` at `position`,
+    /// which is also given as the help.
+    pub fn suggest<S: Into<String>>(mut self, position: Position, replaces: S, replacement: S) -> Diagnostic {
+        let replacement = replacement.into();
+        self.help = Some(format!("did you mean `{}`?", replacement));
+        self.suggestions.push(Suggestion { position, replaces: replaces.into(), replacement });
+        self
+    }
+
+    /// inside records that the diagnostic was raised inside a call of
+    /// `function`, made at `span`.
+    pub fn inside(mut self, function: Option<String>, span: Span) -> Diagnostic {
+        self.trace.push(Call { function, span });
+        self
+    }
+
+    /// position is the start of the primary label, if there is one.
+    pub fn position(&self) -> Option<Position> {
+        self.labels.iter().find(|label| label.primary).map(|label| label.span.start)
+    }
+
+    /// human formats the diagnostic for people, as
+    /// `name:line:column: severity[code]: message` followed by a line for
+    /// each secondary label, the traceback and the help. `name` is the file
+    /// the diagnostic was found in, if there is one.
+    pub fn human(&self, name: Option<&str>) -> String {
+        let at = |position: Option<Position>| match (name, position) {
+            (Some(name), Some(position)) => format!("{}:{}: ", name, position),
+            (Some(name), None) => format!("{}: ", name),
+            (None, Some(position)) => format!("{}: ", position),
+            (None, None) => String::new(),
+        };
+        let severity = match self.severity {
+            Severity::Error => self.severity.to_string().red(),
+            Severity::Warning => self.severity.to_string().yellow(),
+            Severity::Note => self.severity.to_string().cyan(),
+        };
+        let mut out = format!("{}{}[{}]: {}\n", at(self.position()), severity, self.code, self.message);
+        for label in self.labels.iter().filter(|label| !label.primary) {
+            out.push_str(&format!("{}{}: {}\n", at(Some(label.span.start)), "note".cyan(), label.message));
+        }
+        if !self.trace.is_empty() {
+            out.push_str("  traceback, innermost call first:\n");
+            // Recursion repeats lines, which are only written once.
+            let mut previous = None;
+            let mut repeated = 0;
+            for (position, function) in self.traceback() {
+                let line = format!("{}{}", at(position), function);
+                if previous.as_ref() == Some(&line) {
+                    repeated += 1;
+                    continue;
+                }
+                if repeated > 0 {
+                    out.push_str(&format!("    ... repeated {} more time(s)\n", repeated));
+                    repeated = 0;
+                }
+                out.push_str(&format!("    {}\n", line));
+                previous = Some(line);
+            }
+            if repeated > 0 {
+                out.push_str(&format!("    ... repeated {} more time(s)\n", repeated));
+            }
+        }
+        if let Some(help) = &self.help {
+            out.push_str(&format!("  {}: {}\n", "help".cyan(), help));
+        }
+        out
+    }
+
+    // traceback pairs each function in the trace with where it was when the
+    // error unwound through it: where the error was raised for the innermost,
+    // and otherwise where it called the function inside it. The last line is
+    // the top level, where the outermost call was made.
+    fn traceback(&self) -> Vec<(Option<Position>, String)> {
+        let mut lines = vec![];
+        let mut position = self.position();
+        for call in &self.trace {
+            let function = match &call.function {
+                Some(name) => format!("in `{}`", name),
+                None => "in an anonymous function".to_string(),
+            };
+            lines.push((position, function));
+            position = Some(call.span.start);
+        }
+        lines.push((position, "at the top level".to_string()));
+        lines
+    }
+
+    /// json formats the diagnostic for tools, as a single line JSON object
+    /// with the file it was found in, if there is one. A span runs from the
+    /// start of its first token to the start of its last, and is null for
+    /// diagnostics without a position, such as most runtime errors.
+    pub fn json(&self, name: Option<&str>) -> String {
+        let span = |span: Option<Span>| match span {
+            Some(Span { start, end }) => format!(
+                r#"{{"line":{},"column":{},"end_line":{},"end_column":{}}}"#,
+                start.line, start.column, end.line, end.column,
+            ),
+            None => "null".to_string(),
+        };
+        let primary = self.labels.iter().find(|label| label.primary).map(|label| label.span);
+        let labels: Vec<String> = self.labels
+            .iter()
+            .filter(|label| !label.primary)
+            .map(|label| format!(r#"{{"span":{},"message":{}}}"#, span(Some(label.span)), json_string(&label.message)))
+            .collect();
+        let trace: Vec<String> = self.trace
+            .iter()
+            .map(|call| format!(
+                r#"{{"function":{},"span":{}}}"#,
+                call.function.as_deref().map_or("null".to_string(), json_string), span(Some(call.span)),
+            ))
+            .collect();
+        let suggestions: Vec<String> = self.suggestions
+            .iter()
+            .map(|s| format!(
+                r#"{{"line":{},"column":{},"replaces":{},"replacement":{}}}"#,
+                s.position.line, s.position.column, json_string(&s.replaces), json_string(&s.replacement),
+            ))
+            .collect();
+        format!(
+            r#"{{"file":{},"severity":"{}","code":"{}","message":{},"span":{},"labels":[{}],"help":{},"suggestions":[{}],"trace":[{}]}}"#,
+            name.map_or("null".to_string(), json_string),
+            self.severity,
+            self.code,
+            json_string(&self.message),
+            span(primary),
+            labels.join(","),
+            self.help.as_deref().map_or("null".to_string(), json_string),
+            suggestions.join(","),
+            trace.join(","),
+        )
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.position() {
+            Some(position) => write!(f, "{}: {}", position, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for Diagnostic {}
 
-#[derive(Debug)]
-pub struct MultiError(Vec<Box<dyn Error>>);
+/// Diagnostics collects the problems found by a pass over a program, errors
+/// and warnings alike.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Diagnostics(Vec<Diagnostic>);
 
-impl MultiError {
-    pub fn new() -> MultiError {
-        MultiError(Vec::<Box<dyn Error>>::new())
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics(vec![])
     }
-    pub fn push(&mut self, err: Box<dyn Error>) {
-        self.0.push(err)
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic)
+    }
+
+    pub fn extend(&mut self, diagnostics: Diagnostics) {
+        self.0.extend(diagnostics.0)
     }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// has_errors reports whether any diagnostic is an error, rather than a
+    /// warning or note.
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    /// sort puts the diagnostics in source order. Those without a position
+    /// come first.
+    pub fn sort(&mut self) {
+        self.0.sort_by_key(|diagnostic| diagnostic.position().map(|position| (position.line, position.column)));
+    }
 }
 
-impl IntoIterator for MultiError {
-    type Item = Box<dyn Error>;
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
     type IntoIter = ::std::vec::IntoIter<Self::Item>;
-    
+
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
-    } 
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = ::std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
 }
 
-impl Display for MultiError {
+impl Display for Diagnostics {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        for err in &self.0 {
-            write!(f, "  -> {}\n", err)?;
+        for diagnostic in &self.0 {
+            writeln!(f, "  -> {}", diagnostic)?;
         }
         Ok(())
     }
 }
 
-impl Error for MultiError {}
\ No newline at end of file
+impl Error for Diagnostics {}
+
+/// suggest picks the candidate closest to `name`, for a "did you mean"
+/// hint. Only candidates a few edits away are considered, fewer for shorter
+/// names, or that `name` starts with, and never ones that would replace the
+/// whole name. Of equally close candidates, the first wins.
+pub fn suggest<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+    where I: IntoIterator<Item=&'a str>,
+{
+    let length = name.chars().count();
+    let limit = std::cmp::max(1, length / 3);
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in candidates {
+        if candidate == name {
+            continue;
+        }
+        let distance = edit_distance(name, candidate);
+        if (distance > limit && !name.starts_with(candidate)) || distance >= length {
+            continue;
+        }
+        if best.is_none_or(|(shortest, _)| distance < shortest) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map(|(_, candidate)| candidate)
+}
+
+/// edit_distance counts the characters that must be inserted, deleted or
+/// replaced, or the neighbours swapped, to turn `a` into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // rows[i][j] is the distance between the first i characters of a and the
+    // first j of b.
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// json_string quotes and escapes `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_strings() {
+        let tests = vec![
+            ("", r#""""#),
+            ("plain", r#""plain""#),
+            ("say \"hi\"\n", r#""say \"hi\"\n""#),
+            ("back\\slash\u{1b}", r#""back\\slash\u001b""#),
+        ];
+        for (input, want) in tests {
+            assert_eq!(json_string(input), want);
+        }
+    }
+
+    #[test]
+    fn json() {
+        let at = |line, column| Position { line, column };
+        let diagnostic = Diagnostic::error("E0005", "identifier not found: \"x\"")
+            .label(Span { start: at(2, 1), end: at(2, 5) }, "")
+            .secondary(Span { start: at(1, 1), end: at(1, 1) }, "here")
+            .suggest(at(2, 1), "retrun", "return");
+        let want = concat!(
+            r#"{"file":"a.monkey","severity":"error","code":"E0005","message":"identifier not found: \"x\"","#,
+            r#""span":{"line":2,"column":1,"end_line":2,"end_column":5},"#,
+            r#""labels":[{"span":{"line":1,"column":1,"end_line":1,"end_column":1},"message":"here"}],"#,
+            r#""help":"did you mean `return`?","#,
+            r#""suggestions":[{"line":2,"column":1,"replaces":"retrun","replacement":"return"}],"trace":[]}"#,
+        );
+        assert_eq!(diagnostic.json(Some("a.monkey")), want);
+        let want = r#"{"file":null,"severity":"warning","code":"W0004","message":"m","span":null,"labels":[],"help":null,"suggestions":[],"trace":[]}"#;
+        assert_eq!(Diagnostic::warning("W0004", "m").json(None), want);
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["fn", "let", "return", "if", "else", "true", "false", "len", "length", "counter"];
+        let tests = vec![
+            ("retrun", Some("return")),
+            ("lenght", Some("length")),
+            ("lett", Some("let")),
+            ("esle", Some("else")),
+            ("counte", Some("counter")),
+            ("lennn", Some("len")),
+            ("f", None),
+            ("x", None),
+            ("total", None),
+        ];
+        for (input, want) in tests {
+            assert_eq!(suggest(input, names), want, "{}", input);
+        }
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,566 @@
+use crate::ast::{
+    Program, Statement, LetStatement, ReturnStatement, BlockStatement, Expression, Identifier,
+    IntegerLiteral, StringLiteral, BooleanLiteral, PrefixExpression, InfixExpression, IfExpression,
+    FunctionLiteral, CallExpression,
+};
+use crate::token::Position;
+
+use std::sync::Arc;
+
+/// Visitor walks a syntax tree by reference. Each method's default calls the
+/// matching `walk_` function, which visits the node's children, so a visitor
+/// only overrides the nodes it is interested in. An override that still wants
+/// the children visited calls the `walk_` function itself.
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program)
+    }
+
+    fn visit_statement(&mut self, stmt: &Statement) {
+        walk_statement(self, stmt)
+    }
+
+    fn visit_let(&mut self, stmt: &LetStatement) {
+        walk_let(self, stmt)
+    }
+
+    fn visit_return(&mut self, stmt: &ReturnStatement) {
+        walk_return(self, stmt)
+    }
+
+    fn visit_block(&mut self, block: &BlockStatement) {
+        walk_block(self, block)
+    }
+
+    fn visit_expression(&mut self, exp: &Expression) {
+        walk_expression(self, exp)
+    }
+
+    fn visit_identifier(&mut self, _identifier: &Identifier) {}
+
+    fn visit_int(&mut self, _int: &IntegerLiteral) {}
+
+    fn visit_string(&mut self, _string: &StringLiteral) {}
+
+    fn visit_boolean(&mut self, _boolean: &BooleanLiteral) {}
+
+    fn visit_prefix(&mut self, exp: &PrefixExpression) {
+        walk_prefix(self, exp)
+    }
+
+    fn visit_infix(&mut self, exp: &InfixExpression) {
+        walk_infix(self, exp)
+    }
+
+    fn visit_if(&mut self, exp: &IfExpression) {
+        walk_if(self, exp)
+    }
+
+    // Parameters and the names in let statements are names being bound rather
+    // than identifiers being used, so they are not visited as identifiers.
+    fn visit_parameter(&mut self, _parameter: &Identifier) {}
+
+    fn visit_function(&mut self, exp: &FunctionLiteral) {
+        walk_function(self, exp)
+    }
+
+    fn visit_call(&mut self, exp: &CallExpression) {
+        walk_call(self, exp)
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for stmt in &program.statements {
+        visitor.visit_statement(stmt);
+    }
+}
+
+/// walk_statement dispatches to the visitor method for the kind of statement.
+pub fn walk_statement<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Statement) {
+    match stmt {
+        Statement::Let(stmt) => visitor.visit_let(stmt),
+        Statement::Return(stmt) => visitor.visit_return(stmt),
+        Statement::Expression(exp) => visitor.visit_expression(exp),
+    }
+}
+
+pub fn walk_let<V: Visitor + ?Sized>(visitor: &mut V, stmt: &LetStatement) {
+    visitor.visit_expression(&stmt.value);
+}
+
+pub fn walk_return<V: Visitor + ?Sized>(visitor: &mut V, stmt: &ReturnStatement) {
+    visitor.visit_expression(&stmt.value);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &BlockStatement) {
+    for stmt in &block.statements {
+        visitor.visit_statement(stmt);
+    }
+}
+
+/// walk_expression dispatches to the visitor method for the kind of
+/// expression.
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, exp: &Expression) {
+    match exp {
+        Expression::Identifier(identifier) => visitor.visit_identifier(identifier),
+        Expression::Int(int) => visitor.visit_int(int),
+        Expression::String(string) => visitor.visit_string(string),
+        Expression::Boolean(boolean) => visitor.visit_boolean(boolean),
+        Expression::Prefix(exp) => visitor.visit_prefix(exp),
+        Expression::Infix(exp) => visitor.visit_infix(exp),
+        Expression::If(exp) => visitor.visit_if(exp),
+        Expression::Function(exp) => visitor.visit_function(exp),
+        Expression::Call(exp) => visitor.visit_call(exp),
+    }
+}
+
+pub fn walk_prefix<V: Visitor + ?Sized>(visitor: &mut V, exp: &PrefixExpression) {
+    visitor.visit_expression(&exp.value);
+}
+
+pub fn walk_infix<V: Visitor + ?Sized>(visitor: &mut V, exp: &InfixExpression) {
+    visitor.visit_expression(&exp.left);
+    visitor.visit_expression(&exp.right);
+}
+
+pub fn walk_if<V: Visitor + ?Sized>(visitor: &mut V, exp: &IfExpression) {
+    visitor.visit_expression(&exp.predicate);
+    visitor.visit_block(&exp.success);
+    if let Some(fail) = &exp.fail {
+        visitor.visit_block(fail);
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(visitor: &mut V, exp: &FunctionLiteral) {
+    for parameter in &exp.parameters {
+        visitor.visit_parameter(parameter);
+    }
+    visitor.visit_block(&exp.body);
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, exp: &CallExpression) {
+    visitor.visit_expression(&exp.function);
+    for argument in &exp.arguments {
+        visitor.visit_expression(argument);
+    }
+}
+
+/// VisitMut walks a syntax tree by mutable reference, for passes that edit
+/// the tree in place. Overriding `visit_statement_mut` or
+/// `visit_expression_mut` allows a node to be replaced outright.
+pub trait VisitMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+
+    fn visit_statement_mut(&mut self, stmt: &mut Statement) {
+        walk_statement_mut(self, stmt)
+    }
+
+    fn visit_let_mut(&mut self, stmt: &mut LetStatement) {
+        walk_let_mut(self, stmt)
+    }
+
+    fn visit_return_mut(&mut self, stmt: &mut ReturnStatement) {
+        walk_return_mut(self, stmt)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut BlockStatement) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_expression_mut(&mut self, exp: &mut Expression) {
+        walk_expression_mut(self, exp)
+    }
+
+    fn visit_identifier_mut(&mut self, _identifier: &mut Identifier) {}
+
+    fn visit_int_mut(&mut self, _int: &mut IntegerLiteral) {}
+
+    fn visit_string_mut(&mut self, _string: &mut StringLiteral) {}
+
+    fn visit_boolean_mut(&mut self, _boolean: &mut BooleanLiteral) {}
+
+    fn visit_prefix_mut(&mut self, exp: &mut PrefixExpression) {
+        walk_prefix_mut(self, exp)
+    }
+
+    fn visit_infix_mut(&mut self, exp: &mut InfixExpression) {
+        walk_infix_mut(self, exp)
+    }
+
+    fn visit_if_mut(&mut self, exp: &mut IfExpression) {
+        walk_if_mut(self, exp)
+    }
+
+    fn visit_parameter_mut(&mut self, _parameter: &mut Identifier) {}
+
+    fn visit_function_mut(&mut self, exp: &mut FunctionLiteral) {
+        walk_function_mut(self, exp)
+    }
+
+    fn visit_call_mut(&mut self, exp: &mut CallExpression) {
+        walk_call_mut(self, exp)
+    }
+}
+
+pub fn walk_program_mut<V: VisitMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for stmt in &mut program.statements {
+        visitor.visit_statement_mut(stmt);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitMut + ?Sized>(visitor: &mut V, stmt: &mut Statement) {
+    match stmt {
+        Statement::Let(stmt) => visitor.visit_let_mut(stmt),
+        Statement::Return(stmt) => visitor.visit_return_mut(stmt),
+        Statement::Expression(exp) => visitor.visit_expression_mut(exp),
+    }
+}
+
+pub fn walk_let_mut<V: VisitMut + ?Sized>(visitor: &mut V, stmt: &mut LetStatement) {
+    visitor.visit_expression_mut(&mut stmt.value);
+}
+
+pub fn walk_return_mut<V: VisitMut + ?Sized>(visitor: &mut V, stmt: &mut ReturnStatement) {
+    visitor.visit_expression_mut(&mut stmt.value);
+}
+
+pub fn walk_block_mut<V: VisitMut + ?Sized>(visitor: &mut V, block: &mut BlockStatement) {
+    for stmt in &mut block.statements {
+        visitor.visit_statement_mut(stmt);
+    }
+}
+
+pub fn walk_expression_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut Expression) {
+    match exp {
+        Expression::Identifier(identifier) => visitor.visit_identifier_mut(identifier),
+        Expression::Int(int) => visitor.visit_int_mut(int),
+        Expression::String(string) => visitor.visit_string_mut(string),
+        Expression::Boolean(boolean) => visitor.visit_boolean_mut(boolean),
+        Expression::Prefix(exp) => visitor.visit_prefix_mut(exp),
+        Expression::Infix(exp) => visitor.visit_infix_mut(exp),
+        Expression::If(exp) => visitor.visit_if_mut(exp),
+        Expression::Function(exp) => visitor.visit_function_mut(exp),
+        Expression::Call(exp) => visitor.visit_call_mut(exp),
+    }
+}
+
+pub fn walk_prefix_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut PrefixExpression) {
+    visitor.visit_expression_mut(&mut exp.value);
+}
+
+pub fn walk_infix_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut InfixExpression) {
+    visitor.visit_expression_mut(&mut exp.left);
+    visitor.visit_expression_mut(&mut exp.right);
+}
+
+pub fn walk_if_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut IfExpression) {
+    visitor.visit_expression_mut(&mut exp.predicate);
+    visitor.visit_block_mut(&mut exp.success);
+    if let Some(fail) = &mut exp.fail {
+        visitor.visit_block_mut(fail);
+    }
+}
+
+pub fn walk_function_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut FunctionLiteral) {
+    for parameter in &mut exp.parameters {
+        visitor.visit_parameter_mut(parameter);
+    }
+    visitor.visit_block_mut(Arc::make_mut(&mut exp.body));
+}
+
+pub fn walk_call_mut<V: VisitMut + ?Sized>(visitor: &mut V, exp: &mut CallExpression) {
+    visitor.visit_expression_mut(&mut exp.function);
+    for argument in &mut exp.arguments {
+        visitor.visit_expression_mut(argument);
+    }
+}
+
+/// Strip resets every position in a tree to the unknown position, so that
+/// trees can be compared by structure alone.
+pub struct Strip;
+
+impl VisitMut for Strip {
+    fn visit_let_mut(&mut self, stmt: &mut LetStatement) {
+        stmt.position = Position::default();
+        stmt.name.position = Position::default();
+        walk_let_mut(self, stmt)
+    }
+
+    fn visit_return_mut(&mut self, stmt: &mut ReturnStatement) {
+        stmt.position = Position::default();
+        walk_return_mut(self, stmt)
+    }
+
+    fn visit_block_mut(&mut self, block: &mut BlockStatement) {
+        block.position = Position::default();
+        walk_block_mut(self, block)
+    }
+
+    fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+        identifier.position = Position::default();
+    }
+
+    fn visit_int_mut(&mut self, int: &mut IntegerLiteral) {
+        int.position = Position::default();
+    }
+
+    fn visit_string_mut(&mut self, string: &mut StringLiteral) {
+        string.position = Position::default();
+    }
+
+    fn visit_boolean_mut(&mut self, boolean: &mut BooleanLiteral) {
+        boolean.position = Position::default();
+    }
+
+    fn visit_prefix_mut(&mut self, exp: &mut PrefixExpression) {
+        exp.position = Position::default();
+        walk_prefix_mut(self, exp)
+    }
+
+    fn visit_infix_mut(&mut self, exp: &mut InfixExpression) {
+        exp.position = Position::default();
+        walk_infix_mut(self, exp)
+    }
+
+    fn visit_if_mut(&mut self, exp: &mut IfExpression) {
+        exp.position = Position::default();
+        walk_if_mut(self, exp)
+    }
+
+    fn visit_parameter_mut(&mut self, parameter: &mut Identifier) {
+        parameter.position = Position::default();
+    }
+
+    fn visit_function_mut(&mut self, exp: &mut FunctionLiteral) {
+        exp.position = Position::default();
+        walk_function_mut(self, exp)
+    }
+
+    fn visit_call_mut(&mut self, exp: &mut CallExpression) {
+        exp.position = Position::default();
+        walk_call_mut(self, exp)
+    }
+}
+
+/// strip returns the program with its positions reset by `Strip`.
+pub fn strip(mut program: Program) -> Program {
+    Strip.visit_program_mut(&mut program);
+    program
+}
+
+/// Fold rebuilds a syntax tree from an owned one, for passes that replace
+/// nodes with different kinds of node. The defaults fold the children and
+/// put the node back together unchanged, keeping its position.
+pub trait Fold {
+    fn fold_program(&mut self, program: Program) -> Program {
+        fold_program(self, program)
+    }
+
+    fn fold_statement(&mut self, stmt: Statement) -> Statement {
+        fold_statement(self, stmt)
+    }
+
+    fn fold_let(&mut self, stmt: LetStatement) -> Statement {
+        fold_let(self, stmt)
+    }
+
+    fn fold_return(&mut self, stmt: ReturnStatement) -> Statement {
+        fold_return(self, stmt)
+    }
+
+    fn fold_block(&mut self, block: BlockStatement) -> BlockStatement {
+        fold_block(self, block)
+    }
+
+    fn fold_expression(&mut self, exp: Expression) -> Expression {
+        fold_expression(self, exp)
+    }
+
+    fn fold_identifier(&mut self, identifier: Identifier) -> Expression {
+        Expression::Identifier(identifier)
+    }
+
+    fn fold_int(&mut self, int: IntegerLiteral) -> Expression {
+        Expression::Int(int)
+    }
+
+    fn fold_string(&mut self, string: StringLiteral) -> Expression {
+        Expression::String(string)
+    }
+
+    fn fold_boolean(&mut self, boolean: BooleanLiteral) -> Expression {
+        Expression::Boolean(boolean)
+    }
+
+    fn fold_prefix(&mut self, exp: PrefixExpression) -> Expression {
+        fold_prefix(self, exp)
+    }
+
+    fn fold_infix(&mut self, exp: InfixExpression) -> Expression {
+        fold_infix(self, exp)
+    }
+
+    fn fold_if(&mut self, exp: IfExpression) -> Expression {
+        fold_if(self, exp)
+    }
+
+    fn fold_parameter(&mut self, parameter: Identifier) -> Identifier {
+        parameter
+    }
+
+    fn fold_function(&mut self, exp: FunctionLiteral) -> Expression {
+        fold_function(self, exp)
+    }
+
+    fn fold_call(&mut self, exp: CallExpression) -> Expression {
+        fold_call(self, exp)
+    }
+}
+
+pub fn fold_program<F: Fold + ?Sized>(folder: &mut F, program: Program) -> Program {
+    Program::new(program.statements.into_iter().map(|s| folder.fold_statement(s)).collect())
+}
+
+pub fn fold_statement<F: Fold + ?Sized>(folder: &mut F, stmt: Statement) -> Statement {
+    match stmt {
+        Statement::Let(stmt) => folder.fold_let(stmt),
+        Statement::Return(stmt) => folder.fold_return(stmt),
+        Statement::Expression(exp) => Statement::Expression(folder.fold_expression(exp)),
+    }
+}
+
+pub fn fold_let<F: Fold + ?Sized>(folder: &mut F, stmt: LetStatement) -> Statement {
+    let value = folder.fold_expression(stmt.value);
+    Statement::Let(LetStatement { value, ..stmt })
+}
+
+pub fn fold_return<F: Fold + ?Sized>(folder: &mut F, stmt: ReturnStatement) -> Statement {
+    let value = folder.fold_expression(stmt.value);
+    Statement::Return(ReturnStatement { value, ..stmt })
+}
+
+pub fn fold_block<F: Fold + ?Sized>(folder: &mut F, block: BlockStatement) -> BlockStatement {
+    let statements = block.statements.into_iter().map(|s| folder.fold_statement(s)).collect();
+    BlockStatement { statements, ..block }
+}
+
+pub fn fold_expression<F: Fold + ?Sized>(folder: &mut F, exp: Expression) -> Expression {
+    match exp {
+        Expression::Identifier(identifier) => folder.fold_identifier(identifier),
+        Expression::Int(int) => folder.fold_int(int),
+        Expression::String(string) => folder.fold_string(string),
+        Expression::Boolean(boolean) => folder.fold_boolean(boolean),
+        Expression::Prefix(exp) => folder.fold_prefix(exp),
+        Expression::Infix(exp) => folder.fold_infix(exp),
+        Expression::If(exp) => folder.fold_if(exp),
+        Expression::Function(exp) => folder.fold_function(exp),
+        Expression::Call(exp) => folder.fold_call(exp),
+    }
+}
+
+pub fn fold_prefix<F: Fold + ?Sized>(folder: &mut F, exp: PrefixExpression) -> Expression {
+    let value = Box::new(folder.fold_expression(*exp.value));
+    Expression::Prefix(PrefixExpression { value, ..exp })
+}
+
+pub fn fold_infix<F: Fold + ?Sized>(folder: &mut F, exp: InfixExpression) -> Expression {
+    let left = Box::new(folder.fold_expression(*exp.left));
+    let right = Box::new(folder.fold_expression(*exp.right));
+    Expression::Infix(InfixExpression { left, right, ..exp })
+}
+
+pub fn fold_if<F: Fold + ?Sized>(folder: &mut F, exp: IfExpression) -> Expression {
+    let predicate = Box::new(folder.fold_expression(*exp.predicate));
+    let success = folder.fold_block(exp.success);
+    let fail = exp.fail.map(|fail| folder.fold_block(fail));
+    Expression::If(IfExpression { predicate, success, fail, ..exp })
+}
+
+pub fn fold_function<F: Fold + ?Sized>(folder: &mut F, exp: FunctionLiteral) -> Expression {
+    let parameters = exp.parameters.into_iter().map(|p| folder.fold_parameter(p)).collect();
+    let body = Arc::new(folder.fold_block(Arc::unwrap_or_clone(exp.body)));
+    Expression::Function(FunctionLiteral { parameters, body, ..exp })
+}
+
+pub fn fold_call<F: Fold + ?Sized>(folder: &mut F, exp: CallExpression) -> Expression {
+    let function = Box::new(folder.fold_expression(*exp.function));
+    let arguments = exp.arguments.into_iter().map(|a| folder.fold_expression(a)).collect();
+    Expression::Call(CallExpression { function, arguments, ..exp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Prefix;
+    use crate::parser::parse;
+    use crate::printer;
+
+    type Error = Box<dyn std::error::Error>;
+
+    // Names collects the identifiers that are used, but not those bound.
+    #[derive(Default)]
+    struct Names(Vec<String>);
+
+    impl Visitor for Names {
+        fn visit_identifier(&mut self, identifier: &Identifier) {
+            self.0.push(identifier.name.clone());
+        }
+    }
+
+    #[test]
+    fn visitor() -> Result<(), Error> {
+        let program = parse("let f = fn(a, b) { if (a < b) { g(a) } else { -b } }; puts(f(x, \"y\"))")?;
+        let mut names = Names::default();
+        names.visit_program(&program);
+        assert_eq!(names.0, vec!["a", "b", "g", "a", "b", "puts", "f", "x"]);
+        Ok(())
+    }
+
+    // Rename prefixes every identifier and parameter.
+    struct Rename;
+
+    impl VisitMut for Rename {
+        fn visit_identifier_mut(&mut self, identifier: &mut Identifier) {
+            identifier.name.insert(0, '_');
+        }
+
+        fn visit_parameter_mut(&mut self, parameter: &mut Identifier) {
+            self.visit_identifier_mut(parameter);
+        }
+    }
+
+    #[test]
+    fn visit_mut() -> Result<(), Error> {
+        let mut program = parse("let f = fn(a) { a + b }; f(1)")?;
+        Rename.visit_program_mut(&mut program);
+        assert_eq!(printer::print(&program), "let f = fn(_a) {\n    _a + _b;\n};\n_f(1);\n");
+        Ok(())
+    }
+
+    // Negate replaces `-n` on an integer literal with the negative literal,
+    // leaving other prefix expressions alone.
+    struct Negate;
+
+    impl Fold for Negate {
+        fn fold_prefix(&mut self, exp: PrefixExpression) -> Expression {
+            match (exp.operator, self.fold_expression(*exp.value)) {
+                (Prefix::Negative, Expression::Int(n)) => IntegerLiteral::new(-n.value).into(),
+                (operator, value) => PrefixExpression { operator, value: Box::new(value), position: exp.position }.into(),
+            }
+        }
+    }
+
+    #[test]
+    fn fold() -> Result<(), Error> {
+        let program = strip(Negate.fold_program(parse("-(-2); !-1; -x")?));
+        let want: Vec<Statement> = vec![
+            IntegerLiteral::new(2).into(),
+            PrefixExpression::new(Prefix::Not, IntegerLiteral::new(-1)).into(),
+            PrefixExpression::new(Prefix::Negative, Identifier::new("x")).into(),
+        ];
+        assert_eq!(program.statements, want);
+        Ok(())
+    }
+}